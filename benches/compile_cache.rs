@@ -0,0 +1,23 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use wagmi::{Module, ModuleCache};
+
+fn bench_compile_cache(c: &mut Criterion) {
+    let bytes = include_bytes!("coremark-minimal.wasm").to_vec();
+
+    let mut group = c.benchmark_group("compile_coremark_minimal");
+    group.bench_function("uncached", |b| {
+        b.iter(|| black_box(Module::compile(bytes.clone()).expect("compile coremark")))
+    });
+    let mut cache = ModuleCache::new(1);
+    group.bench_function("cached", |b| {
+        b.iter(|| {
+            black_box(Module::compile_cached(bytes.clone(), &mut cache).expect("compile coremark"))
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_compile_cache);
+criterion_main!(benches);
@@ -4,7 +4,7 @@ use std::rc::Rc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use criterion::{criterion_group, criterion_main, Criterion, Throughput};
-use wagmi::{ExportValue, Imports, Instance, Module, RuntimeFunction, ValType, WasmValue};
+use wagmi::{ExportValue, Imports, Instance, Module, RuntimeFunction, Store, ValType, WasmValue};
 
 fn clock_ms_i64() -> i64 {
     SystemTime::now().duration_since(UNIX_EPOCH).expect("Clock may have gone backwards").as_millis()
@@ -25,7 +25,8 @@ fn setup_instance() -> (Instance, wagmi::RuntimeFunction) {
     env_mod.insert("clock_ms".to_string(), ExportValue::Function(clock_fn));
     imports.insert("env".to_string(), env_mod);
 
-    let instance = Instance::instantiate(module, &imports).expect("instantiate coremark");
+    let store = Store::new();
+    let instance = store.instantiate(module, &imports).expect("instantiate coremark");
     let run_fn = match instance.exports.get("run") {
         Some(ExportValue::Function(f)) => f.clone(),
         _ => panic!("Export 'run' not found or not a function"),
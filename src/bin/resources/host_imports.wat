@@ -5,6 +5,10 @@
   (import "host" "mul" (func $host_mul (param i32 i32) (result i32)))
   (import "host" "counter_inc" (func $counter_inc (result i32)))
   (import "host" "counter_get" (func $counter_get (result i32)))
+  (import "host" "print_str" (func $print_str (param i32)))
+
+  (memory 1)
+  (data (i32.const 16) "Hello from linear memory\00")
   
   (func $main (export "main") (result i32)
     i32.const 42
@@ -66,4 +70,9 @@
     local.tee 0
     call $print
     local.get 0)
-)
\ No newline at end of file
+
+  (func $greet (export "greet") (result i32)
+    i32.const 16
+    call $print_str
+    i32.const 0)
+)
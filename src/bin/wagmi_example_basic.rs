@@ -1,5 +1,5 @@
 use std::rc::Rc;
-use wagmi::{Imports, Instance, Module, WasmValue};
+use wagmi::{Imports, Module, Store, WasmValue};
 
 mod utils;
 use utils::load_resource_module;
@@ -8,43 +8,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let arithmetic_bytes = load_resource_module("arithmetic")?;
     let arithmetic_module = Module::compile(arithmetic_bytes)?;
     let arithmetic_module = Rc::new(arithmetic_module);
+    let store = Store::new();
     let imports = Imports::new();
-    let arithmetic_instance = Instance::instantiate(arithmetic_module, &imports)?;
-
-    if let Some(wagmi::ExportValue::Function(add)) = arithmetic_instance.exports.get("add") {
-        let result =
-            arithmetic_instance.invoke(add, &[WasmValue::from_i32(10), WasmValue::from_i32(32)])?;
-        println!("add(10, 32) = {}", result[0].as_i32());
-    }
-
-    if let Some(wagmi::ExportValue::Function(sub)) = arithmetic_instance.exports.get("subtract") {
-        let result = arithmetic_instance
-            .invoke(sub, &[WasmValue::from_i32(100), WasmValue::from_i32(58)])?;
-        println!("subtract(100, 58) = {}", result[0].as_i32());
-    }
-
-    if let Some(wagmi::ExportValue::Function(mul)) = arithmetic_instance.exports.get("multiply") {
-        let result =
-            arithmetic_instance.invoke(mul, &[WasmValue::from_i32(6), WasmValue::from_i32(7)])?;
-        println!("multiply(6, 7) = {}", result[0].as_i32());
-    }
-
-    if let Some(wagmi::ExportValue::Function(div)) = arithmetic_instance.exports.get("divide") {
-        let result =
-            arithmetic_instance.invoke(div, &[WasmValue::from_i32(84), WasmValue::from_i32(2)])?;
-        println!("divide(84, 2) = {}", result[0].as_i32());
-    }
-
-    if let Some(wagmi::ExportValue::Function(modulo)) = arithmetic_instance.exports.get("modulo") {
-        let result = arithmetic_instance
-            .invoke(modulo, &[WasmValue::from_i32(10), WasmValue::from_i32(3)])?;
-        println!("modulo(10, 3) = {}", result[0].as_i32());
-    }
+    let arithmetic_instance = store.instantiate(arithmetic_module, &imports)?;
+
+    let add = arithmetic_instance.get_typed_func::<(i32, i32), i32>("add")?;
+    println!("add(10, 32) = {}", add.call((10, 32))?);
+    let sub = arithmetic_instance.get_typed_func::<(i32, i32), i32>("subtract")?;
+    println!("subtract(100, 58) = {}", sub.call((100, 58))?);
+    let mul = arithmetic_instance.get_typed_func::<(i32, i32), i32>("multiply")?;
+    println!("multiply(6, 7) = {}", mul.call((6, 7))?);
+    let div = arithmetic_instance.get_typed_func::<(i32, i32), i32>("divide")?;
+    println!("divide(84, 2) = {}", div.call((84, 2))?);
+    let modulo = arithmetic_instance.get_typed_func::<(i32, i32), i32>("modulo")?;
+    println!("modulo(10, 3) = {}", modulo.call((10, 3))?);
 
     let factorial_bytes = load_resource_module("factorial")?;
     let factorial_module = Module::compile(factorial_bytes)?;
     let factorial_module = Rc::new(factorial_module);
-    let factorial_instance = Instance::instantiate(factorial_module, &imports)?;
+    let factorial_instance = store.instantiate(factorial_module, &imports)?;
 
     if let Some(wagmi::ExportValue::Function(factorial)) =
         factorial_instance.exports.get("factorial")
@@ -58,7 +40,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let control_bytes = load_resource_module("control_flow")?;
     let control_module = Module::compile(control_bytes)?;
     let control_module = Rc::new(control_module);
-    let control_instance = Instance::instantiate(control_module, &imports)?;
+    let control_instance = store.instantiate(control_module, &imports)?;
 
     if let Some(wagmi::ExportValue::Function(fib)) = control_instance.exports.get("fibonacci") {
         for n in [0, 1, 2, 5, 10] {
@@ -94,7 +76,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let memory_bytes = load_resource_module("memory_ops")?;
     let memory_module = Module::compile(memory_bytes)?;
     let memory_module = Rc::new(memory_module);
-    let memory_instance = Instance::instantiate(memory_module, &imports)?;
+    let memory_instance = store.instantiate(memory_module, &imports)?;
 
     if let (Some(wagmi::ExportValue::Function(store)), Some(wagmi::ExportValue::Function(load))) =
         (memory_instance.exports.get("store_i32"), memory_instance.exports.get("load_i32"))
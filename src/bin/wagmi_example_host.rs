@@ -1,7 +1,8 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
-use wagmi::{ExportValue, Imports, Instance, Module, RuntimeFunction, ValType, WasmValue};
+use wagmi::wasm_memory::WasmMemory;
+use wagmi::{Caller, ExportValue, Imports, Module, RuntimeFunction, Store, ValType, WasmValue};
 
 mod utils;
 use utils::load_resource_module;
@@ -89,6 +90,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Some(WasmValue::from_i32(value))
     });
 
+    // Reads a NUL-terminated string out of the calling instance's memory
+    let state_clone = host_state.clone();
+    let print_str_fn =
+        RuntimeFunction::new_host_with_caller(vec![ValType::I32], None, move |caller, args| {
+            let ptr = args[0].as_u32();
+            let text = read_c_string(caller, ptr)?;
+            println!("  [Host:print_str] {}", text);
+            state_clone.call_sequence.borrow_mut().push(format!("print_str({:?})", text));
+            *state_clone.call_count.borrow_mut() += 1;
+            Ok::<_, wagmi::Error>(())
+        });
+
     let mut imports = Imports::new();
     let mut host_module = HashMap::new();
     host_module.insert("print".to_string(), ExportValue::Function(print_fn));
@@ -97,12 +110,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     host_module.insert("mul".to_string(), ExportValue::Function(mul_fn));
     host_module.insert("counter_inc".to_string(), ExportValue::Function(counter_inc_fn));
     host_module.insert("counter_get".to_string(), ExportValue::Function(counter_get_fn));
+    host_module.insert("print_str".to_string(), ExportValue::Function(print_str_fn));
     imports.insert("host".to_string(), host_module);
 
     let wasm_bytes = load_resource_module("host_imports")?;
     let module = Module::compile(wasm_bytes)?;
     let module = Rc::new(module);
-    let instance = Instance::instantiate(module, &imports)?;
+    let store = Store::new();
+    let instance = store.instantiate(module, &imports)?;
 
     if let Some(ExportValue::Function(main_func)) = instance.exports.get("main") {
         println!("Calling main():");
@@ -128,6 +143,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("→ returned: {}\n", results[0].as_i32());
     }
 
+    if let Some(ExportValue::Function(func)) = instance.exports.get("greet") {
+        println!("Calling greet():");
+        let results = instance.invoke(func, &[])?;
+        println!("→ returned: {}\n", results[0].as_i32());
+    }
+
     println!("=== Summary ===");
     println!("Total host calls: {}", *host_state.call_count.borrow());
     println!("Final counter: {}", *host_state.counter.borrow());
@@ -141,3 +162,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+fn read_c_string(caller: Caller, ptr: u32) -> Result<String, wagmi::Error> {
+    let memory = caller.memory().ok_or(wagmi::Error::trap("no memory to read from"))?.borrow();
+    let size = memory.size() as u64 * WasmMemory::PAGE_SIZE as u64;
+    let len = size.saturating_sub(ptr as u64).min(u32::MAX as u64) as u32;
+    let tail = memory.read_bytes(ptr, len).map_err(wagmi::Error::trap)?;
+    let end = tail.iter().position(|&b| b == 0).ok_or(wagmi::Error::trap("unterminated string"))?;
+    Ok(String::from_utf8_lossy(&tail[..end]).into_owned())
+}
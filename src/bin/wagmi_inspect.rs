@@ -2,7 +2,7 @@ use clap::Parser;
 use std::fs;
 use std::path::PathBuf;
 use std::rc::Rc;
-use wagmi::{ExportValue, Imports, Instance, Module, ValType};
+use wagmi::{ExportValue, Imports, Module, Store, ValType};
 
 #[derive(Parser, Debug)]
 #[command(name = "wagmi-inspect")]
@@ -49,6 +49,8 @@ fn format_type(val_type: &ValType) -> &'static str {
         ValType::I64 => "i64",
         ValType::F32 => "f32",
         ValType::F64 => "f64",
+        ValType::FuncRef => "funcref",
+        ValType::ExternRef => "externref",
         ValType::Any => "any",
     }
 }
@@ -62,6 +64,31 @@ fn format_signature(params: &[ValType], result: Option<ValType>) -> String {
     }
 }
 
+fn format_export(name: &str, export: &ExportValue) -> String {
+    match export {
+        ExportValue::Function(func) => {
+            format!(
+                "  {} : function {}",
+                name,
+                format_signature(func.param_types(), func.result_type())
+            )
+        }
+        ExportValue::Table(table) => {
+            let t = table.borrow();
+            format!("  {} : table [size: {}, max: {}]", name, t.size(), t.max())
+        }
+        ExportValue::Memory(mem) => {
+            let m = mem.borrow();
+            format!("  {} : memory [pages: {}, max: {}]", name, m.size(), m.max())
+        }
+        ExportValue::Global(global) => {
+            let g = global.as_ref();
+            let mutability = if g.mutable { "mut " } else { "" };
+            format!("  {} : global {}{}", name, mutability, format_type(&g.ty))
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
@@ -75,12 +102,31 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let module =
         Module::compile(bytes).map_err(|e| format!("Failed to compile module: {:?}", e))?;
 
-    let module = Rc::new(module);
+    // Only the live export listing needs an instance, and with it the bytecode
+    let (module, listing) = if args.imports_only {
+        (module, None)
+    } else {
+        let store = Store::new();
+        let module = Rc::new(module);
+        let listing = store.instantiate(module.clone(), &Imports::new()).map(|instance| {
+            let mut exports: Vec<_> = instance.exports.iter().collect();
+            exports.sort_by_key(|(name, _)| name.as_str());
+            exports
+                .into_iter()
+                .map(|(name, export)| format_export(name, export))
+                .collect::<Vec<_>>()
+        });
+        // Dropping the store releases any half-instantiated instance still holding the module
+        drop(store);
+        let module = Rc::try_unwrap(module).map_err(|_| "Module is still in use")?;
+        (module, Some(listing))
+    };
+    let metadata = module.into_metadata();
 
     if !args.exports_only {
-        if !module.imports.is_empty() {
+        if !metadata.imports.is_empty() {
             println!("Imports:");
-            for (module_name, imports) in &module.imports {
+            for (module_name, imports) in &metadata.imports {
                 for (field_name, import_type) in imports {
                     let type_str = match import_type {
                         wagmi::module::ExternType::Func => "function",
@@ -98,14 +144,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    if args.imports_only {
-        return Ok(());
-    }
-
-    let imports = Imports::new();
-    let instance = match Instance::instantiate(module.clone(), &imports) {
-        Ok(inst) => inst,
-        Err(e) => {
+    let listing = match listing {
+        None => return Ok(()),
+        Some(Ok(listing)) => listing,
+        Some(Err(e)) => {
             if !args.exports_only {
                 eprintln!(
                     "Note: Module instantiation failed (likely due to missing imports): {:?}",
@@ -114,14 +156,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 eprintln!("Showing available compile-time information only.\n");
             }
 
-            if !module.exports.is_empty() {
+            if !metadata.exports.is_empty() {
                 println!("Exports (from module metadata):");
-                for (name, export) in &module.exports {
+                for (name, export) in &metadata.exports {
                     let type_str = match export.extern_type {
                         wagmi::module::ExternType::Func => {
                             let func_idx = export.idx as usize;
-                            if func_idx < module.functions.len() {
-                                let func = &module.functions[func_idx];
+                            if func_idx < metadata.functions.len() {
+                                let func = &metadata.functions[func_idx];
                                 format!(
                                     "function {}",
                                     format_signature(&func.ty.params, func.ty.result)
@@ -143,48 +185,23 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    if !instance.exports.is_empty() {
+    if !listing.is_empty() {
         println!("Exports:");
-        let mut exports: Vec<_> = instance.exports.iter().collect();
-        exports.sort_by_key(|(name, _)| name.as_str());
-
-        for (name, export) in exports {
-            match export {
-                ExportValue::Function(func) => {
-                    println!(
-                        "  {} : function (params: {}, result: {})",
-                        name,
-                        func.signature().n_params(),
-                        if func.signature().has_result() { "yes" } else { "no" }
-                    );
-                }
-                ExportValue::Table(table) => {
-                    let t = table.borrow();
-                    println!("  {} : table [size: {}, max: {}]", name, t.size(), t.max());
-                }
-                ExportValue::Memory(mem) => {
-                    let m = mem.borrow();
-                    println!("  {} : memory [pages: {}, max: {}]", name, m.size(), m.max());
-                }
-                ExportValue::Global(global) => {
-                    let g = global.as_ref();
-                    let mutability = if g.mutable { "mut " } else { "" };
-                    println!("  {} : global {}{}", name, mutability, format_type(&g.ty));
-                }
-            }
+        for line in listing {
+            println!("{}", line);
         }
         println!();
-    } else if !args.imports_only {
+    } else {
         println!("Exports: none");
         println!();
     }
 
     if args.verbose && !args.exports_only && !args.imports_only {
         println!("Module details:");
-        println!("  Functions: {} total", module.functions.len());
+        println!("  Functions: {} total", metadata.functions.len());
 
-        let imported_funcs = module.functions.iter().filter(|f| f.import.is_some()).count();
-        let defined_funcs = module.functions.len() - imported_funcs;
+        let imported_funcs = metadata.functions.iter().filter(|f| f.import.is_some()).count();
+        let defined_funcs = metadata.functions.len() - imported_funcs;
         if imported_funcs > 0 {
             println!("    - {} imported", imported_funcs);
         }
@@ -192,29 +209,29 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("    - {} defined", defined_funcs);
         }
 
-        if let Some(mem) = &module.memory {
+        if let Some(mem) = &metadata.memory {
             println!("  Memory: {} pages (min), {} pages (max)", mem.min, mem.max);
         }
 
-        if let Some(table) = &module.table {
+        if let Some(table) = &metadata.table {
             println!("  Table: {} elements (min), {} elements (max)", table.min, table.max);
         }
 
-        if !module.globals.is_empty() {
-            println!("  Globals: {}", module.globals.len());
+        if !metadata.globals.is_empty() {
+            println!("  Globals: {}", metadata.globals.len());
         }
 
-        if let Some(start_idx) = module.start {
+        if let Some(start_idx) = metadata.start {
             println!("  Start function: index {}", start_idx);
         }
 
-        if module.n_data > 0 {
-            println!("  Data segments: {}", module.n_data);
+        if metadata.n_data > 0 {
+            println!("  Data segments: {}", metadata.n_data);
         }
 
-        println!("  Type signatures: {}", module.types.len());
-        if args.verbose && !module.types.is_empty() {
-            for (i, sig) in module.types.iter().enumerate() {
+        println!("  Type signatures: {}", metadata.types.len());
+        if args.verbose && !metadata.types.is_empty() {
+            for (i, sig) in metadata.types.iter().enumerate() {
                 println!("    [{}] {}", i, format_signature(&sig.params, sig.result));
             }
         }
@@ -1,7 +1,7 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use std::fs;
 use std::path::PathBuf;
-use wagmi::{ExportValue, Imports, Instance, Module, WasmValue};
+use wagmi::{ExportValue, Imports, Instance, Module, Store, ValType, WasmValue};
 
 mod utils;
 use utils::compile_wat;
@@ -28,8 +28,14 @@ Examples:
   # Invoke a function with floating point arguments
   wagmi-run module.wasm --invoke calculate --args 3.14:f32 2.718:f64
   
+  # Print integer results in hexadecimal (or bin for binary)
+  wagmi-run module.wasm --invoke mask --radix hex
+
   # Enable debug output
   wagmi-run module.wasm --invoke factorial --args 5:i32 --debug
+
+  # Smoke test every export with zero-valued arguments
+  wagmi-run module.wasm --smoke
 ")]
 struct Args {
     /// Path to the WebAssembly module file
@@ -50,6 +56,21 @@ struct Args {
     /// List all exports instead of running
     #[arg(short, long)]
     list_exports: bool,
+
+    /// Invoke every exported function with zero arguments and report which trap
+    #[arg(long)]
+    smoke: bool,
+
+    /// Base integer results are printed in, floats are always decimal
+    #[arg(long, value_enum, default_value_t = Radix::Dec)]
+    radix: Radix,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Radix {
+    Dec,
+    Hex,
+    Bin,
 }
 
 fn parse_value(arg: &str) -> Result<WasmValue, String> {
@@ -93,15 +114,62 @@ fn parse_value(arg: &str) -> Result<WasmValue, String> {
     }
 }
 
-fn format_value(val: &WasmValue, _hint: Option<&str>) -> String {
-    let i32_val = val.as_i32();
-    let i64_val = val.as_i64();
+fn format_value(val: WasmValue, ty: ValType, radix: Radix) -> String {
+    // Hex and binary show the bit pattern, so negative integers print unsigned
+    match (ty, radix) {
+        (ValType::I32, Radix::Hex) => format!("{:#x} (i32)", val.as_u32()),
+        (ValType::I32, Radix::Bin) => format!("{:#b} (i32)", val.as_u32()),
+        (ValType::I64, Radix::Hex) => format!("{:#x} (i64)", val.as_u64()),
+        (ValType::I64, Radix::Bin) => format!("{:#b} (i64)", val.as_u64()),
+        _ => format_decimal(val, ty),
+    }
+}
 
-    if i64_val == i32_val as i64 {
-        format!("{} (i32)", i32_val)
-    } else {
-        format!("{} (i64)", i64_val)
+fn format_decimal(val: WasmValue, ty: ValType) -> String {
+    match ty {
+        ValType::I32 => format!("{} (i32)", val.as_i32()),
+        ValType::I64 => format!("{} (i64)", val.as_i64()),
+        ValType::F32 => format!("{} (f32)", val.as_f32()),
+        ValType::F64 => format!("{} (f64)", val.as_f64()),
+        ValType::FuncRef => format!("{:#x} (funcref)", val.as_u64()),
+        ValType::ExternRef => format!("{:#x} (externref)", val.as_u64()),
+        ValType::Any => format!("{:#x}", val.as_u64()),
+    }
+}
+
+fn smoke(instance: &Instance, radix: Radix) -> Result<(), Box<dyn std::error::Error>> {
+    let mut names: Vec<&String> = instance
+        .exports
+        .iter()
+        .filter(|(_, export)| matches!(export, ExportValue::Function(_)))
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+
+    // Zero is a valid value of every value type, null for references
+    let mut trapped = 0;
+    for name in &names {
+        let Some(ExportValue::Function(func)) = instance.exports.get(*name) else { continue };
+        let args = vec![WasmValue::default(); func.param_count()];
+        match instance.invoke(func, &args) {
+            Ok(results) => {
+                let ty = func.result_type().unwrap_or(ValType::Any);
+                let shown: Vec<String> =
+                    results.iter().map(|r| format_value(*r, ty, radix)).collect();
+                println!("  ok    {} [{}]", name, shown.join(", "));
+            }
+            Err(e) => {
+                trapped += 1;
+                println!("  trap  {} ({})", name, e);
+            }
+        }
+    }
+
+    println!("{} passed, {} trapped", names.len() - trapped, trapped);
+    if trapped > 0 {
+        return Err(format!("{} of {} exports trapped", trapped, names.len()).into());
     }
+    Ok(())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -130,25 +198,24 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let module = std::rc::Rc::new(module);
 
+    let store = Store::new();
     let imports = Imports::new();
-    let instance = Instance::instantiate(module.clone(), &imports)
+    let instance = store
+        .instantiate(module.clone(), &imports)
         .map_err(|e| format!("Failed to instantiate module: {:?}", e))?;
 
     if args.list_exports {
         println!("Exported functions:");
         for (name, export) in &instance.exports {
             if let ExportValue::Function(func) = export {
-                print!("  {} (", name);
-                let n_params = func.signature().n_params();
-                for i in 0..n_params {
-                    if i > 0 {
-                        print!(", ");
-                    }
-                    print!("param{}", i);
-                }
-                print!(")");
-                if func.signature().has_result() {
-                    print!(" -> result");
+                let params: Vec<String> = func
+                    .param_types()
+                    .iter()
+                    .map(|ty| format!("{:?}", ty).to_lowercase())
+                    .collect();
+                print!("  {} ({})", name, params.join(", "));
+                if let Some(result) = func.result_type() {
+                    print!(" -> {}", format!("{:?}", result).to_lowercase());
                 }
                 println!();
             }
@@ -156,6 +223,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         return Ok(());
     }
 
+    if args.smoke {
+        return smoke(&instance, args.radix);
+    }
+
     let func_name = args.invoke.as_deref().unwrap_or("_start");
 
     if args.debug {
@@ -172,6 +243,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         _ => return Err(format!("Export '{}' is not a function", func_name).into()),
     };
 
+    let result_type = func.result_type().unwrap_or(ValType::Any);
+
     let mut wasm_args = Vec::new();
     for arg_str in &args.args {
         wasm_args.push(parse_value(arg_str)?);
@@ -201,7 +274,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     } else {
         println!("Result:");
         for (i, result) in results.iter().enumerate() {
-            println!("  [{}] {}", i, format_value(result, None));
+            println!("  [{}] {}", i, format_value(*result, result_type, args.radix));
         }
     }
 
@@ -54,12 +54,6 @@ fn v_file(path: &PathBuf, verbose: bool, quiet: bool) -> Result<(), Box<dyn std:
         Ok(mut module) => {
             if verbose {
                 println!("  Module compiled successfully");
-                println!("  Functions: {}", module.functions.len());
-                println!("  Exports: {}", module.exports.len());
-                if !module.imports.is_empty() {
-                    let import_count: usize = module.imports.values().map(|m| m.len()).sum();
-                    println!("  Imports: {}", import_count);
-                }
             }
 
             let func_count = module.functions.len();
@@ -82,7 +76,19 @@ fn v_file(path: &PathBuf, verbose: bool, quiet: bool) -> Result<(), Box<dyn std:
                 }
 
                 if let Err(e) = validator.v_function(idx) {
-                    return Err(format!("Validation failed for function {}: {:?}", idx, e).into());
+                    let e = if verbose { e.explain() } else { format!("{:?}", e) };
+                    return Err(format!("Validation failed for function {}: {}", idx, e).into());
+                }
+            }
+
+            // Only declarations are needed from here on, release the bytecode
+            let metadata = module.into_metadata();
+            if verbose {
+                println!("  Functions: {}", metadata.functions.len());
+                println!("  Exports: {}", metadata.exports.len());
+                if !metadata.imports.is_empty() {
+                    let import_count: usize = metadata.imports.values().map(|m| m.len()).sum();
+                    println!("  Imports: {}", import_count);
                 }
             }
 
@@ -91,6 +97,7 @@ fn v_file(path: &PathBuf, verbose: bool, quiet: bool) -> Result<(), Box<dyn std:
             }
             Ok(())
         }
+        Err(e) if verbose => Err(format!("INVALID: {} - {}", path.display(), e.explain()).into()),
         Err(e) => Err(format!("INVALID: {} - {:?}", path.display(), e).into()),
     }
 }
@@ -0,0 +1,91 @@
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+
+use crate::error::Error;
+use crate::module::{CompileOptions, Module};
+
+/// In-memory LRU of compiled modules keyed by a hash of their bytes, for embedders
+/// that compile the same wasm over and over.
+///
+/// A hit hands back the already validated module instead of parsing it again. Entries
+/// are matched on the full bytes and the compile options, the hash only narrows the
+/// search. Failed compiles are not cached.
+pub struct ModuleCache {
+    capacity: usize,
+    /// Least recently used first
+    entries: Vec<CacheEntry>,
+    hits: u64,
+    misses: u64,
+}
+
+struct CacheEntry {
+    hash: u64,
+    options: CompileOptions,
+    module: Rc<Module>,
+}
+
+impl ModuleCache {
+    /// A cache holding at most `capacity` modules, zero disables caching.
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, entries: Vec::new(), hits: 0, misses: 0 }
+    }
+
+    /// The cached module compiled from `bytes` with `options`, compiling and caching it
+    /// on a miss.
+    pub fn get_or_compile(
+        &mut self,
+        bytes: Vec<u8>,
+        options: CompileOptions,
+    ) -> Result<Rc<Module>, Error> {
+        let hash = content_hash(&bytes);
+        let found = self.entries.iter().position(|e| {
+            e.hash == hash && e.options == options && e.module.bytes.as_slice() == bytes.as_slice()
+        });
+        if let Some(pos) = found {
+            self.hits += 1;
+            let entry = self.entries.remove(pos);
+            let module = entry.module.clone();
+            self.entries.push(entry);
+            return Ok(module);
+        }
+
+        self.misses += 1;
+        let module = Rc::new(Module::compile_with_options(bytes, options)?);
+        if self.capacity > 0 {
+            if self.entries.len() >= self.capacity {
+                self.entries.remove(0);
+            }
+            self.entries.push(CacheEntry { hash, options, module: module.clone() });
+        }
+        Ok(module)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Lookups answered from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Lookups that had to compile.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// 64-bit FNV-1a, stable across runs and available without std.
+fn content_hash(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, &b| (hash ^ b as u64).wrapping_mul(PRIME))
+}
@@ -1,38 +1,173 @@
-use std::fmt::{Display, Formatter};
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt::{Display, Formatter};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     Malformed(&'static str),
     Validation(&'static str),
     Trap(&'static str),
+    /// Trap raised by a host function with a message built at runtime, e.g. a guest's
+    /// decoded abort message.
+    HostTrap(String),
     Link(&'static str),
     Uninstantiable(&'static str),
 }
 
 impl Display for Error {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
             Error::Malformed(s)
             | Error::Validation(s)
             | Error::Trap(s)
             | Error::Link(s)
             | Error::Uninstantiable(s) => f.write_str(s),
+            Error::HostTrap(s) => f.write_str(s),
         }
     }
 }
 
-impl std::error::Error for Error {}
+impl core::error::Error for Error {}
 
 #[rustfmt::skip]
 impl Error {
     #[cold] #[inline(never)] pub fn malformed(msg: &'static str) -> Self { Error::Malformed(msg) }
     #[cold] #[inline(never)] pub fn validation(msg: &'static str) -> Self { Error::Validation(msg) }
     #[cold] #[inline(never)] pub fn trap(msg: &'static str) -> Self { Error::Trap(msg) }
+    #[cold] #[inline(never)] pub fn host_trap(msg: impl Into<String>) -> Self { Error::HostTrap(msg.into()) }
     #[cold] #[inline(never)] pub fn link(msg: &'static str) -> Self { Error::Link(msg) }
     #[cold] #[inline(never)] pub fn uninstantiable(msg: &'static str) -> Self { Error::Uninstantiable(msg) }
+
+    /// The module uses a proposal wagmi doesn't implement (SIMD, threads) rather than being malformed.
+    pub fn is_unsupported_feature(&self) -> bool { *self == Error::Malformed(UNSUPPORTED_FEATURE) }
+}
+
+/// Stable identifier for the rule behind an [`Error::Validation`], independent of message wording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ValidationRule {
+    AlignmentTooLarge,
+    ConstExpRequired,
+    DupExportName,
+    DupType,
+    ElseMustCloseIf,
+    FloatForbidden,
+    GlobalIsImmutable,
+    InvalidDataSegFlag,
+    InvalidElemType,
+    InvalidExportDesc,
+    InvalidLocalType,
+    InvalidResultArity,
+    InvalidResultType,
+    MemorySizeLimit,
+    MinGreaterThanMax,
+    MultipleMemories,
+    MultipleTables,
+    StartFunc,
+    StraightLineExprRequired,
+    TypeMismatch,
+    UnknownDataSegment,
+    UnknownElemSegment,
+    UnknownFunc,
+    UnknownGlobal,
+    UnknownLabel,
+    UnknownLocal,
+    UnknownMemory,
+    UnknownTable,
+    UnknownType,
+}
+
+impl Error {
+    /// The rule a validation error was raised for, `None` for every other kind of error.
+    pub fn validation_rule(&self) -> Option<ValidationRule> {
+        let Error::Validation(msg) = *self else {
+            return None;
+        };
+        Some(match msg {
+            ALIGNMENT_TOO_LARGE => ValidationRule::AlignmentTooLarge,
+            CONST_EXP_REQUIRED => ValidationRule::ConstExpRequired,
+            DUP_EXPORT_NAME => ValidationRule::DupExportName,
+            DUP_TYPE => ValidationRule::DupType,
+            ELSE_MUST_CLOSE_IF => ValidationRule::ElseMustCloseIf,
+            FLOAT_FORBIDDEN => ValidationRule::FloatForbidden,
+            GLOBAL_IS_IMMUTABLE => ValidationRule::GlobalIsImmutable,
+            INVALID_DATA_SEG_FLAG => ValidationRule::InvalidDataSegFlag,
+            INVALID_ELEM_TYPE => ValidationRule::InvalidElemType,
+            INVALID_EXPORT_DESC => ValidationRule::InvalidExportDesc,
+            INVALID_LOCAL_TYPE => ValidationRule::InvalidLocalType,
+            INVALID_RESULT_ARITY => ValidationRule::InvalidResultArity,
+            INVALID_RESULT_TYPE => ValidationRule::InvalidResultType,
+            MEMORY_SIZE_LIMIT => ValidationRule::MemorySizeLimit,
+            MIN_GREATER_THAN_MAX => ValidationRule::MinGreaterThanMax,
+            MULTIPLE_MEMORIES => ValidationRule::MultipleMemories,
+            MULTIPLE_TABLES => ValidationRule::MultipleTables,
+            START_FUNC => ValidationRule::StartFunc,
+            STRAIGHT_LINE_EXPR_REQUIRED => ValidationRule::StraightLineExprRequired,
+            TYPE_MISMATCH => ValidationRule::TypeMismatch,
+            UNKNOWN_DATA_SEGMENT => ValidationRule::UnknownDataSegment,
+            UNKNOWN_ELEM_SEGMENT => ValidationRule::UnknownElemSegment,
+            UNKNOWN_FUNC => ValidationRule::UnknownFunc,
+            UNKNOWN_GLOBAL => ValidationRule::UnknownGlobal,
+            UNKNOWN_LABEL => ValidationRule::UnknownLabel,
+            UNKNOWN_LOCAL => ValidationRule::UnknownLocal,
+            UNKNOWN_MEMORY => ValidationRule::UnknownMemory,
+            UNKNOWN_TABLE => ValidationRule::UnknownTable,
+            UNKNOWN_TYPE => ValidationRule::UnknownType,
+            _ => return None,
+        })
+    }
+
+    /// A short suggestion for fixing a common error, `None` for the rest.
+    pub fn hint(&self) -> Option<&'static str> {
+        let (Error::Malformed(msg)
+        | Error::Validation(msg)
+        | Error::Trap(msg)
+        | Error::Link(msg)
+        | Error::Uninstantiable(msg)) = *self
+        else {
+            return None;
+        };
+        Some(match msg {
+            ALIGNMENT_TOO_LARGE => {
+                "the align hint must not exceed the access's natural alignment, e.g. 4 bytes for i32.load"
+            }
+            CONST_EXP_REQUIRED => {
+                "initializers and segment offsets may only use constants and global.get of imported globals"
+            }
+            GLOBAL_IS_IMMUTABLE => "declare the global (mut ...) to assign it with global.set",
+            TYPE_MISMATCH => {
+                "an instruction's operands or a block's or function's results don't have the types its signature requires"
+            }
+            UNKNOWN_LABEL => "branch depths count enclosing blocks outwards from 0 for the innermost",
+            UNKNOWN_MEMORY => "declare or import a memory before loading, storing or growing it",
+            UNSUPPORTED_FEATURE => "the module uses SIMD or threads, which wagmi doesn't implement",
+            DIVIDE_BY_ZERO => "check the divisor first, or set DeterminismConfig::relaxed_div_by_zero",
+            INDIRECT_CALL_MISMATCH => {
+                "the table entry's function signature differs from the call_indirect type"
+            }
+            OOB_MEMORY_ACCESS => "address plus offset plus access width exceeds the current memory size",
+            STACK_EXHAUSTED => "recursion went deeper than the interpreter's call depth limit",
+            UNINITIALIZED_ELEM => "the table slot was never filled by an element segment or the host",
+            INCOMPATIBLE_IMPORT => {
+                "the provided value must have the imported kind and signature, and memories and tables fit the imported limits"
+            }
+            UNKNOWN_IMPORT => "provide every import under its module and field name in the Imports passed to instantiate",
+            _ => return None,
+        })
+    }
+
+    /// The message followed by its [`Error::hint`], for developer-facing output.
+    pub fn explain(&self) -> String {
+        match self.hint() {
+            Some(hint) => format!("{} (hint: {})", self, hint),
+            None => self.to_string(),
+        }
+    }
 }
 
 // Malformed errors
+pub const DATA_COUNT_MISMATCH: &str = "data count and data section have inconsistent lengths";
+pub const DATA_COUNT_REQUIRED: &str = "data count section required";
 pub const END_EXPECTED: &str = "END opcode expected";
 pub const FUNC_CODE_INCONSISTENT: &str = "function and code section have inconsistent lengths";
 pub const ILLEGAL_OP: &str = "illegal opcode";
@@ -47,19 +182,29 @@ pub const JUNK_AFTER_LAST: &str = "junk after last section";
 pub const LENGTH_OUT_OF_BOUNDS: &str = "length out of bounds";
 pub const NO_MAGIC_HEADER: &str = "magic header not detected";
 pub const MALFORMED_IMPORT_KIND: &str = "malformed import kind";
+pub const MALFORMED_MEMOP_FLAGS: &str = "malformed memop flags";
 pub const MALFORMED_REF_TYPE: &str = "malformed reference type";
+pub const NESTING_TOO_DEEP: &str = "control nesting too deep";
 pub const SECTION_SIZE_MISMATCH: &str = "section size mismatch";
+pub const TOO_MANY_DATA_SEGMENTS: &str = "too many data segments";
+pub const TOO_MANY_ELEM_SEGMENTS: &str = "too many element segments";
+pub const TOO_MANY_FUNCTIONS: &str = "too many functions";
+pub const TOO_MANY_GLOBALS: &str = "too many globals";
 pub const TOO_MANY_LOCALS: &str = "too many locals";
+pub const TOO_MANY_TYPES: &str = "too many types";
 pub const UNEXPECTED_END: &str = "unexpected end of section or function";
 pub const UNEXPECTED_END_SHORT: &str = "unexpected end";
 pub const UNKNOWN_BINARY_VERSION: &str = "unknown binary version";
 pub const UNKNOWN_INSTRUCTION: &str = "unknown instruction";
+pub const UNSUPPORTED_FEATURE: &str = "unsupported feature";
 pub const ZERO_FLAG_EXPECTED: &str = "zero flag expected";
 // Validation errors
 pub const ALIGNMENT_TOO_LARGE: &str = "alignment must not be larger than natural";
 pub const CONST_EXP_REQUIRED: &str = "constant expression required";
 pub const DUP_EXPORT_NAME: &str = "duplicate export name";
+pub const DUP_TYPE: &str = "duplicate function type";
 pub const ELSE_MUST_CLOSE_IF: &str = "else must close an if";
+pub const FLOAT_FORBIDDEN: &str = "floating point is not allowed";
 pub const GLOBAL_IS_IMMUTABLE: &str = "global is immutable";
 pub const INVALID_DATA_SEG_FLAG: &str = "invalid data segment flag";
 pub const INVALID_ELEM_TYPE: &str = "invalid table element type";
@@ -72,7 +217,10 @@ pub const MIN_GREATER_THAN_MAX: &str = "size minimum must not be greater than ma
 pub const MULTIPLE_MEMORIES: &str = "multiple memories";
 pub const MULTIPLE_TABLES: &str = "multiple tables";
 pub const START_FUNC: &str = "start function";
+pub const STRAIGHT_LINE_EXPR_REQUIRED: &str = "straight-line expression required";
 pub const TYPE_MISMATCH: &str = "type mismatch";
+pub const UNKNOWN_DATA_SEGMENT: &str = "unknown data segment";
+pub const UNKNOWN_ELEM_SEGMENT: &str = "unknown elem segment";
 pub const UNKNOWN_FUNC: &str = "unknown function";
 pub const UNKNOWN_GLOBAL: &str = "unknown global";
 pub const UNKNOWN_LABEL: &str = "unknown label";
@@ -81,14 +229,22 @@ pub const UNKNOWN_MEMORY: &str = "unknown memory";
 pub const UNKNOWN_TABLE: &str = "unknown table";
 pub const UNKNOWN_TYPE: &str = "unknown type";
 // Trap errors
+pub const ARG_TYPE_MISMATCH: &str = "argument type mismatch";
+pub const CANCELLED: &str = "cancelled";
 pub const DIVIDE_BY_ZERO: &str = "integer divide by zero";
 pub const FUNC_NO_IMPL: &str = "function has no implementation";
+pub const GUEST_ABORTED: &str = "guest aborted";
+pub const HOST_RESULT_MISMATCH: &str = "host result mismatch";
+pub const HOST_REPLAY_DIVERGED: &str = "host call diverged from replay log";
 pub const INDIRECT_CALL_MISMATCH: &str = "indirect call type mismatch";
 pub const INTEGER_OVERFLOW: &str = "integer overflow";
 pub const INVALID_CONV_TO_INT: &str = "invalid conversion to integer";
 pub const INVALID_NUM_ARG: &str = "invalid number of arguments";
+pub const MISSING_SIDE_TABLE_ENTRY: &str = "missing side table entry";
 pub const OOB_MEMORY_ACCESS: &str = "out of bounds memory access";
 pub const OOB_TABLE_ACCESS: &str = "out of bounds table access";
+pub const OUT_OF_FUEL: &str = "out of fuel";
+pub const READONLY_MEMORY: &str = "write to readonly memory";
 pub const STACK_EXHAUSTED: &str = "call stack exhausted";
 pub const STACK_UNDERFLOW: &str = "stack underflow";
 pub const UNDEF_ELEM: &str = "undefined element";
@@ -97,5 +253,9 @@ pub const UNREACHABLE: &str = "unreachable";
 // Link errors
 pub const DATA_SEG_DNF: &str = "data segment does not fit";
 pub const ELEM_SEG_DNF: &str = "elements segment does not fit";
+pub const GLOBAL_MEMORY_LIMIT: &str = "global memory limit exceeded";
+pub const INCOMPATIBLE_EXPORT: &str = "incompatible export type";
 pub const INCOMPATIBLE_IMPORT: &str = "incompatible import type";
+pub const PARTIAL_MEMORY_PAGE: &str = "memory size is not a multiple of the page size";
+pub const UNKNOWN_EXPORT: &str = "unknown export";
 pub const UNKNOWN_IMPORT: &str = "unknown import";
@@ -0,0 +1,80 @@
+use core::ops::{BitOr, BitOrAssign};
+
+use crate::opcodes::{
+    ELEM_DROP, I32_EXTEND8_S, I32_TRUNC_SAT_F32_S, I64_EXTEND32_S, I64_TRUNC_SAT_F64_U,
+    MEMORY_INIT, SELECT_T, TABLE_FILL, TABLE_GET, TABLE_GROW, TABLE_SET,
+};
+
+/// Set of post-MVP proposals the validator accepts, see [`crate::Module::compile_with_features`].
+///
+/// Defaults to every proposal the interpreter implements. Instructions of a
+/// disabled proposal are rejected as unknown instructions, its value types as
+/// invalid value types.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Features(u32);
+
+#[rustfmt::skip]
+impl Features {
+    /// The 1.0 spec only
+    pub const MVP:              Features = Features(0);
+    /// Block types that reference a function type, blocks with parameters
+    pub const MULTI_VALUE:      Features = Features(1 << 0);
+    /// `funcref` and `externref` value types, typed `select` and the
+    /// `table.get`/`set`/`size`/`grow`/`fill` instructions
+    pub const REFERENCE_TYPES:  Features = Features(1 << 1);
+    /// `i32.extend8_s` through `i64.extend32_s`
+    pub const SIGN_EXTENSION:   Features = Features(1 << 2);
+    /// `memory.copy`, `memory.fill`, passive data segments with `memory.init` and
+    /// `data.drop`, the data count section, and passive and declarative element
+    /// segments with `table.init` and `elem.drop`
+    pub const BULK_MEMORY:      Features = Features(1 << 3);
+    /// Saturating float to int truncations, `i32.trunc_sat_f32_s` and friends
+    pub const SAT_FLOAT_TO_INT: Features = Features(1 << 4);
+    pub const ALL:              Features = Features(
+        Self::MULTI_VALUE.0 | Self::REFERENCE_TYPES.0 | Self::SIGN_EXTENSION.0 | Self::SAT_FLOAT_TO_INT.0
+            | Self::BULK_MEMORY.0,
+    );
+
+    pub const fn contains(self, other: Features) -> bool { self.0 & other.0 == other.0 }
+    pub const fn without(self, other: Features) -> Features { Features(self.0 & !other.0) }
+}
+
+impl Features {
+    /// Proposals an opcode belongs to, [`Features::MVP`] for 1.0 instructions.
+    pub(crate) const fn for_opcode(opcode: u8) -> Features {
+        match opcode {
+            SELECT_T | TABLE_GET | TABLE_SET => Features::REFERENCE_TYPES,
+            I32_EXTEND8_S..=I64_EXTEND32_S => Features::SIGN_EXTENSION,
+            _ => Features::MVP,
+        }
+    }
+
+    /// Proposals a 0xfc sub-opcode belongs to.
+    pub(crate) const fn for_misc_opcode(sub: u32) -> Features {
+        match sub {
+            I32_TRUNC_SAT_F32_S..=I64_TRUNC_SAT_F64_U => Features::SAT_FLOAT_TO_INT,
+            MEMORY_INIT..=ELEM_DROP => Features::BULK_MEMORY,
+            TABLE_GROW..=TABLE_FILL => Features::REFERENCE_TYPES,
+            _ => Features::MVP,
+        }
+    }
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features::ALL
+    }
+}
+
+impl BitOr for Features {
+    type Output = Features;
+    fn bitor(self, rhs: Features) -> Features {
+        Features(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Features {
+    fn bitor_assign(&mut self, rhs: Features) {
+        self.0 |= rhs.0;
+    }
+}
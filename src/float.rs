@@ -0,0 +1,25 @@
+//! Float rounding and square root for `no_std` builds, where they aren't inherent
+//! methods on f32/f64. With `std` the inherent methods are used instead.
+
+pub(crate) trait FloatMath {
+    fn ceil(self) -> Self;
+    fn floor(self) -> Self;
+    fn trunc(self) -> Self;
+    fn sqrt(self) -> Self;
+}
+
+#[rustfmt::skip]
+impl FloatMath for f32 {
+    fn ceil(self) -> Self { libm::ceilf(self) }
+    fn floor(self) -> Self { libm::floorf(self) }
+    fn trunc(self) -> Self { libm::truncf(self) }
+    fn sqrt(self) -> Self { libm::sqrtf(self) }
+}
+
+#[rustfmt::skip]
+impl FloatMath for f64 {
+    fn ceil(self) -> Self { libm::ceil(self) }
+    fn floor(self) -> Self { libm::floor(self) }
+    fn trunc(self) -> Self { libm::trunc(self) }
+    fn sqrt(self) -> Self { libm::sqrt(self) }
+}
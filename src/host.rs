@@ -0,0 +1,140 @@
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use crate::error::*;
+use crate::instance::{RuntimeFunction, WasmValue};
+use crate::signature::ValType;
+use crate::wasm_memory::WasmMemory;
+
+/// Collects values a guest pushes through a `yield`-style host import.
+///
+/// Every function built by [`CollectingHost::function`] appends its argument to
+/// the same shared buffer, so a wasm loop calling the import repeatedly produces
+/// a stream the embedder can read back after `invoke` returns.
+#[derive(Clone, Default)]
+pub struct CollectingHost {
+    values: Rc<RefCell<Vec<WasmValue>>>,
+}
+
+impl CollectingHost {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a `(param ty)` host function that records each value it receives.
+    pub fn function(&self, ty: ValType) -> RuntimeFunction {
+        let values = self.values.clone();
+        RuntimeFunction::new_host(vec![ty], None, move |args| {
+            values.borrow_mut().push(args[0]);
+            None
+        })
+    }
+
+    /// Shared buffer backing this collector.
+    pub fn buffer(&self) -> Rc<RefCell<Vec<WasmValue>>> {
+        self.values.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.borrow().is_empty()
+    }
+
+    /// Returns a copy of the values collected so far.
+    pub fn values(&self) -> Vec<WasmValue> {
+        self.values.borrow().clone()
+    }
+
+    /// Drains the collected values, leaving the buffer empty for the next run.
+    pub fn take(&self) -> Vec<WasmValue> {
+        core::mem::take(&mut *self.values.borrow_mut())
+    }
+}
+
+/// Turns a guest's `abort`/`panic` import into a trap with a readable reason.
+///
+/// The handler reads the UTF-8 message (and, for [`AbortHost::handler_with_location`],
+/// the file name and line) out of guest memory and traps with an [`Error::HostTrap`]
+/// carrying it, or plain "guest aborted" if it can't be read. The decoded text is also
+/// kept here for the embedder to read back with [`AbortHost::message`].
+#[derive(Clone, Default)]
+pub struct AbortHost {
+    message: Rc<RefCell<Option<String>>>,
+}
+
+impl AbortHost {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds an `(param $ptr i32 $len i32)` host function. `memory` is asked for the
+    /// guest memory at each call, so it can be wired to an export after instantiation.
+    pub fn handler(
+        &self,
+        memory: impl Fn() -> Option<Rc<RefCell<WasmMemory>>> + 'static,
+    ) -> RuntimeFunction {
+        let message = self.message.clone();
+        RuntimeFunction::new_host(vec![ValType::I32; 2], None, move |args| {
+            let text = read_str(&memory, args[0], args[1]);
+            Err::<(), _>(abort_trap(&message, text))
+        })
+    }
+
+    /// Builds an `(param $ptr i32 $len i32 $file_ptr i32 $file_len i32 $line i32)` host
+    /// function, recording the message as `"message at file:line"`.
+    pub fn handler_with_location(
+        &self,
+        memory: impl Fn() -> Option<Rc<RefCell<WasmMemory>>> + 'static,
+    ) -> RuntimeFunction {
+        let message = self.message.clone();
+        RuntimeFunction::new_host(vec![ValType::I32; 5], None, move |args| {
+            let text = read_str(&memory, args[0], args[1]);
+            let file = read_str(&memory, args[2], args[3]);
+            let text = text.map(|text| {
+                format!("{} at {}:{}", text, file.unwrap_or_default(), args[4].as_u32())
+            });
+            Err::<(), _>(abort_trap(&message, text))
+        })
+    }
+
+    /// Message of the most recent abort, `None` if none happened or it couldn't be read.
+    pub fn message(&self) -> Option<String> {
+        self.message.borrow().clone()
+    }
+}
+
+/// The `(param $ptr i32 $len i32)` abort import Rust and AssemblyScript guests call,
+/// together with the [`AbortHost`] its decoded message is read back from.
+pub fn abort_handler(
+    memory: impl Fn() -> Option<Rc<RefCell<WasmMemory>>> + 'static,
+) -> (RuntimeFunction, AbortHost) {
+    let host = AbortHost::new();
+    (host.handler(memory), host)
+}
+
+/// Records `text` as the latest abort message and builds the trap carrying it.
+fn abort_trap(message: &RefCell<Option<String>>, text: Option<String>) -> Error {
+    *message.borrow_mut() = text.clone();
+    match text {
+        Some(text) => Error::host_trap(format!("{}: {}", GUEST_ABORTED, text)),
+        None => Error::trap(GUEST_ABORTED),
+    }
+}
+
+fn read_str(
+    memory: &impl Fn() -> Option<Rc<RefCell<WasmMemory>>>,
+    ptr: WasmValue,
+    len: WasmValue,
+) -> Option<String> {
+    let memory = memory()?;
+    let memory = memory.borrow();
+    let bytes = memory.read_bytes(ptr.as_u32(), len.as_u32()).ok()?;
+    Some(String::from_utf8_lossy(bytes).into_owned())
+}
@@ -1,14 +1,26 @@
 use crate::error::*;
+#[cfg(not(feature = "std"))]
+use crate::float::FloatMath;
+use crate::instructions::{Immediate, Instructions};
+use crate::interface::{ExternDesc, ImportDesc};
 use crate::leb128::{read_leb128, read_sleb128};
-use crate::module::ExternType;
+use crate::module::{read_array, read_byte, ElementMode, ExternType};
 use crate::opcodes::*;
 use crate::signature::{RuntimeSignature, Signature, ValType};
+use crate::store::{Store, WeakStore};
 use crate::wasm_memory::WasmMemory;
+use crate::HashMap;
 use crate::Module;
+use alloc::collections::VecDeque;
+use alloc::rc::{Rc, Weak};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
 use paste::paste;
-use std::cell::{Cell, RefCell};
-use std::collections::HashMap;
-use std::rc::{Rc, Weak};
 
 #[derive(Copy, Clone, Default)]
 pub struct WasmValue(pub u64);
@@ -33,156 +45,119 @@ impl WasmValue {
     #[inline(always)] pub fn as_f64(self) -> f64 { f64::from_bits(self.as_f64_bits()) }
 }
 
-#[derive(Debug)]
-struct FuncRef {
-    handle: u64,
+/// The NaN classes float results are checked against, see [`WasmValue::nan_f32`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NanKind {
+    /// Only the most significant payload bit set, either sign
+    Canonical,
+    /// Most significant payload bit set, the others arbitrary. Includes canonical NaNs
+    Arithmetic,
 }
 
-impl FuncRef {
-    const NULL: Self = Self { handle: 0 };
+#[rustfmt::skip]
+impl WasmValue {
+    const F32_QUIET: u32 = 0x7fc0_0000;
+    const F64_QUIET: u64 = 0x7ff8_0000_0000_0000;
 
-    fn new(owner_id: u32, func_idx: u32) -> Self {
-        if owner_id == 0 || func_idx == u32::MAX {
-            return Self::NULL;
-        }
-        // Try to increment refcount, but don't fail if thread local is gone
-        let _ = INSTANCE_MANAGER.try_with(|mgr| {
-            mgr.borrow_mut().inc_ref(owner_id);
-        });
-        Self { handle: ((owner_id as u64) << 32) | ((func_idx as u64) + 1) }
+    /// A positive NaN of `kind`. The arithmetic one also sets the lowest payload bit so it
+    /// isn't canonical.
+    pub fn nan_f32(kind: NanKind) -> Self {
+        Self::from_f32_bits(Self::F32_QUIET | (kind == NanKind::Arithmetic) as u32)
     }
-
-    fn from_raw(handle: u64) -> Self {
-        if handle != 0 {
-            let owner_id = (handle >> 32) as u32;
-            // Try to increment refcount, but don't fail if thread local is gone
-            let _ = INSTANCE_MANAGER.try_with(|mgr| {
-                mgr.borrow_mut().inc_ref(owner_id);
-            });
-        }
-        Self { handle }
+    pub fn nan_f64(kind: NanKind) -> Self {
+        Self::from_f64_bits(Self::F64_QUIET | (kind == NanKind::Arithmetic) as u64)
     }
 
-    fn as_raw(&self) -> u64 {
-        self.handle
-    }
-    fn owner_id(&self) -> u32 {
-        (self.handle >> 32) as u32
+    /// Whether the value read as an f32 is a NaN of `kind`.
+    pub fn is_nan_f32(self, kind: NanKind) -> bool {
+        let bits = self.as_f32_bits() & !(1 << 31);
+        match kind {
+            NanKind::Canonical => bits == Self::F32_QUIET,
+            NanKind::Arithmetic => bits & Self::F32_QUIET == Self::F32_QUIET,
+        }
     }
-}
-
-impl Clone for FuncRef {
-    fn clone(&self) -> Self {
-        if self.handle != 0 {
-            // Use try_with to avoid panicking if thread local is destroyed
-            let _ = INSTANCE_MANAGER.try_with(|mgr| {
-                mgr.borrow_mut().inc_ref(self.owner_id());
-            });
+    pub fn is_nan_f64(self, kind: NanKind) -> bool {
+        let bits = self.as_f64_bits() & !(1 << 63);
+        match kind {
+            NanKind::Canonical => bits == Self::F64_QUIET,
+            NanKind::Arithmetic => bits & Self::F64_QUIET == Self::F64_QUIET,
         }
-        Self { handle: self.handle }
     }
 }
 
-impl Drop for FuncRef {
-    fn drop(&mut self) {
-        if self.handle != 0 {
-            // Use try_with to avoid panicking if thread local is destroyed
-            let _ = INSTANCE_MANAGER.try_with(|mgr| {
-                mgr.borrow_mut().dec_ref(self.owner_id());
-            });
-        }
+impl WasmValue {
+    /// Byte width of a value in the layouts produced by [`WasmValue::write_le`].
+    pub const SIZE: usize = 8;
+
+    /// Writes the raw 64-bit slot little-endian into `buf[..8]`. 32-bit values occupy the
+    /// low four bytes and the rest are zero. Panics if `buf` is shorter than 8 bytes.
+    pub fn write_le(&self, buf: &mut [u8]) {
+        buf[..Self::SIZE].copy_from_slice(&self.0.to_le_bytes());
     }
-}
 
-impl Default for FuncRef {
-    fn default() -> Self {
-        Self::NULL
+    /// Packs `values` back to back, 8 bytes each, as [`WasmValue::write_le`] would.
+    pub fn to_le_buffer(values: &[WasmValue]) -> Vec<u8> {
+        let mut buf = vec![0u8; values.len() * Self::SIZE];
+        for (v, chunk) in values.iter().zip(buf.chunks_exact_mut(Self::SIZE)) {
+            v.write_le(chunk);
+        }
+        buf
     }
 }
 
-/// Manages instance registry and reference counting
-struct InstanceManager {
-    registry: HashMap<u32, Weak<Instance>>,
-    refcounts: HashMap<u32, usize>,
-    next_id: u32,
-    /// Instances that failed to instantiate but have live funcref references
-    /// These are kept alive until their refcount drops to zero
-    zombie_instances: HashMap<u32, Rc<Instance>>,
+#[derive(Default)]
+struct FuncRef {
+    handle: u64,
+    store: WeakStore,
 }
 
-impl InstanceManager {
-    fn new() -> Self {
-        Self {
-            registry: HashMap::new(),
-            refcounts: HashMap::new(),
-            next_id: 1,
-            zombie_instances: HashMap::new(),
+impl FuncRef {
+    fn new(store: &WeakStore, owner_id: u32, func_idx: u32) -> Self {
+        if owner_id == 0 || func_idx == u32::MAX {
+            return Self::default();
         }
+        Self::from_raw(store, ((owner_id as u64) << 32) | ((func_idx as u64) + 1))
     }
 
-    fn with<R>(f: impl FnOnce(&mut InstanceManager) -> R) -> R {
-        INSTANCE_MANAGER.with(|mgr| f(&mut mgr.borrow_mut()))
-    }
-
-    fn allocate_id(&mut self) -> u32 {
-        let id = self.next_id;
-        self.next_id += 1;
-        id
-    }
-
-    fn register_instance(&mut self, inst: &Rc<Instance>) {
-        self.registry.insert(inst.id, Rc::downgrade(inst));
-    }
-
-    fn get_instance(&self, id: u32) -> Option<Rc<Instance>> {
-        // First check if it's a zombie instance (failed instantiation but has live refs)
-        if let Some(zombie) = self.zombie_instances.get(&id) {
-            return Some(zombie.clone());
+    fn from_raw(store: &WeakStore, handle: u64) -> Self {
+        if handle != 0 {
+            store.inc_ref((handle >> 32) as u32);
         }
-        // Otherwise check the normal registry
-        self.registry.get(&id).and_then(|w| w.upgrade())
-    }
-
-    fn inc_ref(&mut self, owner_id: u32) {
-        *self.refcounts.entry(owner_id).or_insert(0) += 1;
+        Self { handle, store: store.clone() }
     }
 
-    fn dec_ref(&mut self, owner_id: u32) {
-        if let Some(count) = self.refcounts.get_mut(&owner_id) {
-            if *count > 0 {
-                *count -= 1;
-                // If refcount drops to zero, remove any zombie instance
-                if *count == 0 {
-                    self.zombie_instances.remove(&owner_id);
-                }
-            }
-        }
+    fn as_raw(&self) -> u64 {
+        self.handle
     }
+}
 
-    fn has_refs(&self, owner_id: u32) -> bool {
-        self.refcounts.get(&owner_id).copied().unwrap_or(0) > 0
+impl Clone for FuncRef {
+    fn clone(&self) -> Self {
+        Self::from_raw(&self.store, self.handle)
     }
+}
 
-    fn add_zombie(&mut self, inst: Rc<Instance>) {
-        if self.has_refs(inst.id) {
-            self.zombie_instances.insert(inst.id, inst);
+impl Drop for FuncRef {
+    fn drop(&mut self) {
+        if self.handle != 0 {
+            self.store.dec_ref((self.handle >> 32) as u32);
         }
     }
 }
 
-thread_local! {
-    static INSTANCE_MANAGER: RefCell<InstanceManager> = RefCell::new(InstanceManager::new());
-}
-
 pub struct WasmTable {
     elements: Vec<FuncRef>,
     current: u32,
     maximum: u32,
+    store: WeakStore,
 }
 
 #[rustfmt::skip]
 impl WasmTable {
-    pub fn new(initial: u32, maximum: u32) -> Self { Self { elements: vec![FuncRef::default(); initial as usize], current: initial, maximum } }
+    /// Creates a table whose funcrefs resolve through `store`.
+    pub fn new(store: &Store, initial: u32, maximum: u32) -> Self {
+        Self { elements: vec![FuncRef::default(); initial as usize], current: initial, maximum, store: store.downgrade() }
+    }
     pub fn size(&self) -> u32 { self.current }
     pub fn max(&self) -> u32 { self.maximum }
 }
@@ -195,8 +170,13 @@ impl WasmTable {
         if delta > self.maximum.saturating_sub(self.current) {
             return u32::MAX;
         }
+        // An unbounded table can be asked for more than the allocator has, which must fail
+        // the grow rather than abort the process
+        if self.elements.try_reserve_exact(delta as usize).is_err() {
+            return u32::MAX;
+        }
         let new_current = self.current + delta;
-        let func_ref = FuncRef::from_raw(value.as_u64());
+        let func_ref = FuncRef::from_raw(&self.store, value.as_u64());
         self.elements.resize(new_current as usize, func_ref);
         let old = self.current;
         self.current = new_current;
@@ -217,7 +197,46 @@ impl WasmTable {
             return Err(OOB_TABLE_ACCESS);
         }
         // FuncRef handles ref-counting automatically via Drop/Clone
-        self.elements[i] = FuncRef::from_raw(value.as_u64());
+        self.elements[i] = FuncRef::from_raw(&self.store, value.as_u64());
+        Ok(())
+    }
+    /// `table.fill`: sets `len` slots from `dst` to `value`, nothing is written if the
+    /// range is out of bounds.
+    pub fn fill(&mut self, dst: u32, value: WasmValue, len: u32) -> Result<(), &'static str> {
+        if dst as u64 + len as u64 > self.elements.len() as u64 {
+            return Err(OOB_TABLE_ACCESS);
+        }
+        let func_ref = FuncRef::from_raw(&self.store, value.as_u64());
+        self.elements[dst as usize..(dst + len) as usize].fill(func_ref);
+        Ok(())
+    }
+    /// `table.init`: copies `segment[src..src + len]` to `dst`. Both ranges are checked
+    /// before any slot is written, so an out-of-bounds init has no partial effect.
+    pub fn init(
+        &mut self,
+        dst: u32,
+        segment: &[WasmValue],
+        src: u32,
+        len: u32,
+    ) -> Result<(), &'static str> {
+        let segment: Vec<FuncRef> =
+            segment.iter().map(|value| FuncRef::from_raw(&self.store, value.as_u64())).collect();
+        self.init_refs(dst, &segment, src, len)
+    }
+
+    fn init_refs(
+        &mut self,
+        dst: u32,
+        segment: &[FuncRef],
+        src: u32,
+        len: u32,
+    ) -> Result<(), &'static str> {
+        let fits = |start: u32, size: usize| start as u64 + len as u64 <= size as u64;
+        if !fits(src, segment.len()) || !fits(dst, self.elements.len()) {
+            return Err(OOB_TABLE_ACCESS);
+        }
+        let (dst, src, len) = (dst as usize, src as usize, len as usize);
+        self.elements[dst..dst + len].clone_from_slice(&segment[src..src + len]);
         Ok(())
     }
 }
@@ -230,21 +249,79 @@ pub struct WasmGlobal {
 
 // --------------- Imports/Exports and Functions ---------------
 
+pub type HostCallback = Rc<dyn Fn(Caller, &[WasmValue]) -> Result<Vec<WasmValue>, Error>>;
+
+/// The instance whose code called a host function, giving the host access to its
+/// memory, table, globals and exports, e.g. to dereference pointer arguments.
+#[derive(Clone, Copy)]
+pub struct Caller<'a> {
+    instance: &'a Instance,
+}
+
+#[rustfmt::skip]
+impl<'a> Caller<'a> {
+    pub fn instance(&self) -> &'a Instance { self.instance }
+    pub fn memory(&self) -> Option<&'a Rc<RefCell<WasmMemory>>> { self.instance.memory.as_ref() }
+    pub fn table(&self) -> Option<&'a Rc<RefCell<WasmTable>>> { self.instance.table.as_ref() }
+    pub fn globals(&self) -> &'a [Rc<WasmGlobal>] { &self.instance.globals }
+    pub fn export(&self, name: &str) -> Option<&'a ExportValue> { self.instance.exports.get(name) }
+}
+
+/// What a host function can hand back: its results, which must be exactly as many as the
+/// signature declares (so zero or one until multi-value results land), or, through `Result`,
+/// an error that ends the invocation, e.g. a trap.
+pub trait IntoHostResults {
+    fn into_host_results(self) -> Result<Vec<WasmValue>, Error>;
+}
+
+#[rustfmt::skip]
+impl IntoHostResults for () { fn into_host_results(self) -> Result<Vec<WasmValue>, Error> { Ok(Vec::new()) } }
+#[rustfmt::skip]
+impl IntoHostResults for WasmValue { fn into_host_results(self) -> Result<Vec<WasmValue>, Error> { Ok(vec![self]) } }
+#[rustfmt::skip]
+impl IntoHostResults for Option<WasmValue> { fn into_host_results(self) -> Result<Vec<WasmValue>, Error> { Ok(self.into_iter().collect()) } }
+#[rustfmt::skip]
+impl IntoHostResults for Vec<WasmValue> { fn into_host_results(self) -> Result<Vec<WasmValue>, Error> { Ok(self) } }
+#[rustfmt::skip]
+impl<const N: usize> IntoHostResults for [WasmValue; N] { fn into_host_results(self) -> Result<Vec<WasmValue>, Error> { Ok(self.into()) } }
+#[rustfmt::skip]
+impl<R: IntoHostResults> IntoHostResults for Result<R, Error> { fn into_host_results(self) -> Result<Vec<WasmValue>, Error> { self?.into_host_results() } }
+
+/// A call into an imported wasm function that can't be reached, because its owning
+/// instance was dropped or, for a stub from [`Instance::instantiate_lenient`], never existed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DanglingImport {
+    /// The import the function was linked to, `None` if it was invoked directly
+    /// rather than through one of this instance's imports.
+    pub import: Option<ImportDesc>,
+    /// Index of the function in its exporting instance, `None` for a stub.
+    pub function_index: Option<usize>,
+}
+
+/// Called with the unreachable import just before the call traps.
+pub type DanglingImportHook = Rc<dyn Fn(&DanglingImport)>;
+
+/// Every variant keeps the declared signature next to its packed runtime form, which
+/// only records counts and which value types occur.
 #[derive(Clone)]
 pub enum RuntimeFunction {
     OwnedWasm {
         runtime_sig: RuntimeSignature,
+        sig: Rc<Signature>,
         pc_start: usize,
         locals_count: usize,
+        max_stack_height: usize,
     },
     ImportedWasm {
         runtime_sig: RuntimeSignature,
+        sig: Rc<Signature>,
         owner: Weak<Instance>,
         function_index: usize,
     },
     Host {
-        callback: Rc<dyn Fn(&[WasmValue]) -> Option<WasmValue>>,
+        callback: HostCallback,
         runtime_sig: RuntimeSignature,
+        sig: Rc<Signature>,
     },
 }
 
@@ -263,14 +340,44 @@ impl RuntimeFunction {
         self.signature().n_params() as usize
     }
 
-    pub fn new_host(
+    fn declared_signature(&self) -> &Signature {
+        match self {
+            RuntimeFunction::OwnedWasm { sig, .. }
+            | RuntimeFunction::ImportedWasm { sig, .. }
+            | RuntimeFunction::Host { sig, .. } => sig,
+        }
+    }
+
+    /// Parameter types in order.
+    pub fn param_types(&self) -> &[ValType] {
+        &self.declared_signature().params
+    }
+
+    pub fn result_type(&self) -> Option<ValType> {
+        self.declared_signature().result
+    }
+
+    /// Wraps `callback` as a host function. Returning an `Err`, e.g. `Error::trap`, ends
+    /// the invocation with that error instead of resuming the guest.
+    pub fn new_host<R: IntoHostResults>(
+        params: Vec<ValType>,
+        result: Option<ValType>,
+        callback: impl Fn(&[WasmValue]) -> R + 'static,
+    ) -> Self {
+        Self::new_host_with_caller(params, result, move |_, args| callback(args))
+    }
+
+    /// Like [`RuntimeFunction::new_host`], but the callback also gets the calling instance.
+    pub fn new_host_with_caller<R: IntoHostResults>(
         params: Vec<ValType>,
         result: Option<ValType>,
-        callback: impl Fn(&[WasmValue]) -> Option<WasmValue> + 'static,
+        callback: impl Fn(Caller, &[WasmValue]) -> R + 'static,
     ) -> Self {
+        let sig = Signature { params, result };
         RuntimeFunction::Host {
-            callback: Rc::new(callback),
-            runtime_sig: RuntimeSignature::from_signature(&Signature { params, result }),
+            callback: Rc::new(move |caller, args| callback(caller, args).into_host_results()),
+            runtime_sig: RuntimeSignature::from_signature(&sig),
+            sig: Rc::new(sig),
         }
     }
 }
@@ -287,6 +394,47 @@ pub type Exports = HashMap<String, ExportValue>;
 pub type ModuleImports = HashMap<String, ExportValue>;
 pub type Imports = HashMap<String, ModuleImports>;
 
+// --------------- Determinism Config ---------------
+/// Knobs that trade spec-exact results for determinism, set as one bundle with
+/// [`Instance::set_determinism`]. Everything is off by default.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeterminismConfig {
+    /// Rewrite a `-0.0` produced by float arithmetic or conversion to `+0.0`.
+    /// Constants, loads and reinterprets pass their bits through untouched.
+    pub canonicalize_signed_zero: bool,
+    /// Rewrite any NaN produced by float arithmetic or conversion to the positive
+    /// canonical NaN, hiding the host's payload and sign propagation.
+    pub canonicalize_nan: bool,
+    /// Non-standard: integer division and remainder by zero produce 0 instead of trapping.
+    /// Signed division overflow still traps.
+    pub relaxed_div_by_zero: bool,
+}
+
+/// Scratch space for [`Instance::invoke_pooled`]: the operand, control and call frame
+/// stacks, kept between invocations so their allocations are reused.
+#[derive(Default)]
+pub struct ExecPool {
+    stack: Vec<WasmValue>,
+    control: Vec<ControlFrame>,
+    call_frames: Vec<CallFrame>,
+}
+
+impl ExecPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Preallocates room for `values` operands and locals and `frames` nested blocks
+    /// and calls.
+    pub fn with_capacity(values: usize, frames: usize) -> Self {
+        Self {
+            stack: Vec::with_capacity(values),
+            control: Vec::with_capacity(frames),
+            call_frames: Vec::with_capacity(frames),
+        }
+    }
+}
+
 #[repr(C)]
 struct ControlFrame {
     stack_len: u32,
@@ -299,6 +447,8 @@ struct ControlFrame {
 struct CallFrame {
     stack_base: usize,
     ctrl_index: usize,
+    /// Params plus declared locals
+    n_locals: usize,
 }
 
 #[derive(Default)]
@@ -310,13 +460,293 @@ pub struct Instance {
     pub globals: Vec<Rc<WasmGlobal>>,
     pub functions: Vec<RuntimeFunction>,
     pub exports: Exports,
+    config: Cell<DeterminismConfig>,
+    store: Store,
+    memory_events: RefCell<Option<MemoryEventLog>>,
+    dangling_import_hook: RefCell<Option<DanglingImportHook>>,
+    applied_data: Vec<(u32, usize)>,
+    /// Per data segment, set by `data.drop` and for active segments once applied
+    dropped_data: Vec<Cell<bool>>,
+    /// Funcrefs per element segment, emptied by `elem.drop`. Holding them as
+    /// [`FuncRef`]s keeps their owners counted until the segment is dropped
+    elem_segments: Vec<RefCell<Vec<FuncRef>>>,
+    trap_capture: Cell<Option<usize>>,
+    last_trap: RefCell<Option<TrapState>>,
+    host_io: RefCell<HostIo>,
+    fuel: Cell<Option<u64>>,
+    strict_host_results: Cell<bool>,
+    /// Budget given to the last `set_fuel`, for `fuel_consumed`
+    fuel_budget: Cell<u64>,
+    cancel_flag: RefCell<Option<Arc<AtomicBool>>>,
+}
+
+/// Whether host function results are passed through, logged, or served from a log.
+#[derive(Default)]
+enum HostIo {
+    #[default]
+    Live,
+    Recording(Vec<Vec<WasmValue>>),
+    Replaying(VecDeque<Vec<WasmValue>>),
+}
+
+/// The trapping frame as it was when an [`Instance::invoke`] trapped, see
+/// [`Instance::capture_trap_state`].
+#[derive(Clone, Default)]
+pub struct TrapState {
+    /// Params followed by declared locals of the innermost wasm frame, empty if
+    /// the trap happened before any wasm frame was entered.
+    pub locals: Vec<WasmValue>,
+    /// Up to the requested number of that frame's operand stack values, top last.
+    pub stack_top: Vec<WasmValue>,
+    /// Wasm frames live at the trap, the trapping one included.
+    pub call_depth: usize,
+}
+
+/// Bounded log of `(old_pages, new_pages)` for successful `memory.grow`s, oldest dropped first.
+struct MemoryEventLog {
+    events: VecDeque<(u32, u32)>,
+    capacity: usize,
 }
 
 impl Instance {
+    /// Instructions executed between polls of the flag given to [`Instance::set_cancel_flag`].
+    pub const CANCEL_CHECK_INTERVAL: u32 = 1024;
+
     /// Register or re-register an instance, used for testing when wrapping in a new Rc
     pub fn register_external_instance(inst: &Rc<Instance>) {
         // This updates the registry entry even if the instance was already registered
-        InstanceManager::with(|mgr| mgr.register_instance(inst));
+        inst.store.with(|mgr| mgr.register_instance(inst));
+    }
+
+    /// Index into `functions` of the function exported as `name`.
+    pub fn func_index(&self, name: &str) -> Option<usize> {
+        match self.module.exports.get(name)? {
+            export if matches!(export.extern_type, ExternType::Func) => Some(export.idx as usize),
+            _ => None,
+        }
+    }
+
+    /// The function at `idx` in the function index space, exported or not, imports first.
+    /// Like an exported function the handle is invoked through this instance; to import it
+    /// elsewhere wrap it in [`RuntimeFunction::ImportedWasm`] owned by this instance.
+    pub fn function_handle(&self, idx: usize) -> Option<RuntimeFunction> {
+        self.functions.get(idx).cloned()
+    }
+
+    /// `(offset, len)` of each active data segment as written into memory at instantiation,
+    /// in segment order.
+    pub fn applied_data(&self) -> &[(u32, usize)] {
+        &self.applied_data
+    }
+
+    /// The store this instance was instantiated in.
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    pub fn determinism(&self) -> DeterminismConfig {
+        self.config.get()
+    }
+
+    pub fn set_determinism(&self, config: DeterminismConfig) {
+        self.config.set(config);
+    }
+
+    /// Starts recording successful `memory.grow` executions, keeping the latest `capacity`.
+    /// A capacity of zero turns recording off. Any previously recorded events are cleared.
+    pub fn record_memory_events(&self, capacity: usize) {
+        let log = (capacity > 0)
+            .then(|| MemoryEventLog { events: VecDeque::with_capacity(capacity), capacity });
+        *self.memory_events.borrow_mut() = log;
+    }
+
+    /// Recorded `(old_pages, new_pages)` grow events, oldest first.
+    pub fn memory_events(&self) -> Vec<(u32, u32)> {
+        self.memory_events
+            .borrow()
+            .as_ref()
+            .map_or(Vec::new(), |log| log.events.iter().copied().collect())
+    }
+
+    #[cold]
+    fn log_memory_grow(&self, old: u32, new: u32) {
+        if let Some(log) = self.memory_events.borrow_mut().as_mut() {
+            if log.events.len() == log.capacity {
+                log.events.pop_front();
+            }
+            log.events.push_back((old, new));
+        }
+    }
+
+    /// Snapshot the innermost frame's locals and top `stack_values` operands whenever an
+    /// invocation traps, for post-mortem inspection. `None` turns capturing off and drops
+    /// any held snapshot.
+    pub fn capture_trap_state(&self, stack_values: Option<usize>) {
+        self.trap_capture.set(stack_values);
+        *self.last_trap.borrow_mut() = None;
+    }
+
+    /// State captured at the most recent trapping invocation, kept until the next one traps.
+    pub fn last_trap_state(&self) -> Option<TrapState> {
+        self.last_trap.borrow().clone()
+    }
+
+    /// Caps the instructions this instance executes across all later invocations at
+    /// `fuel`, trapping with "out of fuel" once spent. Calls into other instances are
+    /// charged to their own budget.
+    pub fn set_fuel(&self, fuel: u64) {
+        self.fuel.set(Some(fuel));
+        self.fuel_budget.set(fuel);
+    }
+
+    /// Fuel left in the session, `None` when unmetered.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel.get()
+    }
+
+    /// Instructions executed since the last [`Instance::set_fuel`], zero when unmetered.
+    pub fn fuel_consumed(&self) -> u64 {
+        self.fuel.get().map_or(0, |left| self.fuel_budget.get() - left)
+    }
+
+    /// Lifts the session budget, later invocations run unmetered.
+    pub fn clear_fuel(&self) {
+        self.fuel.set(None);
+    }
+
+    /// Makes later invocations poll `flag` every [`Instance::CANCEL_CHECK_INTERVAL`]
+    /// instructions and trap with "cancelled" once it is set, e.g. by a watchdog thread.
+    /// The flag is left set, clear it before invoking again.
+    pub fn set_cancel_flag(&self, flag: Arc<AtomicBool>) {
+        *self.cancel_flag.borrow_mut() = Some(flag);
+    }
+
+    /// Stops polling the cancellation flag.
+    pub fn clear_cancel_flag(&self) {
+        *self.cancel_flag.borrow_mut() = None;
+    }
+
+    /// When on, a host function returning a value with high bits set for an `i32` or `f32`
+    /// result traps with "host result mismatch". The result count is always checked, values
+    /// are untyped, so this is the only type check that can be made.
+    pub fn set_strict_host_results(&self, strict: bool) {
+        self.strict_host_results.set(strict);
+    }
+
+    /// Raw funcref handle of the exported function `name`, the encoding element segments
+    /// store in tables, so it can be placed with [`WasmTable::set`]. Functions re-exported
+    /// from another instance resolve to their owner.
+    pub fn func_ref_handle(&self, name: &str) -> Option<u64> {
+        let value = self.func_ref_value(Some(self.func_index(name)? as u32)).ok()?;
+        Some(value.as_u64())
+    }
+
+    /// Funcref handle of an element segment item, null for `None`.
+    fn func_ref_value(&self, func_idx: Option<u32>) -> Result<WasmValue, Error> {
+        let Some(func_idx) = func_idx else {
+            return Ok(WasmValue::from_u64(0));
+        };
+        let (owner_id, func_idx) =
+            self.func_ref_owner(func_idx).ok_or(Error::validation(UNKNOWN_FUNC))?;
+        Ok(WasmValue::from_u64(((owner_id as u64) << 32) | (func_idx as u64 + 1)))
+    }
+
+    /// `(instance id, function index)` a funcref to this instance's function `func_idx`
+    /// points at, following imported wasm functions back to their owner.
+    fn func_ref_owner(&self, func_idx: u32) -> Option<(u32, u32)> {
+        match self.functions.get(func_idx as usize)? {
+            RuntimeFunction::ImportedWasm { owner, function_index, .. } => match owner.upgrade() {
+                Some(owner_rc) => Some((owner_rc.id, *function_index as u32)),
+                None => Some((self.id, func_idx)),
+            },
+            RuntimeFunction::OwnedWasm { .. } | RuntimeFunction::Host { .. } => {
+                Some((self.id, func_idx))
+            }
+        }
+    }
+
+    #[cold]
+    fn snapshot_trap(&self, stack: &[WasmValue], call_frames: &[CallFrame], stack_values: usize) {
+        let (locals, operands) = match call_frames.last() {
+            Some(frame) => {
+                let locals_end = (frame.stack_base + frame.n_locals).min(stack.len());
+                (&stack[frame.stack_base.min(locals_end)..locals_end], &stack[locals_end..])
+            }
+            None => (&[][..], stack),
+        };
+        let top = &operands[operands.len().saturating_sub(stack_values)..];
+        *self.last_trap.borrow_mut() = Some(TrapState {
+            locals: locals.to_vec(),
+            stack_top: top.to_vec(),
+            call_depth: call_frames.len(),
+        });
+    }
+
+    /// Whether `other` holds the same memory contents and global values, bit for bit, e.g.
+    /// to check two instances that ran the same calls in a differential test. Tables and
+    /// the instances' configuration aren't compared.
+    pub fn state_equal(&self, other: &Instance) -> bool {
+        let memories_equal = match (&self.memory, &other.memory) {
+            (Some(a), Some(b)) => a.borrow().as_bytes() == b.borrow().as_bytes(),
+            (None, None) => true,
+            _ => false,
+        };
+        memories_equal
+            && self.globals.len() == other.globals.len()
+            && self
+                .globals
+                .iter()
+                .zip(&other.globals)
+                .all(|(a, b)| a.value.get().as_u64() == b.value.get().as_u64())
+    }
+
+    /// Starts logging the result of every host function call made by this instance's
+    /// code, in call order, replacing any log being recorded or replayed.
+    pub fn record_host_calls(&self) {
+        *self.host_io.borrow_mut() = HostIo::Recording(Vec::new());
+    }
+
+    /// Serves host function calls from a log taken by [`Instance::take_host_log`] instead
+    /// of running the callbacks, so a recorded run can be reproduced. A call the log has
+    /// no matching entry for traps.
+    pub fn replay_host_calls(&self, log: Vec<Vec<WasmValue>>) {
+        *self.host_io.borrow_mut() = HostIo::Replaying(log.into());
+    }
+
+    /// Goes back to calling host functions directly, returning the recorded results or
+    /// the entries a replay didn't consume.
+    pub fn take_host_log(&self) -> Vec<Vec<WasmValue>> {
+        match self.host_io.replace(HostIo::Live) {
+            HostIo::Live => Vec::new(),
+            HostIo::Recording(log) => log,
+            HostIo::Replaying(log) => log.into(),
+        }
+    }
+
+    /// Installs a callback run just before a call into a dangling imported function traps.
+    pub fn set_dangling_import_hook(&self, hook: impl Fn(&DanglingImport) + 'static) {
+        *self.dangling_import_hook.borrow_mut() = Some(Rc::new(hook));
+    }
+
+    /// `idx` is the importing function's index in this instance, `function_index` the
+    /// one in its exporter.
+    #[cold]
+    fn dangling_import(&self, idx: Option<usize>, function_index: usize) -> Error {
+        let hook = self.dangling_import_hook.borrow().clone();
+        if let Some(hook) = hook {
+            let function = idx.and_then(|idx| self.module.functions.get(idx));
+            let import = function.and_then(|f| {
+                let import = f.import.as_ref()?;
+                Some(ImportDesc {
+                    module: import.module.clone(),
+                    field: import.field.clone(),
+                    desc: ExternDesc::Func(Signature::clone(&f.ty)),
+                })
+            });
+            let function_index = (function_index != usize::MAX).then_some(function_index);
+            hook(&DanglingImport { import, function_index });
+        }
+        Error::trap(FUNC_NO_IMPL)
     }
 
     fn resolve_import<'a>(
@@ -329,14 +759,96 @@ impl Instance {
             .ok_or(Error::link(UNKNOWN_IMPORT))
     }
 
-    pub fn instantiate(module: Rc<Module>, imports: &Imports) -> Result<Self, Error> {
+    /// Like [`Instance::instantiate`], but imports missing from `imports` are stubbed
+    /// instead of failing to link. Returns the stubbed imports as well.
+    ///
+    /// Stubbed functions trap with "function has no implementation" when called, like a
+    /// call into a dropped instance. Stubbed memories and tables start at their minimum
+    /// size and stubbed globals hold zero. The stubs are listed sorted by module and field;
+    /// imports that are provided but don't match still fail to link.
+    pub fn instantiate_lenient(
+        store: &Store,
+        module: Rc<Module>,
+        imports: &Imports,
+    ) -> Result<(Self, Vec<ImportDesc>), Error> {
+        let mut imports = imports.clone();
+        let mut stubbed = Vec::new();
+        for ((module_name, field), desc) in module.import_descs() {
+            if imports.get(&module_name).is_some_and(|m| m.contains_key(&field)) {
+                continue;
+            }
+            let stub = match &desc {
+                ExternDesc::Func(sig) => ExportValue::Function(RuntimeFunction::ImportedWasm {
+                    runtime_sig: RuntimeSignature::from_signature(sig),
+                    sig: Rc::new(sig.clone()),
+                    owner: Weak::new(),
+                    function_index: usize::MAX,
+                }),
+                ExternDesc::Table { min, max } => {
+                    ExportValue::Table(Rc::new(RefCell::new(WasmTable::new(store, *min, *max))))
+                }
+                ExternDesc::Memory { min, max } => {
+                    let mem = WasmMemory::try_new(*min, *max).map_err(Error::link)?;
+                    ExportValue::Memory(Rc::new(RefCell::new(mem)))
+                }
+                ExternDesc::Global { ty, mutable } => ExportValue::Global(Rc::new(WasmGlobal {
+                    ty: *ty,
+                    mutable: *mutable,
+                    value: Cell::new(WasmValue::default()),
+                })),
+            };
+            imports.entry(module_name.clone()).or_default().insert(field.clone(), stub);
+            stubbed.push(ImportDesc { module: module_name, field, desc });
+        }
+        stubbed.sort_by(|a, b| (&a.module, &a.field).cmp(&(&b.module, &b.field)));
+        let instance = Self::instantiate(store, module, &imports)?;
+        Ok((instance, stubbed))
+    }
+
+    /// Instantiates `module` in `store`. Funcrefs stored in tables only dispatch between
+    /// instances of the same store, so imported tables and wasm functions from another
+    /// store fail with "incompatible import type".
+    pub fn instantiate(
+        store: &Store,
+        module: Rc<Module>,
+        imports: &Imports,
+    ) -> Result<Self, Error> {
+        Self::instantiate_impl(store, module, imports, None)
+    }
+
+    /// Like [`Instance::instantiate`], but a trapping start function also hands back the
+    /// instance it ran in.
+    ///
+    /// When the start function traps, the error comes with the partially initialized
+    /// instance so its memory and globals can be inspected. It stays a zombie in the store
+    /// as long as funcrefs to it are alive, the handle only adds one more reference. Every
+    /// other failure happens before the instance exists and comes with `None`.
+    pub fn instantiate_capturing_start_trap(
+        store: &Store,
+        module: Rc<Module>,
+        imports: &Imports,
+    ) -> Result<Self, (Error, Option<Rc<Instance>>)> {
+        let mut started = None;
+        Self::instantiate_impl(store, module, imports, Some(&mut started)).map_err(|e| (e, started))
+    }
+
+    fn instantiate_impl(
+        store: &Store,
+        module: Rc<Module>,
+        imports: &Imports,
+        start_trap_instance: Option<&mut Option<Rc<Instance>>>,
+    ) -> Result<Self, Error> {
         // Build the instance inside a Rc so we can register a Weak handle
         // for cross-instance func_ref dispatch even if instantiation ultimately fails.
-        let mut inst_rc = Rc::new(Instance { module: module.clone(), ..Default::default() });
+        let mut inst_rc = Rc::new(Instance {
+            module: module.clone(),
+            store: store.clone(),
+            ..Default::default()
+        });
         {
             // Configure the instance while we hold the only strong Rc
             let inst = Rc::get_mut(&mut inst_rc).expect("sole owner expected");
-            inst.id = InstanceManager::with(|mgr| mgr.allocate_id());
+            inst.id = store.with(|mgr| mgr.allocate_id());
 
             // Memory
             if let Some(memory) = &module.memory {
@@ -345,7 +857,10 @@ impl Instance {
                     match imported {
                         ExportValue::Memory(mem) => {
                             let m = mem.borrow();
-                            if m.size() < memory.min || m.max() > memory.max {
+                            // A declared max of MAX_PAGES means none was given, which
+                            // accepts a provided memory with any maximum
+                            let unbounded = memory.max == Module::MAX_PAGES;
+                            if m.size() < memory.min || (!unbounded && m.max() > memory.max) {
                                 return Err(Error::link(INCOMPATIBLE_IMPORT));
                             }
                             drop(m);
@@ -354,8 +869,8 @@ impl Instance {
                         _ => return Err(Error::link(INCOMPATIBLE_IMPORT)),
                     }
                 } else {
-                    inst.memory =
-                        Some(Rc::new(RefCell::new(WasmMemory::new(memory.min, memory.max))));
+                    let mem = WasmMemory::try_new(memory.min, memory.max).map_err(Error::link)?;
+                    inst.memory = Some(Rc::new(RefCell::new(mem)));
                 }
             }
 
@@ -366,7 +881,10 @@ impl Instance {
                     match imported {
                         ExportValue::Table(tab) => {
                             let tb = tab.borrow();
-                            if tb.size() < table.min || tb.max() > table.max {
+                            // Instance ids are only unique within a store, a funcref from
+                            // another store would resolve to the wrong instance
+                            if !store.is(&tb.store) || tb.size() < table.min || tb.max() > table.max
+                            {
                                 return Err(Error::link(INCOMPATIBLE_IMPORT));
                             }
                             drop(tb);
@@ -375,7 +893,8 @@ impl Instance {
                         _ => return Err(Error::link(INCOMPATIBLE_IMPORT)),
                     }
                 } else {
-                    inst.table = Some(Rc::new(RefCell::new(WasmTable::new(table.min, table.max))));
+                    inst.table =
+                        Some(Rc::new(RefCell::new(WasmTable::new(store, table.min, table.max))));
                 }
             }
 
@@ -384,12 +903,18 @@ impl Instance {
             for function in &module.functions {
                 if let Some(import_ref) = &function.import {
                     let imported = Self::resolve_import(imports, import_ref)?;
-                    let runtime_sig = RuntimeSignature::from_signature(&function.ty);
                     match imported {
                         ExportValue::Function(f) => {
-                            if f.signature() != runtime_sig {
+                            // The packed runtime signature would accept reordered params
+                            if *f.declared_signature() != *function.ty {
                                 return Err(Error::link(INCOMPATIBLE_IMPORT));
                             }
+                            if let RuntimeFunction::ImportedWasm { owner, .. } = f {
+                                if owner.upgrade().is_some_and(|o| !store.is(&o.store.downgrade()))
+                                {
+                                    return Err(Error::link(INCOMPATIBLE_IMPORT));
+                                }
+                            }
                             inst.functions.push(f.clone());
                         }
                         _ => return Err(Error::link(INCOMPATIBLE_IMPORT)),
@@ -399,8 +924,10 @@ impl Instance {
                         function.locals.len().saturating_sub(function.ty.params.len());
                     inst.functions.push(RuntimeFunction::OwnedWasm {
                         runtime_sig: RuntimeSignature::from_signature(&function.ty),
+                        sig: function.ty.clone(),
                         pc_start: function.body.start,
                         locals_count,
+                        max_stack_height: function.max_stack_height,
                     });
                 }
             }
@@ -432,43 +959,42 @@ impl Instance {
                 }
             }
 
-            // Collect element segments (validate bounds, defer writes)
-            let mut collected_elements: Vec<(u32, Vec<u32>)> = Vec::new();
-            if module.element_count > 0 {
-                if inst.table.is_none() {
-                    return Err(Error::link(UNKNOWN_TABLE));
-                }
-                let bytes = &module.bytes;
-                let mut it = module.element_start;
-                collected_elements.reserve(module.element_count as usize);
-                for _ in 0..module.element_count {
-                    let flags: u32 = read_leb128(bytes, &mut it)?;
-                    if flags != 0 {
-                        return Err(Error::malformed(INVALID_VALUE_TYPE));
-                    }
-                    let offset = Instance::eval_const(&module, &mut it, &inst.globals)?.as_u32();
-                    let n: u32 = read_leb128(bytes, &mut it)?;
-                    {
-                        let table_rc = inst.table.as_ref().ok_or(Error::link(UNKNOWN_TABLE))?;
-                        let table_borrow = table_rc.borrow();
-                        if (offset as u64) + (n as u64) > table_borrow.size() as u64 {
-                            return Err(Error::link(ELEM_SEG_DNF));
-                        }
-                    }
-                    let mut indices: Vec<u32> = Vec::with_capacity(n as usize);
-                    for _ in 0..n {
-                        let func_idx: u32 = read_leb128(bytes, &mut it)?;
-                        indices.push(func_idx);
-                    }
-                    collected_elements.push((offset, indices));
+            // Collect active element segments (validate bounds, defer writes). Active and
+            // declarative segments count as dropped, only passive ones feed table.init
+            let mut collected_elements: Vec<(u32, Vec<Option<u32>>)> = Vec::new();
+            for segment in module.element_segments_iter() {
+                let ElementMode::Active { mut offset_expr, .. } = segment.mode else {
+                    let handles = match segment.mode {
+                        ElementMode::Passive => segment
+                            .functions
+                            .iter()
+                            .map(|idx| {
+                                let handle = inst.func_ref_value(*idx)?.as_u64();
+                                Ok(FuncRef::from_raw(&store.downgrade(), handle))
+                            })
+                            .collect::<Result<_, Error>>()?,
+                        _ => Vec::new(),
+                    };
+                    inst.elem_segments.push(RefCell::new(handles));
+                    continue;
+                };
+                inst.elem_segments.push(RefCell::default());
+                let table_rc = inst.table.as_ref().ok_or(Error::link(UNKNOWN_TABLE))?;
+                let offset =
+                    Instance::eval_const(&module, &mut offset_expr, &inst.globals)?.as_u32();
+                let n = segment.functions.len() as u64;
+                if (offset as u64) + n > table_rc.borrow().size() as u64 {
+                    return Err(Error::link(ELEM_SEG_DNF));
                 }
+                collected_elements.push((offset, segment.functions));
             }
 
-            // Validate data segments (bounds check, defer writes)
-            let mut pending_data: Vec<(u32, usize, usize)> = Vec::new();
+            // Validate data segments (bounds check, defer writes). Bounds are taken
+            // against the current size, memory is never grown to fit a segment.
+            let mut pending_data: Vec<(u32, Range<usize>)> = Vec::new();
             if let Some(mem) = &inst.memory {
                 for seg in &module.data_segments {
-                    let mut ip = seg.initializer_offset;
+                    let Some(mut ip) = seg.initializer_offset else { continue };
                     let offset = Instance::eval_const(&module, &mut ip, &inst.globals)?.as_u32();
                     let data_len = seg.data_range.end - seg.data_range.start;
                     let m = mem.borrow();
@@ -477,7 +1003,7 @@ impl Instance {
                         return Err(Error::link(DATA_SEG_DNF));
                     }
                     drop(m);
-                    pending_data.push((offset, seg.data_range.start, seg.data_range.end));
+                    pending_data.push((offset, seg.data_range.clone()));
                 }
             }
 
@@ -486,34 +1012,36 @@ impl Instance {
                 let table_rc = inst.table.as_ref().ok_or(Error::link(UNKNOWN_TABLE))?.clone();
                 for (offset, indices) in &collected_elements {
                     for (j, idx) in indices.iter().enumerate() {
-                        let func_idx = *idx as usize;
-                        let (owner_id, owner_func_idx) = match &inst.functions[func_idx] {
-                            RuntimeFunction::ImportedWasm { owner, function_index, .. } => {
-                                if let Some(owner_rc) = owner.upgrade() {
-                                    (owner_rc.id, *function_index as u32)
-                                } else {
-                                    (inst.id, func_idx as u32)
-                                }
-                            }
-                            RuntimeFunction::OwnedWasm { .. } | RuntimeFunction::Host { .. } => {
-                                (inst.id, func_idx as u32)
+                        let func_ref = match idx {
+                            Some(idx) => {
+                                let (owner_id, owner_func_idx) = inst
+                                    .func_ref_owner(*idx)
+                                    .ok_or(Error::validation(UNKNOWN_FUNC))?;
+                                FuncRef::new(&store.downgrade(), owner_id, owner_func_idx)
                             }
+                            None => FuncRef::default(),
                         };
-                        let func_ref = FuncRef::new(owner_id, owner_func_idx);
                         let func_ref_value = WasmValue::from_u64(func_ref.as_raw());
-                        if table_rc.borrow_mut().set(*offset + (j as u32), func_ref_value).is_err()
-                        {
+                        // offset + n was checked against the table size in u64, so no slot wraps
+                        let slot = offset.checked_add(j as u32);
+                        debug_assert!(slot.is_some(), "element slot index overflowed");
+                        let slot = slot.ok_or(Error::link(ELEM_SEG_DNF))?;
+                        if table_rc.borrow_mut().set(slot, func_ref_value).is_err() {
                             return Err(Error::link(ELEM_SEG_DNF));
                         }
                     }
                 }
             }
 
-            // Apply data segments (writes), after elements
+            // Apply data segments (writes), after elements, copying straight from the module bytes.
+            // Active segments count as dropped afterwards, only passive ones feed memory.init
+            inst.dropped_data =
+                module.data_segments.iter().map(|seg| Cell::new(!seg.is_passive())).collect();
             if let Some(mem) = &inst.memory {
                 let mut m = mem.borrow_mut();
-                for &(offset, start, end) in &pending_data {
-                    m.write_bytes(offset, &module.bytes[start..end]).map_err(Error::trap)?;
+                for (offset, range) in pending_data {
+                    inst.applied_data.push((offset, range.len()));
+                    m.write_bytes(offset, &module.bytes[range]).map_err(Error::trap)?;
                 }
             }
 
@@ -549,7 +1077,7 @@ impl Instance {
         // Register a weak reference before potential start execution so that
         // even if start traps, func_refs already stored in tables can resolve
         // the owning instance via the registry
-        InstanceManager::with(|mgr| mgr.register_instance(&inst_rc));
+        store.with(|mgr| mgr.register_instance(&inst_rc));
 
         // Start
         if let Some(start_idx) = module.start {
@@ -570,11 +1098,19 @@ impl Instance {
                 &mut call_frames,
             ) {
                 Ok(()) => {}
-                Err(Error::Trap(msg)) => {
+                Err(e @ (Error::Trap(_) | Error::HostTrap(_))) => {
+                    if let Some(slot) = start_trap_instance {
+                        *slot = Some(inst_rc.clone());
+                    }
                     // If there are live func_ref references to this instance,
                     // keep it alive as a zombie until all references are dropped
-                    InstanceManager::with(|mgr| mgr.add_zombie(inst_rc));
-                    return Err(Error::uninstantiable(msg));
+                    let unreferenced = store.with(|mgr| mgr.add_zombie(inst_rc));
+                    drop(unreferenced);
+                    // A host trap keeps its runtime message instead of becoming uninstantiable
+                    return Err(match e {
+                        Error::Trap(msg) => Error::uninstantiable(msg),
+                        e => e,
+                    });
                 }
                 Err(e) => {
                     return Err(e);
@@ -597,25 +1133,28 @@ impl Instance {
     ) -> Result<WasmValue, Error> {
         let bytes: &[u8] = &module.bytes;
         let mut stack: Vec<WasmValue> = Vec::with_capacity(4);
+        // Compiled initializers are validated, but the module bytes are public and may have
+        // been altered since, so an empty stack is an error rather than a panic
+        macro_rules! pop { () => { stack.pop().ok_or(Error::validation(TYPE_MISMATCH))? } }
         loop {
-            let op = bytes[*pc]; *pc += 1;
+            let op = read_byte(bytes, pc)?;
             match op {
                 I32_CONST => { let v: i32 = read_sleb128(bytes, pc)?; stack.push(WasmValue::from_i32(v)); }
                 I64_CONST => { let v: i64 = read_sleb128(bytes, pc)?; stack.push(WasmValue::from_i64(v)); }
-                F32_CONST => { let bits = u32::from_le_bytes(bytes[*pc..*pc+4].try_into().unwrap()); *pc += 4; stack.push(WasmValue::from_f32_bits(bits)); }
-                F64_CONST => { let bits = u64::from_le_bytes(bytes[*pc..*pc+8].try_into().unwrap()); *pc += 8; stack.push(WasmValue::from_f64_bits(bits)); }
+                F32_CONST => { let bits = u32::from_le_bytes(read_array(bytes, pc)?); stack.push(WasmValue::from_f32_bits(bits)); }
+                F64_CONST => { let bits = u64::from_le_bytes(read_array(bytes, pc)?); stack.push(WasmValue::from_f64_bits(bits)); }
                 GLOBAL_GET => { let gi: u32 = read_leb128(bytes, pc)?; let g = gi as usize; if g >= globals.len() { return Err(Error::validation(UNKNOWN_GLOBAL)); }stack.push(globals[g].value.get()); }
-                I32_ADD => { let b = stack.pop().unwrap().as_u32(); let a = stack.pop().unwrap().as_u32(); stack.push(WasmValue::from_u32(a.wrapping_add(b))); }
-                I32_SUB => { let b = stack.pop().unwrap().as_u32(); let a = stack.pop().unwrap().as_u32(); stack.push(WasmValue::from_u32(a.wrapping_sub(b))); }
-                I32_MUL => { let b = stack.pop().unwrap().as_u32(); let a = stack.pop().unwrap().as_u32(); stack.push(WasmValue::from_u32(a.wrapping_mul(b))); }
-                I64_ADD => { let b = stack.pop().unwrap().as_u64(); let a = stack.pop().unwrap().as_u64(); stack.push(WasmValue::from_u64(a.wrapping_add(b))); }
-                I64_SUB => { let b = stack.pop().unwrap().as_u64(); let a = stack.pop().unwrap().as_u64(); stack.push(WasmValue::from_u64(a.wrapping_sub(b))); }
-                I64_MUL => { let b = stack.pop().unwrap().as_u64(); let a = stack.pop().unwrap().as_u64(); stack.push(WasmValue::from_u64(a.wrapping_mul(b))); }
+                I32_ADD => { let b = pop!().as_u32(); let a = pop!().as_u32(); stack.push(WasmValue::from_u32(a.wrapping_add(b))); }
+                I32_SUB => { let b = pop!().as_u32(); let a = pop!().as_u32(); stack.push(WasmValue::from_u32(a.wrapping_sub(b))); }
+                I32_MUL => { let b = pop!().as_u32(); let a = pop!().as_u32(); stack.push(WasmValue::from_u32(a.wrapping_mul(b))); }
+                I64_ADD => { let b = pop!().as_u64(); let a = pop!().as_u64(); stack.push(WasmValue::from_u64(a.wrapping_add(b))); }
+                I64_SUB => { let b = pop!().as_u64(); let a = pop!().as_u64(); stack.push(WasmValue::from_u64(a.wrapping_sub(b))); }
+                I64_MUL => { let b = pop!().as_u64(); let a = pop!().as_u64(); stack.push(WasmValue::from_u64(a.wrapping_mul(b))); }
                 END => break,
                 _ => return Err(Error::validation(CONST_EXP_REQUIRED)),
             }
         }
-        Ok(stack.pop().unwrap())
+        Ok(pop!())
     }
 
     #[inline]
@@ -649,27 +1188,58 @@ impl Instance {
         }
 
         // Track function frame
-        call_frames.push(CallFrame { stack_base: locals_start, ctrl_index: control.len() - 1 });
+        call_frames.push(CallFrame {
+            stack_base: locals_start,
+            ctrl_index: control.len() - 1,
+            n_locals: n_params + locals_count,
+        });
 
         // Return the function's start PC
         Ok(pc_start)
     }
 
-    /// Dispatch a host function call, handling params and optional result.
+    /// Dispatch a host function call, replacing its params with its results. Signatures
+    /// declare at most one result, and the host must return exactly that many values.
+    /// Multiple results are not supported, so a surplus traps rather than being dropped.
+    /// Recorded and replayed results go through the same checks, so a replay behaves as
+    /// the live run did.
     #[inline(always)]
     fn call_host(
-        callback: &dyn Fn(&[WasmValue]) -> Option<WasmValue>,
-        runtime_sig: RuntimeSignature,
+        &self,
+        callback: &HostCallback,
+        sig: &Signature,
         stack: &mut Vec<WasmValue>,
-    ) {
-        let param_count = runtime_sig.n_params() as usize;
-        let params_start = stack.len() - param_count;
-        if let Some(result) = callback(&stack[params_start..]) {
-            stack.truncate(params_start);
-            stack.push(result);
-        } else {
-            stack.truncate(params_start);
+    ) -> Result<(), Error> {
+        let params_start = stack.len() - sig.params.len();
+        // No borrow is held across the callback, it may call back into this instance
+        let replayed = match &mut *self.host_io.borrow_mut() {
+            HostIo::Replaying(log) => Some(log.pop_front()),
+            _ => None,
+        };
+        let results = match replayed {
+            Some(Some(results)) => results,
+            Some(None) => return Err(Error::trap(HOST_REPLAY_DIVERGED)),
+            None => {
+                let results = callback(Caller { instance: self }, &stack[params_start..])?;
+                if let HostIo::Recording(log) = &mut *self.host_io.borrow_mut() {
+                    log.push(results.clone());
+                }
+                results
+            }
+        };
+        // Too few values would leave the caller reading past the operand stack, and without
+        // multi-result signatures there is nowhere to put more
+        if results.len() != sig.result.is_some() as usize {
+            return Err(Error::trap(HOST_RESULT_MISMATCH));
+        }
+        // Like invoke's argument check, a 32-bit result must be zero-extended
+        let narrow = matches!(sig.result, Some(ValType::I32 | ValType::F32));
+        if self.strict_host_results.get() && narrow && results[0].as_u64() >> 32 != 0 {
+            return Err(Error::trap(HOST_RESULT_MISMATCH));
         }
+        stack.truncate(params_start);
+        stack.extend(results);
+        Ok(())
     }
 
     /// Dispatch a cross-instance call by copying params to a temporary stack.
@@ -712,7 +1282,7 @@ impl Instance {
         }
         let fi = &self.functions[idx];
         match fi {
-            RuntimeFunction::OwnedWasm { runtime_sig, pc_start, locals_count } => {
+            RuntimeFunction::OwnedWasm { runtime_sig, pc_start, locals_count, .. } => {
                 let pc = Self::setup_wasm_function_call(
                     *runtime_sig,
                     *pc_start,
@@ -722,7 +1292,7 @@ impl Instance {
                     call_frames,
                     *return_pc,
                 )?;
-                self.interpret(pc, stack, control, call_frames)?;
+                self.interpret(&self.module.bytes, pc, stack, control, call_frames)?;
             }
             RuntimeFunction::ImportedWasm { owner, function_index, .. } => {
                 if let Some(owner_rc) = owner.upgrade() {
@@ -734,11 +1304,11 @@ impl Instance {
                         call_frames,
                     )?;
                 } else {
-                    return Err(Error::trap(FUNC_NO_IMPL));
+                    return Err(self.dangling_import(Some(idx), *function_index));
                 }
             }
-            RuntimeFunction::Host { callback, runtime_sig } => {
-                Self::call_host(callback.as_ref(), *runtime_sig, stack);
+            RuntimeFunction::Host { callback, sig, .. } => {
+                self.call_host(callback, sig, stack)?;
             }
         }
         Ok(())
@@ -747,17 +1317,55 @@ impl Instance {
     #[rustfmt::skip]
     fn interpret(
         &self,
+        bytes: &[u8],
         mut pc: usize,
         stack: &mut Vec<WasmValue>,
         control: &mut Vec<ControlFrame>,
         call_frames: &mut Vec<CallFrame>,
     ) -> Result<(), Error> {
-        let bytes: &[u8] = &self.module.bytes;
         let mem = self.memory.as_ref();
         let tab = self.table.as_ref();
-        let mut current_base = call_frames.last().unwrap().stack_base;
-
-        macro_rules! next_op { () => {{ let byte = unsafe { *bytes.get_unchecked(pc) }; pc += 1; byte }} }
+        let Some(entry_frame) = call_frames.last() else {
+            return Err(Error::trap(STACK_UNDERFLOW));
+        };
+        let mut current_base = entry_frame.stack_base;
+        let config = self.config.get();
+        let canonical_zero = config.canonicalize_signed_zero;
+        let canonical_nan = config.canonicalize_nan;
+        let relaxed_div = config.relaxed_div_by_zero;
+        let metered = self.fuel.get().is_some();
+        let cancel_flag = self.cancel_flag.borrow().clone();
+        let mut until_cancel_check = Self::CANCEL_CHECK_INTERVAL;
+
+        macro_rules! next_op { () => {{
+            match bytes.get(pc) { Some(&byte) => { pc += 1; byte } None => return Err(Error::malformed(UNEXPECTED_END)) }
+        }} }
+        macro_rules! next_bytes { ($n:literal) => {{
+            match bytes.get(pc..pc + $n) {
+                Some(raw) => { pc += $n; <[u8; $n]>::try_from(raw).unwrap() }
+                None => return Err(Error::malformed(UNEXPECTED_END)),
+            }
+        }} }
+        // Validation bounds local indices, debug builds also check them against the frame
+        macro_rules! local_slot {
+            ($local:expr) => {{
+                let n_locals = call_frames.last().map_or(0, |f| f.n_locals);
+                debug_assert!(($local as usize) < n_locals, "local index {} out of frame range", $local);
+                debug_assert!(current_base + n_locals <= stack.len(), "frame base {} past stack", current_base);
+                current_base + $local as usize
+            }};
+        }
+        // Validation rules these failures out, they trap rather than panic should the
+        // interpreter ever disagree with the validator
+        macro_rules! frame { () => {{
+            match call_frames.last() { Some(frame) => *frame, None => return Err(Error::trap(STACK_UNDERFLOW)) }
+        }} }
+        macro_rules! block_entry { () => {{
+            match self.module.side_table.lookup(pc) { Some(entry) => entry, None => return Err(Error::trap(MISSING_SIDE_TABLE_ENTRY)) }
+        }} }
+        macro_rules! block_base { ($params_len:expr) => {{
+            match stack.len().checked_sub($params_len as usize) { Some(base) => base as u32, None => return Err(Error::trap(STACK_UNDERFLOW)) }
+        }} }
         macro_rules! pop_val { () => {{
             match stack.pop() { Some(v) => v, None => return Err(Error::trap(STACK_UNDERFLOW)) }
         }} }
@@ -842,6 +1450,19 @@ impl Instance {
                 }
             }};
         }
+        macro_rules! canonicalize {
+            ($type:ident) => {{
+                paste! {
+                    if canonical_zero || canonical_nan {
+                        let len = stack.len();
+                        let top = unsafe { stack.get_unchecked_mut(len - 1) };
+                        let x = top.[<as_ $type>]();
+                        if canonical_zero && x == 0.0 { *top = WasmValue::[<from_ $type>](0.0); }
+                        if canonical_nan && x.is_nan() { *top = WasmValue::[<from_ $type>](<$type>::NAN); }
+                    }
+                }
+            }};
+        }
         macro_rules! minmax {
             ($type:ident, min) => {{ minmax!(@impl $type, min, true) }};
             ($type:ident, max) => {{ minmax!(@impl $type, max, false) }};
@@ -849,12 +1470,11 @@ impl Instance {
                 paste! {
                     let (a, b) = peek_two!($type);
 
-                    let result = if a.is_nan() {
-                        a
-                    } else if b.is_nan() {
-                        b
+                    // Arithmetic on a NaN quiets it, a signaling operand can't come back unchanged
+                    let result = if a.is_nan() || b.is_nan() {
+                        a + b
                     } else if a == b && a == 0.0 {
-                        const SIGN_BIT_SHIFT: usize = std::mem::size_of::<$type>() * 8 - 1;
+                        const SIGN_BIT_SHIFT: usize = core::mem::size_of::<$type>() * 8 - 1;
                         let a_has_sign = a.to_bits() >> SIGN_BIT_SHIFT != 0;
                         if a_has_sign == $want_negative { a } else { b }
                     } else {
@@ -885,7 +1505,9 @@ impl Instance {
             ($type:ident) => {{
                 paste! {
                     let x = peek_one!($type);
-                    let y = if x.is_nan() || x.is_infinite() {
+                    let y = if x.is_nan() {
+                        x + x
+                    } else if x.is_infinite() {
                         x
                     } else {
                         let lower = x.floor();
@@ -930,13 +1552,23 @@ impl Instance {
                 }
             }};
         }
+        // Division by zero traps, or yields 0 under `relaxed_div_by_zero`
+        macro_rules! div_by_zero {
+            ($type:ident) => {{
+                paste! {
+                    if !relaxed_div { return Err(Error::trap(DIVIDE_BY_ZERO)); }
+                    overwrite!(WasmValue::[<from_ $type>](0));
+                }
+            }};
+        }
         macro_rules! div_s {
             ($int_type:ident) => {{
                 paste! {
                     let (a, b) = peek_two!($int_type);
-                    if b == 0 { return Err(Error::trap(DIVIDE_BY_ZERO)); }
-                    if a == $int_type::MIN && b == -1 { return Err(Error::trap(INTEGER_OVERFLOW)); }
-                    overwrite!(WasmValue::[<from_ $int_type>](a / b));
+                    if b == 0 { div_by_zero!($int_type); } else {
+                        if a == $int_type::MIN && b == -1 { return Err(Error::trap(INTEGER_OVERFLOW)); }
+                        overwrite!(WasmValue::[<from_ $int_type>](a / b));
+                    }
                 }
             }};
         }
@@ -944,8 +1576,9 @@ impl Instance {
             ($uint_type:ident) => {{
                 paste! {
                     let (a, b) = peek_two!($uint_type);
-                    if b == 0 { return Err(Error::trap(DIVIDE_BY_ZERO)); }
-                    overwrite!(WasmValue::[<from_ $uint_type>](a / b));
+                    if b == 0 { div_by_zero!($uint_type); } else {
+                        overwrite!(WasmValue::[<from_ $uint_type>](a / b));
+                    }
                 }
             }};
         }
@@ -953,9 +1586,10 @@ impl Instance {
             ($int_type:ident) => {{
                 paste! {
                     let (a, b) = peek_two!($int_type);
-                    if b == 0 { return Err(Error::trap(DIVIDE_BY_ZERO)); }
-                    let result = if a == $int_type::MIN && b == -1 { 0 } else { a % b };
-                    overwrite!(WasmValue::[<from_ $int_type>](result));
+                    if b == 0 { div_by_zero!($int_type); } else {
+                        let result = if a == $int_type::MIN && b == -1 { 0 } else { a % b };
+                        overwrite!(WasmValue::[<from_ $int_type>](result));
+                    }
                 }
             }};
         }
@@ -963,8 +1597,9 @@ impl Instance {
             ($uint_type:ident) => {{
                 paste! {
                     let (a, b) = peek_two!($uint_type);
-                    if b == 0 { return Err(Error::trap(DIVIDE_BY_ZERO)); }
-                    overwrite!(WasmValue::[<from_ $uint_type>](a % b));
+                    if b == 0 { div_by_zero!($uint_type); } else {
+                        overwrite!(WasmValue::[<from_ $uint_type>](a % b));
+                    }
                 }
             }};
         }
@@ -988,16 +1623,31 @@ impl Instance {
         }}}
 
         loop {
+            if metered {
+                match self.fuel.get() {
+                    Some(0) => return Err(Error::trap(OUT_OF_FUEL)),
+                    fuel => self.fuel.set(fuel.map(|f| f - 1)),
+                }
+            }
+            if let Some(flag) = &cancel_flag {
+                until_cancel_check -= 1;
+                if until_cancel_check == 0 {
+                    until_cancel_check = Self::CANCEL_CHECK_INTERVAL;
+                    if flag.load(Ordering::Relaxed) {
+                        return Err(Error::trap(CANCELLED));
+                    }
+                }
+            }
             match next_op!() {
                 OP_UNREACHABLE => return Err(Error::trap(UNREACHABLE)),
                 // nop and reinterprets (no-op on raw bits)
                 NOP | I32_REINTERPRET_F32 | I64_REINTERPRET_F64 | F32_REINTERPRET_I32 | F64_REINTERPRET_I64 => {}
                 BLOCK => {
                     let (body_pc, end_pc, _else_pc, params_len, has_result) =
-                        self.module.side_table.lookup(pc).unwrap();
+                        block_entry!();
                     pc = body_pc;
                     control.push(ControlFrame {
-                        stack_len: (stack.len() - (params_len as usize)) as u32,
+                        stack_len: block_base!(params_len),
                         dest_pc: end_pc as u32,
                         arity: has_result as u32,
                         has_result: has_result as u32,
@@ -1006,10 +1656,10 @@ impl Instance {
                 LOOP => {
                     let loop_op_pc = pc - 1;
                     let (body_pc, _end_pc, _else_pc, params_len, has_result) =
-                        self.module.side_table.lookup(pc).unwrap();
+                        block_entry!();
                     pc = body_pc;
                     control.push(ControlFrame {
-                        stack_len: (stack.len() - (params_len as usize)) as u32,
+                        stack_len: block_base!(params_len),
                         dest_pc: loop_op_pc as u32,
                         arity: params_len as u32,
                         has_result: has_result as u32,
@@ -1017,10 +1667,10 @@ impl Instance {
                 }
                 IF => {
                     let (body_pc, end_pc, else_pc, params_len, has_result) =
-                        self.module.side_table.lookup(pc).unwrap();
+                        block_entry!();
                     let cond = pop_val!().as_u32();
                     control.push(ControlFrame {
-                        stack_len: (stack.len() - (params_len as usize)) as u32,
+                        stack_len: block_base!(params_len),
                         dest_pc: end_pc as u32,
                         arity: has_result as u32,
                         has_result: has_result as u32,
@@ -1039,7 +1689,7 @@ impl Instance {
                                 return Ok(());
                             }
                             call_frames.pop();
-                            current_base = call_frames.last().unwrap().stack_base;
+                            current_base = frame!().stack_base;
                             continue; // Skip the regular block logic
                         }
                     }
@@ -1048,9 +1698,9 @@ impl Instance {
                     if let Some(target) = control.pop() {
                         let sl = target.stack_len as usize;
                         if target.has_result != 0 {
-                            let result = stack[stack.len() - 1];
-                            stack.truncate(sl + 1);
-                            stack[sl] = result;
+                            let result = pop_val!();
+                            stack.truncate(sl);
+                            stack.push(result);
                         } else {
                             stack.truncate(sl);
                         }
@@ -1069,19 +1719,21 @@ impl Instance {
                 }
                 BR_TABLE => {
                     let v = pop_val!().as_u32();
-                    let depth = self.module.side_table.lookup_br_table(pc, v).unwrap();
+                    let Some(depth) = self.module.side_table.lookup_br_table(pc, v) else {
+                        return Err(Error::trap(MISSING_SIDE_TABLE_ENTRY));
+                    };
                     if Instance::branch(&mut pc, stack, control, depth) { return Ok(()); }
                 }
                 RETURN => {
                     if control.is_empty() { return Ok(()); }
-                    let base_idx = call_frames.last().unwrap().ctrl_index;
+                    let base_idx = frame!().ctrl_index;
                     let depth = (control.len() - 1).saturating_sub(base_idx) as u32;
                     if Instance::branch(&mut pc, stack, control, depth) {
                         call_frames.pop();
                         return Ok(());
                     }
                     call_frames.pop();
-                    current_base = call_frames.last().unwrap().stack_base;
+                    current_base = frame!().stack_base;
                 }
                 // Call instructions
                 CALL => {
@@ -1092,16 +1744,16 @@ impl Instance {
                     let f = &self.functions[fi as usize];
 
                     match f {
-                        RuntimeFunction::OwnedWasm { runtime_sig, pc_start, locals_count } => {
+                        RuntimeFunction::OwnedWasm { runtime_sig, pc_start, locals_count, .. } => {
                             pc = Self::setup_wasm_function_call(*runtime_sig, *pc_start, *locals_count, stack, control, call_frames, pc)?;
-                            current_base = call_frames.last().unwrap().stack_base;
+                            current_base = frame!().stack_base;
                         }
-                        RuntimeFunction::ImportedWasm { owner, function_index, runtime_sig } => {
-                            let owner_rc = owner.upgrade().ok_or(Error::trap(FUNC_NO_IMPL))?;
+                        RuntimeFunction::ImportedWasm { owner, function_index, runtime_sig, .. } => {
+                            let owner_rc = owner.upgrade().ok_or_else(|| self.dangling_import(Some(fi as usize), *function_index))?;
                             Self::call_remote(&owner_rc, *function_index, runtime_sig.n_params() as usize, stack)?;
                         }
-                        RuntimeFunction::Host { callback, runtime_sig } => {
-                            Self::call_host(callback.as_ref(), *runtime_sig, stack);
+                        RuntimeFunction::Host { callback, sig, .. } => {
+                            self.call_host(callback, sig, stack)?;
                         }
                     }
                 }
@@ -1109,7 +1761,7 @@ impl Instance {
                     // Indirect calls must enforce params at runtime
                     // Here we must parse the indices
                     let type_idx: u32 = read_leb128(bytes, &mut pc)?;
-                    pc += 1; // Skip the zero flag
+                    next_op!(); // Skip the zero flag
                     let elem_idx = pop_val!().as_u32();
                     let table_rc = match tab {
                         Some(t) => t,
@@ -1133,46 +1785,45 @@ impl Instance {
                         return Err(Error::trap(FUNC_NO_IMPL));
                     }
                     let func_idx = (low - 1) as usize;
-                    let expected = RuntimeSignature::from_signature(&self.module.types[type_idx as usize]);
+                    // Declared types are compared whole, the packed runtime signature loses param order.
+                    // Shared Rcs make the common same-module case a pointer comparison
+                    let expected = &self.module.types[type_idx as usize];
 
                     if owner_id != self.id {
-                        let mut result: Option<Result<(), Error>> = None;
-                        let mut sig_ok = false;
-                        InstanceManager::with(|mgr| {
-                            if let Some(owner) = mgr.get_instance(owner_id) {
-                                let callee = &owner.functions[func_idx];
-                                sig_ok = callee.signature() == expected;
-                                if sig_ok {
-                                    result = Some(Self::call_remote(&owner, func_idx, callee.param_count(), stack));
-                                }
-                            }
-                        });
-                        if !sig_ok {
+                        // Look the owner up first so the store isn't borrowed during the call
+                        let owner = self.store.with(|mgr| mgr.get_instance(owner_id));
+                        let Some(owner) = owner else {
+                            return Err(Error::trap(INDIRECT_CALL_MISMATCH));
+                        };
+                        let Some(callee) = owner.functions.get(func_idx) else {
+                            return Err(Error::trap(UNDEF_ELEM));
+                        };
+                        if &owner.module.functions[func_idx].ty != expected {
                             return Err(Error::trap(INDIRECT_CALL_MISMATCH));
                         }
-                        match result {
-                            Some(Ok(())) => continue,
-                            Some(Err(e)) => return Err(e),
-                            None => return Err(Error::trap(FUNC_NO_IMPL)),
-                        }
+                        // A trap in the callee is returned as is, not reported as a mismatch
+                        Self::call_remote(&owner, func_idx, callee.param_count(), stack)?;
+                        continue;
                     }
 
-                    let callee = &self.functions[func_idx];
-                    if callee.signature() != expected {
+                    let Some(callee) = self.functions.get(func_idx) else {
+                        return Err(Error::trap(UNDEF_ELEM));
+                    };
+                    if &self.module.functions[func_idx].ty != expected {
                         return Err(Error::trap(INDIRECT_CALL_MISMATCH));
                     }
 
                     match callee {
-                        RuntimeFunction::ImportedWasm { runtime_sig, owner, function_index } => {
-                            let owner_rc = owner.upgrade().ok_or(Error::trap(FUNC_NO_IMPL))?;
+                        RuntimeFunction::ImportedWasm { runtime_sig, owner, function_index, .. } => {
+                            let owner_rc = owner.upgrade().ok_or_else(|| self.dangling_import(Some(func_idx), *function_index))?;
                             Self::call_remote(&owner_rc, *function_index, runtime_sig.n_params() as usize, stack)?;
                         }
-                        RuntimeFunction::OwnedWasm { runtime_sig, pc_start, locals_count } => {
+                        RuntimeFunction::OwnedWasm { runtime_sig, pc_start, locals_count, .. } => {
                             pc = Self::setup_wasm_function_call(*runtime_sig, *pc_start, *locals_count, stack, control, call_frames, pc)?;
-                            current_base = call_frames.last().unwrap().stack_base;
+                            current_base = frame!().stack_base;
                         }
-                        RuntimeFunction::Host { callback, runtime_sig } => {
-                            Self::call_host(callback.as_ref(), *runtime_sig, stack);
+                        RuntimeFunction::Host { callback, sig, .. } => {
+                            self.call_host(callback, sig, stack)?;
                         }
                     }
                 }
@@ -1185,15 +1836,24 @@ impl Instance {
                     let v1 = pop_val!();
                     stack.push(if cond != 0 { v1 } else { v2 });
                 }
+                SELECT_T => {
+                    let n_types: u32 = read_leb128(bytes, &mut pc)?;
+                    pc += n_types as usize; // Value types are single bytes
+                    if pc > bytes.len() { return Err(Error::malformed(UNEXPECTED_END)); }
+                    let cond = pop_val!().as_u32();
+                    let v2 = pop_val!();
+                    let v1 = pop_val!();
+                    stack.push(if cond != 0 { v1 } else { v2 });
+                }
                 LOCAL_GET => {
                     let local: u32 = read_leb128(bytes, &mut pc)?;
-                    let i = current_base + local as usize;
+                    let i = local_slot!(local);
                     stack.push(stack[i]);
                 }
                 LOCAL_SET => {
                     let local: u32 = read_leb128(bytes, &mut pc)?;
                     let val = pop_val!();
-                    let i = current_base + local as usize;
+                    let i = local_slot!(local);
                     stack[i] = val;
                 }
                 LOCAL_TEE => {
@@ -1202,7 +1862,7 @@ impl Instance {
                         Some(v) => *v,
                         None => return Err(Error::trap(STACK_UNDERFLOW))
                     };
-                    let i = current_base + local as usize;
+                    let i = local_slot!(local);
                     stack[i] = val;
                 }
                 GLOBAL_GET => {
@@ -1240,16 +1900,29 @@ impl Instance {
                 I64_STORE8 => { store!(store_u8,  |w: WasmValue| (w.as_u64() & 0xFF) as u8); }
                 I64_STORE16 => { store!(store_u16, |w: WasmValue| (w.as_u64() & 0xFFFF) as u16); }
                 I64_STORE32 => { store!(store_u32, |w: WasmValue| (w.as_u64() & 0xFFFF_FFFF) as u32); }
+                TABLE_GET => {
+                    read_leb128::<u32>(bytes, &mut pc)?; // Skip table index
+                    let idx = pop_val!().as_u32();
+                    let table = tab.ok_or(Error::validation(UNKNOWN_TABLE))?;
+                    stack.push(table.borrow().get(idx).map_err(Error::trap)?);
+                }
+                TABLE_SET => {
+                    read_leb128::<u32>(bytes, &mut pc)?; // Skip table index
+                    let (value, idx) = (pop_val!(), pop_val!().as_u32());
+                    let table = tab.ok_or(Error::validation(UNKNOWN_TABLE))?;
+                    table.borrow_mut().set(idx, value).map_err(Error::trap)?;
+                }
                 MEMORY_SIZE => {
-                    pc += 1; // Skip zero flag
+                    next_op!(); // Skip zero flag
                     let mem = mem.ok_or(Error::validation(UNKNOWN_MEMORY))?;
                     stack.push(WasmValue::from_u32(mem.borrow().size()));
                 }
                 MEMORY_GROW => {
-                    pc += 1; // Skip zero flag
+                    next_op!(); // Skip zero flag
                     let delta = pop_val!().as_u32();
                     let mem = mem.ok_or(Error::validation(UNKNOWN_MEMORY))?;
                     let old = mem.borrow_mut().grow(delta);
+                    if old != u32::MAX { self.log_memory_grow(old, old + delta); }
                     stack.push(WasmValue::from_u32(old));
                 }
                 I32_CONST => {
@@ -1259,14 +1932,10 @@ impl Instance {
                     stack.push(WasmValue::from_i64(read_sleb128::<i64>(bytes, &mut pc)?));
                 }
                 F32_CONST => {
-                    let bits = unsafe { (bytes.as_ptr().add(pc) as *const u32).read_unaligned() };
-                    stack.push(WasmValue::from_f32_bits(u32::from_le(bits)));
-                    pc += 4;
+                    stack.push(WasmValue::from_f32_bits(u32::from_le_bytes(next_bytes!(4))));
                 }
                 F64_CONST => {
-                    let bits = unsafe { (bytes.as_ptr().add(pc) as *const u64).read_unaligned() };
-                    stack.push(WasmValue::from_f64_bits(u64::from_le(bits)));
-                    pc += 8;
+                    stack.push(WasmValue::from_f64_bits(u64::from_le_bytes(next_bytes!(8))));
                 }
                 I32_EQZ => { unary!(u32, |x: u32| (x == 0) as u32); }
                 I32_EQ => { compare!(u32, ==); }
@@ -1341,34 +2010,34 @@ impl Instance {
                 I64_SHR_U => { shift!(u64, >>); }
                 I64_ROTL => { rotate!(u64, left); }
                 I64_ROTR => { rotate!(u64, right); }
-                F32_ABS => { unary!(f32, |x: f32| x.abs()); }
-                F32_NEG => { unary!(f32, |x: f32| -x); }
-                F32_CEIL => { unary!(f32, |x: f32| x.ceil()); }
-                F32_FLOOR => { unary!(f32, |x: f32| x.floor()); }
-                F32_TRUNC => { unary!(f32, |x: f32| x.trunc()); }
-                F32_NEAREST => { nearest!(f32); }
-                F32_SQRT => { unary!(f32, |x: f32| x.sqrt()); }
-                F32_ADD => { binary!(f32, +); }
-                F32_SUB => { binary!(f32, -); }
-                F32_MUL => { binary!(f32, *); }
-                F32_DIV => { binary!(f32, /); }
-                F32_MIN => { minmax!(f32, min); }
-                F32_MAX => { minmax!(f32, max); }
-                F32_COPYSIGN => { copysign!(f32); }
-                F64_ABS => { unary!(f64, |x: f64| x.abs()); }
-                F64_NEG => { unary!(f64, |x: f64| -x); }
-                F64_CEIL => { unary!(f64, |x: f64| x.ceil()); }
-                F64_FLOOR => { unary!(f64, |x: f64| x.floor()); }
-                F64_TRUNC => { unary!(f64, |x: f64| x.trunc()); }
-                F64_NEAREST => { nearest!(f64); }
-                F64_SQRT => { unary!(f64, |x: f64| x.sqrt()); }
-                F64_ADD => { binary!(f64, +); }
-                F64_SUB => { binary!(f64, -); }
-                F64_MUL => { binary!(f64, *); }
-                F64_DIV => { binary!(f64, /); }
-                F64_MIN => { minmax!(f64, min); }
-                F64_MAX => { minmax!(f64, max); }
-                F64_COPYSIGN => { copysign!(f64); }
+                F32_ABS => { unary!(f32, |x: f32| x.abs()); canonicalize!(f32); }
+                F32_NEG => { unary!(f32, |x: f32| -x); canonicalize!(f32); }
+                F32_CEIL => { unary!(f32, |x: f32| x.ceil()); canonicalize!(f32); }
+                F32_FLOOR => { unary!(f32, |x: f32| x.floor()); canonicalize!(f32); }
+                F32_TRUNC => { unary!(f32, |x: f32| x.trunc()); canonicalize!(f32); }
+                F32_NEAREST => { nearest!(f32); canonicalize!(f32); }
+                F32_SQRT => { unary!(f32, |x: f32| x.sqrt()); canonicalize!(f32); }
+                F32_ADD => { binary!(f32, +); canonicalize!(f32); }
+                F32_SUB => { binary!(f32, -); canonicalize!(f32); }
+                F32_MUL => { binary!(f32, *); canonicalize!(f32); }
+                F32_DIV => { binary!(f32, /); canonicalize!(f32); }
+                F32_MIN => { minmax!(f32, min); canonicalize!(f32); }
+                F32_MAX => { minmax!(f32, max); canonicalize!(f32); }
+                F32_COPYSIGN => { copysign!(f32); canonicalize!(f32); }
+                F64_ABS => { unary!(f64, |x: f64| x.abs()); canonicalize!(f64); }
+                F64_NEG => { unary!(f64, |x: f64| -x); canonicalize!(f64); }
+                F64_CEIL => { unary!(f64, |x: f64| x.ceil()); canonicalize!(f64); }
+                F64_FLOOR => { unary!(f64, |x: f64| x.floor()); canonicalize!(f64); }
+                F64_TRUNC => { unary!(f64, |x: f64| x.trunc()); canonicalize!(f64); }
+                F64_NEAREST => { nearest!(f64); canonicalize!(f64); }
+                F64_SQRT => { unary!(f64, |x: f64| x.sqrt()); canonicalize!(f64); }
+                F64_ADD => { binary!(f64, +); canonicalize!(f64); }
+                F64_SUB => { binary!(f64, -); canonicalize!(f64); }
+                F64_MUL => { binary!(f64, *); canonicalize!(f64); }
+                F64_DIV => { binary!(f64, /); canonicalize!(f64); }
+                F64_MIN => { minmax!(f64, min); canonicalize!(f64); }
+                F64_MAX => { minmax!(f64, max); canonicalize!(f64); }
+                F64_COPYSIGN => { copysign!(f64); canonicalize!(f64); }
                 I32_WRAP_I64 => { convert!(u64 -> u32); }
                 I32_TRUNC_F32_S => { trunc!(f32 -> i32 : -2147483777.0, 2147483648.0); }
                 I32_TRUNC_F32_U => { trunc!(f32 -> u32 : -1.0, 4294967296.0); }
@@ -1380,16 +2049,95 @@ impl Instance {
                 I64_TRUNC_F32_U => { trunc!(f32 -> u64 : -1.0, 18446744073709552000.0); }
                 I64_TRUNC_F64_S => { trunc!(f64 -> i64 : -9223372036854777856.0, 9223372036854776000.0); }
                 I64_TRUNC_F64_U => { trunc!(f64 -> u64 : -1.0, 18446744073709552000.0); }
-                F32_CONVERT_I32_S => { convert!(i32 -> f32); }
-                F32_CONVERT_I32_U => { convert!(u32 -> f32); }
-                F32_CONVERT_I64_S => { convert!(i64 -> f32); }
-                F32_CONVERT_I64_U => { convert!(u64 -> f32); }
-                F32_DEMOTE_F64 => { convert!(f64 -> f32); }
-                F64_CONVERT_I32_S => { convert!(i32 -> f64); }
-                F64_CONVERT_I32_U => { convert!(u32 -> f64); }
-                F64_CONVERT_I64_S => { convert!(i64 -> f64); }
-                F64_CONVERT_I64_U => { convert!(u64 -> f64); }
-                F64_PROMOTE_F32 => { convert!(f32 -> f64); }
+                F32_CONVERT_I32_S => { convert!(i32 -> f32); canonicalize!(f32); }
+                F32_CONVERT_I32_U => { convert!(u32 -> f32); canonicalize!(f32); }
+                F32_CONVERT_I64_S => { convert!(i64 -> f32); canonicalize!(f32); }
+                F32_CONVERT_I64_U => { convert!(u64 -> f32); canonicalize!(f32); }
+                F32_DEMOTE_F64 => { convert!(f64 -> f32); canonicalize!(f32); }
+                F64_CONVERT_I32_S => { convert!(i32 -> f64); canonicalize!(f64); }
+                F64_CONVERT_I32_U => { convert!(u32 -> f64); canonicalize!(f64); }
+                F64_CONVERT_I64_S => { convert!(i64 -> f64); canonicalize!(f64); }
+                F64_CONVERT_I64_U => { convert!(u64 -> f64); canonicalize!(f64); }
+                F64_PROMOTE_F32 => { convert!(f32 -> f64); canonicalize!(f64); }
+                I32_EXTEND8_S => { unary!(i32, |x: i32| x as i8 as i32); }
+                I32_EXTEND16_S => { unary!(i32, |x: i32| x as i16 as i32); }
+                I64_EXTEND8_S => { unary!(i64, |x: i64| x as i8 as i64); }
+                I64_EXTEND16_S => { unary!(i64, |x: i64| x as i16 as i64); }
+                I64_EXTEND32_S => { unary!(i64, |x: i64| x as i32 as i64); }
+                MISC_PREFIX => match read_leb128::<u32>(bytes, &mut pc)? {
+                    // Saturating truncations clamp instead of trapping, as `as` casts do
+                    I32_TRUNC_SAT_F32_S => { convert!(f32 -> i32); }
+                    I32_TRUNC_SAT_F32_U => { convert!(f32 -> u32); }
+                    I32_TRUNC_SAT_F64_S => { convert!(f64 -> i32); }
+                    I32_TRUNC_SAT_F64_U => { convert!(f64 -> u32); }
+                    I64_TRUNC_SAT_F32_S => { convert!(f32 -> i64); }
+                    I64_TRUNC_SAT_F32_U => { convert!(f32 -> u64); }
+                    I64_TRUNC_SAT_F64_S => { convert!(f64 -> i64); }
+                    I64_TRUNC_SAT_F64_U => { convert!(f64 -> u64); }
+                    MEMORY_INIT => {
+                        let data_idx = read_leb128::<u32>(bytes, &mut pc)? as usize;
+                        next_op!(); // Skip zero flag
+                        let (len, src, dst) = (pop_val!().as_u32(), pop_val!().as_u32(), pop_val!().as_u32());
+                        let mem = mem.ok_or(Error::validation(UNKNOWN_MEMORY))?;
+                        let dropped = self.dropped_data.get(data_idx).ok_or(Error::validation(UNKNOWN_DATA_SEGMENT))?;
+                        // A dropped segment reads as empty
+                        let segment = if dropped.get() {
+                            &[][..]
+                        } else {
+                            &self.module.bytes[self.module.data_segments[data_idx].data_range.clone()]
+                        };
+                        mem.borrow_mut().init(dst, segment, src, len).map_err(Error::trap)?;
+                    }
+                    DATA_DROP => {
+                        let data_idx = read_leb128::<u32>(bytes, &mut pc)? as usize;
+                        let dropped = self.dropped_data.get(data_idx).ok_or(Error::validation(UNKNOWN_DATA_SEGMENT))?;
+                        dropped.set(true);
+                    }
+                    MEMORY_COPY => {
+                        pc += 2; // Skip zero flags
+                        let (len, src, dst) = (pop_val!().as_u32(), pop_val!().as_u32(), pop_val!().as_u32());
+                        let mem = mem.ok_or(Error::validation(UNKNOWN_MEMORY))?;
+                        mem.borrow_mut().copy(dst, src, len).map_err(Error::trap)?;
+                    }
+                    TABLE_INIT => {
+                        let elem_idx = read_leb128::<u32>(bytes, &mut pc)? as usize;
+                        read_leb128::<u32>(bytes, &mut pc)?; // Skip table index
+                        let (len, src, dst) = (pop_val!().as_u32(), pop_val!().as_u32(), pop_val!().as_u32());
+                        let table = tab.ok_or(Error::validation(UNKNOWN_TABLE))?;
+                        // A dropped segment is empty
+                        let segment = self.elem_segments.get(elem_idx).ok_or(Error::validation(UNKNOWN_ELEM_SEGMENT))?;
+                        table.borrow_mut().init_refs(dst, &segment.borrow(), src, len).map_err(Error::trap)?;
+                    }
+                    ELEM_DROP => {
+                        let elem_idx = read_leb128::<u32>(bytes, &mut pc)? as usize;
+                        let segment = self.elem_segments.get(elem_idx).ok_or(Error::validation(UNKNOWN_ELEM_SEGMENT))?;
+                        segment.take();
+                    }
+                    TABLE_GROW => {
+                        read_leb128::<u32>(bytes, &mut pc)?; // Skip table index
+                        let (delta, init) = (pop_val!().as_u32(), pop_val!());
+                        let table = tab.ok_or(Error::validation(UNKNOWN_TABLE))?;
+                        stack.push(WasmValue::from_u32(table.borrow_mut().grow(delta, init)));
+                    }
+                    TABLE_SIZE => {
+                        read_leb128::<u32>(bytes, &mut pc)?; // Skip table index
+                        let table = tab.ok_or(Error::validation(UNKNOWN_TABLE))?;
+                        stack.push(WasmValue::from_u32(table.borrow().size()));
+                    }
+                    TABLE_FILL => {
+                        read_leb128::<u32>(bytes, &mut pc)?; // Skip table index
+                        let (len, value, dst) = (pop_val!().as_u32(), pop_val!(), pop_val!().as_u32());
+                        let table = tab.ok_or(Error::validation(UNKNOWN_TABLE))?;
+                        table.borrow_mut().fill(dst, value, len).map_err(Error::trap)?;
+                    }
+                    MEMORY_FILL => {
+                        next_op!(); // Skip zero flag
+                        let (len, val, dst) = (pop_val!().as_u32(), pop_val!().as_u32(), pop_val!().as_u32());
+                        let mem = mem.ok_or(Error::validation(UNKNOWN_MEMORY))?;
+                        mem.borrow_mut().fill(dst, val as u8, len).map_err(Error::trap)?;
+                    }
+                    _ => return Err(Error::malformed(UNKNOWN_INSTRUCTION)),
+                },
                 _ => {
                     return Err(Error::malformed(UNKNOWN_INSTRUCTION));
                 }
@@ -1432,58 +2180,293 @@ impl Instance {
         control.is_empty()
     }
 
+    /// Runs `bytes`, a standalone instruction sequence ending in `end`, against this
+    /// instance's memory, table and globals, returning what it leaves on the stack.
+    /// `inputs` are the expression's locals. Only straight-line code is accepted, no
+    /// blocks, branches or calls, and operand types aren't checked, so the expression
+    /// should come pre-validated.
+    pub fn eval_expr(&self, bytes: &[u8], inputs: &[WasmValue]) -> Result<Vec<WasmValue>, Error> {
+        let arity = self.expr_arity(bytes, inputs.len())?;
+        let mut stack = inputs.to_vec();
+        let mut control =
+            vec![ControlFrame { stack_len: 0, dest_pc: 0, arity, has_result: (arity > 0) as u32 }];
+        let mut call_frames =
+            vec![CallFrame { stack_base: 0, ctrl_index: 0, n_locals: inputs.len() }];
+        self.interpret(bytes, 0, &mut stack, &mut control, &mut call_frames)?;
+        Ok(stack)
+    }
+
+    /// Number of values the straight-line expression `bytes` leaves on the stack, checking
+    /// it ends in its only `end` and never pops more than it pushed.
+    fn expr_arity(&self, bytes: &[u8], n_locals: usize) -> Result<u32, Error> {
+        let mut height = 0usize;
+        for instr in Instructions::new(bytes, &self.module.types, 0, bytes.len()) {
+            let (pops, pushes) = match (instr.opcode, &instr.immediate) {
+                (END, _) if instr.pc + 1 == bytes.len() => return Ok(height as u32),
+                (BLOCK | LOOP | IF | ELSE | END | BR | BR_IF | BR_TABLE | RETURN, _)
+                | (CALL | CALL_INDIRECT, _) => {
+                    return Err(Error::validation(STRAIGHT_LINE_EXPR_REQUIRED))
+                }
+                (LOCAL_GET | LOCAL_SET | LOCAL_TEE, Immediate::Index(idx))
+                    if *idx as usize >= n_locals =>
+                {
+                    return Err(Error::validation(UNKNOWN_LOCAL))
+                }
+                (GLOBAL_GET | GLOBAL_SET, Immediate::Index(idx))
+                    if *idx as usize >= self.globals.len() =>
+                {
+                    return Err(Error::validation(UNKNOWN_GLOBAL))
+                }
+                (GLOBAL_SET, Immediate::Index(idx)) if !self.globals[*idx as usize].mutable => {
+                    return Err(Error::validation(GLOBAL_IS_IMMUTABLE))
+                }
+                (
+                    MISC_PREFIX,
+                    Immediate::Misc { sub: MEMORY_INIT | DATA_DROP, index: Some(idx) },
+                ) if *idx as usize >= self.dropped_data.len() => {
+                    return Err(Error::validation(UNKNOWN_DATA_SEGMENT))
+                }
+                (
+                    MISC_PREFIX,
+                    Immediate::Misc { sub: TABLE_INIT | ELEM_DROP, index: Some(idx) },
+                ) if *idx as usize >= self.elem_segments.len() => {
+                    return Err(Error::validation(UNKNOWN_ELEM_SEGMENT))
+                }
+                (MISC_PREFIX, Immediate::Misc { sub, .. }) => match *sub {
+                    I32_TRUNC_SAT_F32_S..=I64_TRUNC_SAT_F64_U => (1, 1),
+                    MEMORY_INIT | MEMORY_COPY | MEMORY_FILL | TABLE_INIT | TABLE_FILL => (3, 0),
+                    DATA_DROP | ELEM_DROP => (0, 0),
+                    TABLE_GROW => (2, 1),
+                    TABLE_SIZE => (0, 1),
+                    _ => return Err(Error::malformed(ILLEGAL_OP)),
+                },
+                (OP_UNREACHABLE | NOP, _) => (0, 0),
+                (DROP | LOCAL_SET | GLOBAL_SET, _) => (1, 0),
+                (SELECT | SELECT_T, _) => (3, 1),
+                (LOCAL_GET | GLOBAL_GET | MEMORY_SIZE | I32_CONST..=F64_CONST, _) => (0, 1),
+                (TABLE_SET | I32_STORE..=I64_STORE32, _) => (2, 0),
+                (I32_EQ..=I32_GE_U | I64_EQ..=F64_GE, _) => (2, 1),
+                (I32_ADD..=I32_ROTR | I64_ADD..=I64_ROTR, _) => (2, 1),
+                (F32_ADD..=F32_COPYSIGN | F64_ADD..=F64_COPYSIGN, _) => (2, 1),
+                (LOCAL_TEE | TABLE_GET | I32_LOAD..=I64_LOAD32_U | MEMORY_GROW, _) => (1, 1),
+                (I32_EQZ | I64_EQZ | I32_CLZ..=I32_POPCNT | I64_CLZ..=I64_POPCNT, _) => (1, 1),
+                (F32_ABS..=F32_SQRT | F64_ABS..=F64_SQRT | I32_WRAP_I64..=I64_EXTEND32_S, _) => {
+                    (1, 1)
+                }
+                _ => return Err(Error::malformed(ILLEGAL_OP)),
+            };
+            height = height.checked_sub(pops).ok_or(Error::validation(TYPE_MISMATCH))? + pushes;
+        }
+        Err(Error::malformed(END_EXPECTED))
+    }
+
     pub fn invoke(
         &self,
         func: &RuntimeFunction,
         args: &[WasmValue],
     ) -> Result<Vec<WasmValue>, Error> {
+        let mut stack: Vec<WasmValue> = Vec::new();
+        self.invoke_into(func, args, &mut stack)?;
+        Ok(stack)
+    }
+
+    /// Like [`Instance::invoke`], but leaves the results in `out`, reusing its allocation.
+    /// `out` is cleared first and doubles as the operand stack; it is left empty on error.
+    pub fn invoke_into(
+        &self,
+        func: &RuntimeFunction,
+        args: &[WasmValue],
+        out: &mut Vec<WasmValue>,
+    ) -> Result<(), Error> {
+        out.clear();
+        let mut control: Vec<ControlFrame> = Vec::with_capacity(64);
+        let mut call_frames: Vec<CallFrame> = Vec::with_capacity(16);
+        let result = self.invoke_on(func, args, out, &mut control, &mut call_frames);
+        if result.is_err() {
+            out.clear();
+        }
+        result
+    }
+
+    /// Like [`Instance::invoke_into`], but every stack the interpreter needs comes from
+    /// `pool` and keeps its capacity for the next call, so once warmed up repeated
+    /// invocations don't allocate. Host functions and calls into other instances still
+    /// allocate for their own results and frames. The results borrow from `pool`.
+    pub fn invoke_pooled<'p>(
+        &self,
+        func: &RuntimeFunction,
+        args: &[WasmValue],
+        pool: &'p mut ExecPool,
+    ) -> Result<&'p [WasmValue], Error> {
+        let ExecPool { stack, control, call_frames } = pool;
+        stack.clear();
+        control.clear();
+        call_frames.clear();
+        match self.invoke_on(func, args, stack, control, call_frames) {
+            Ok(()) => Ok(stack),
+            Err(e) => {
+                stack.clear();
+                Err(e)
+            }
+        }
+    }
+
+    /// Sized from the entry function's own frame when known, calls it makes may still grow the stack.
+    fn initial_stack_capacity(func: &RuntimeFunction) -> usize {
+        const DEFAULT_CAPACITY: usize = 1024;
+        const HEADROOM: usize = 32;
+        match func {
+            RuntimeFunction::OwnedWasm { max_stack_height, .. } => max_stack_height + HEADROOM,
+            _ => DEFAULT_CAPACITY,
+        }
+    }
+
+    /// Index of the imported function `func` was cloned from, if it is one of this
+    /// instance's imports. Clones share the declared signature's `Rc`.
+    fn import_index(&self, func: &RuntimeFunction) -> Option<usize> {
+        let RuntimeFunction::ImportedWasm { owner, function_index, sig, .. } = func else {
+            return None;
+        };
+        self.functions.iter().position(|f| match f {
+            RuntimeFunction::ImportedWasm { owner: o, function_index: i, sig: s, .. } => {
+                o.ptr_eq(owner) && i == function_index && Rc::ptr_eq(s, sig)
+            }
+            _ => false,
+        })
+    }
+
+    fn invoke_on(
+        &self,
+        func: &RuntimeFunction,
+        args: &[WasmValue],
+        stack: &mut Vec<WasmValue>,
+        control: &mut Vec<ControlFrame>,
+        call_frames: &mut Vec<CallFrame>,
+    ) -> Result<(), Error> {
         let n_params = func.param_count();
         if n_params != args.len() {
             return Err(Error::trap(INVALID_NUM_ARG));
         }
+        // Values are untyped, but 32-bit ones are zero-extended, so high bits mean a 64-bit
+        // value went into a 32-bit parameter
+        let narrow = |ty: &ValType| matches!(ty, ValType::I32 | ValType::F32);
+        if func
+            .param_types()
+            .iter()
+            .zip(args)
+            .any(|(ty, arg)| narrow(ty) && arg.as_u64() >> 32 != 0)
+        {
+            return Err(Error::trap(ARG_TYPE_MISMATCH));
+        }
 
-        let mut stack: Vec<WasmValue> = Vec::with_capacity(1024);
+        stack.reserve(Self::initial_stack_capacity(func));
         stack.extend_from_slice(args);
-        let mut control: Vec<ControlFrame> = Vec::with_capacity(64);
-        let mut call_frames: Vec<CallFrame> = Vec::with_capacity(16);
-        let return_pc: usize = 0;
+        let mut return_pc: usize = 0;
 
-        match func {
-            RuntimeFunction::OwnedWasm { runtime_sig, pc_start, locals_count } => {
-                let pc = Self::setup_wasm_function_call(
+        let result = match func {
+            RuntimeFunction::OwnedWasm { runtime_sig, pc_start, locals_count, .. } => {
+                Self::setup_wasm_function_call(
                     *runtime_sig,
                     *pc_start,
                     *locals_count,
-                    &mut stack,
-                    &mut control,
-                    &mut call_frames,
+                    stack,
+                    control,
+                    call_frames,
                     return_pc,
-                )?;
-                self.interpret(pc, &mut stack, &mut control, &mut call_frames)?;
-            }
-            RuntimeFunction::ImportedWasm { owner, function_index, .. } => {
-                if let Some(owner_rc) = owner.upgrade() {
-                    let mut owned_stack: Vec<WasmValue> = Vec::with_capacity(64);
-                    owned_stack.extend_from_slice(args);
-                    let mut control: Vec<ControlFrame> = Vec::with_capacity(16);
-                    let mut return_pc: usize = 0;
-                    let mut call_frames: Vec<CallFrame> = Vec::with_capacity(8);
-                    owner_rc.call_function_idx(
-                        *function_index,
-                        &mut return_pc,
-                        &mut owned_stack,
-                        &mut control,
-                        &mut call_frames,
-                    )?;
-                    return Ok(owned_stack);
-                } else {
-                    return Err(Error::trap(FUNC_NO_IMPL));
-                }
-            }
-            RuntimeFunction::Host { callback, runtime_sig, .. } => {
-                Self::call_host(callback.as_ref(), *runtime_sig, &mut stack);
+                )
+                .and_then(|pc| self.interpret(&self.module.bytes, pc, stack, control, call_frames))
             }
+            RuntimeFunction::ImportedWasm { owner, function_index, .. } => match owner.upgrade() {
+                Some(owner_rc) => owner_rc.call_function_idx(
+                    *function_index,
+                    &mut return_pc,
+                    stack,
+                    control,
+                    call_frames,
+                ),
+                None => Err(self.dangling_import(self.import_index(func), *function_index)),
+            },
+            RuntimeFunction::Host { callback, sig, .. } => self.call_host(callback, sig, stack),
+        };
+        // Frames are not unwound on error, the trapping one is still on top
+        if let (Err(Error::Trap(_) | Error::HostTrap(_)), Some(n)) =
+            (&result, self.trap_capture.get())
+        {
+            self.snapshot_trap(stack, call_frames, n);
+        }
+        result
+    }
+
+    /// Current value of the global exported as `name`, `None` if there is no such global.
+    pub fn get_global(&self, name: &str) -> Option<WasmValue> {
+        match self.exports.get(name)? {
+            ExportValue::Global(global) => Some(global.value.get()),
+            _ => None,
+        }
+    }
+
+    /// Sets the mutable global exported as `name`. Values are untyped, so like `invoke`'s
+    /// arguments only a 64-bit value for an `i32` or `f32` global can be caught, which
+    /// fails with "argument type mismatch".
+    pub fn set_global(&self, name: &str, value: WasmValue) -> Result<(), Error> {
+        let global = match self.exports.get(name) {
+            Some(ExportValue::Global(global)) => global,
+            Some(_) => return Err(Error::link(INCOMPATIBLE_EXPORT)),
+            None => return Err(Error::link(UNKNOWN_EXPORT)),
+        };
+        if !global.mutable {
+            return Err(Error::link(GLOBAL_IS_IMMUTABLE));
+        }
+        if matches!(global.ty, ValType::I32 | ValType::F32) && value.as_u64() >> 32 != 0 {
+            return Err(Error::trap(ARG_TYPE_MISMATCH));
+        }
+        global.value.set(value);
+        Ok(())
+    }
+
+    /// Copies `len` bytes of this instance's memory starting at `addr`.
+    pub fn memory_read(&self, addr: u32, len: u32) -> Result<Vec<u8>, Error> {
+        let mem = self.memory.as_ref().ok_or(Error::link(UNKNOWN_MEMORY))?;
+        let bytes = mem.borrow().read_bytes(addr, len).map_err(Error::trap)?.to_vec();
+        Ok(bytes)
+    }
+
+    /// Writes `data` into this instance's memory at `addr`, nothing is written if it
+    /// doesn't fit.
+    pub fn memory_write(&self, addr: u32, data: &[u8]) -> Result<(), Error> {
+        let mem = self.memory.as_ref().ok_or(Error::link(UNKNOWN_MEMORY))?;
+        mem.borrow_mut().write_bytes(addr, data).map_err(Error::trap)
+    }
+
+    /// Copies `data` into guest memory through the guest's own allocator: calls the
+    /// `(i32) -> i32` export `alloc_name` with the length, writes `data` at the returned
+    /// pointer and returns `(ptr, len)` ready to be passed as arguments.
+    pub fn pass_bytes(&self, alloc_name: &str, data: &[u8]) -> Result<(u32, u32), Error> {
+        let alloc = match self.exports.get(alloc_name) {
+            Some(ExportValue::Function(f)) => f,
+            Some(_) => return Err(Error::link(INCOMPATIBLE_EXPORT)),
+            None => return Err(Error::link(UNKNOWN_EXPORT)),
+        };
+        let alloc_sig = Signature { params: vec![ValType::I32], result: Some(ValType::I32) };
+        if alloc.signature() != RuntimeSignature::from_signature(&alloc_sig) {
+            return Err(Error::link(INCOMPATIBLE_EXPORT));
+        }
+        let mem = self.memory.as_ref().ok_or(Error::link(UNKNOWN_MEMORY))?;
+        let len = u32::try_from(data.len()).map_err(|_| Error::trap(OOB_MEMORY_ACCESS))?;
+
+        let ptr = self.invoke(alloc, &[WasmValue::from_u32(len)])?[0].as_u32();
+        mem.borrow_mut().write_bytes(ptr, data).map_err(Error::trap)?;
+        Ok((ptr, len))
+    }
+
+    /// Invokes `func` and returns its trap message, panicking if it returns or fails otherwise.
+    #[cfg(feature = "testutil")]
+    #[track_caller]
+    pub fn expect_trap(&self, func: &RuntimeFunction, args: &[WasmValue]) -> &'static str {
+        match self.invoke(func, args) {
+            Err(Error::Trap(msg)) => msg,
+            Err(e) => panic!("expected a trap, got error: {:?}", e),
+            Ok(results) => panic!("expected a trap, returned {} value(s)", results.len()),
         }
-        Ok(stack)
     }
 }
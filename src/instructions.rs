@@ -0,0 +1,177 @@
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+
+use crate::leb128::{safe_read_leb128, safe_read_sleb128};
+use crate::opcodes::*;
+use crate::signature::{val_type_from_byte, Signature, ValType};
+
+// ---------------- Decoded Instructions ----------------
+/// Signature of a block, loop or if, resolved from its value type, void or type index
+/// immediate.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlockType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+impl From<Signature> for BlockType {
+    fn from(sig: Signature) -> Self {
+        Self { results: sig.result_types().to_vec(), params: sig.params }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Immediate {
+    None,
+    BlockType(BlockType),
+    /// Label depth, function, local, global or table index
+    Index(u32),
+    BrTable {
+        targets: Vec<u32>,
+        default: u32,
+    },
+    CallIndirect {
+        type_idx: u32,
+    },
+    ValTypes(Vec<ValType>),
+    MemArg {
+        align: u32,
+        offset: u32,
+    },
+    /// Sub-opcode of a 0xfc prefixed instruction, with the data segment index of
+    /// `memory.init` and `data.drop`, the element segment index of `table.init` and
+    /// `elem.drop` or the table index of other table instructions
+    Misc {
+        sub: u32,
+        index: Option<u32>,
+    },
+    I32(i32),
+    I64(i64),
+    F32(u32),
+    F64(u64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+    /// Absolute offset of the opcode within the module bytes
+    pub pc: usize,
+    pub opcode: u8,
+    pub immediate: Immediate,
+}
+
+/// Decodes instructions from an already validated instruction stream.
+///
+/// Iteration stops at the end of the stream or at the first undecodable instruction.
+pub struct Instructions<'a> {
+    bytes: &'a [u8],
+    /// Type section, for block types given as an index
+    types: &'a [Rc<Signature>],
+    pc: usize,
+    end: usize,
+}
+
+impl<'a> Instructions<'a> {
+    pub fn new(bytes: &'a [u8], types: &'a [Rc<Signature>], start: usize, end: usize) -> Self {
+        Self { bytes, types, pc: start, end: end.min(bytes.len()) }
+    }
+
+    fn fixed<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let raw = self.bytes.get(self.pc..self.pc + N)?.try_into().ok()?;
+        self.pc += N;
+        Some(raw)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        safe_read_leb128(self.bytes, &mut self.pc, 32).ok()
+    }
+
+    fn immediate(&mut self, opcode: u8) -> Option<Immediate> {
+        let bytes = self.bytes;
+        Some(match opcode {
+            BLOCK | LOOP | IF => {
+                Immediate::BlockType(Signature::read(self.types, bytes, &mut self.pc).ok()?.into())
+            }
+            BR | BR_IF | CALL | LOCAL_GET | LOCAL_SET | LOCAL_TEE | GLOBAL_GET | GLOBAL_SET
+            | TABLE_GET | TABLE_SET => Immediate::Index(self.u32()?),
+            BR_TABLE => {
+                let n = self.u32()?;
+                let mut targets = Vec::new();
+                for _ in 0..n {
+                    targets.push(self.u32()?);
+                }
+                Immediate::BrTable { targets, default: self.u32()? }
+            }
+            CALL_INDIRECT => {
+                let type_idx = self.u32()?;
+                self.fixed::<1>()?; // table index
+                Immediate::CallIndirect { type_idx }
+            }
+            SELECT_T => {
+                let n = self.u32()?;
+                let mut types = Vec::new();
+                for _ in 0..n {
+                    types.push(val_type_from_byte(self.fixed::<1>()?[0])?);
+                }
+                Immediate::ValTypes(types)
+            }
+            I32_LOAD..=I64_STORE32 => Immediate::MemArg { align: self.u32()?, offset: self.u32()? },
+            MEMORY_SIZE | MEMORY_GROW => {
+                self.fixed::<1>()?; // memory index
+                Immediate::None
+            }
+            MISC_PREFIX => {
+                let sub = self.u32()?;
+                let index = match sub {
+                    MEMORY_INIT => {
+                        let data_idx = self.u32()?;
+                        self.fixed::<1>()?; // memory index
+                        Some(data_idx)
+                    }
+                    TABLE_INIT => {
+                        let elem_idx = self.u32()?;
+                        self.u32()?; // table index
+                        Some(elem_idx)
+                    }
+                    DATA_DROP | ELEM_DROP | TABLE_GROW | TABLE_SIZE | TABLE_FILL => {
+                        Some(self.u32()?)
+                    }
+                    MEMORY_COPY => {
+                        self.fixed::<2>()?; // memory indices
+                        None
+                    }
+                    MEMORY_FILL => {
+                        self.fixed::<1>()?; // memory index
+                        None
+                    }
+                    _ => None,
+                };
+                Immediate::Misc { sub, index }
+            }
+            I32_CONST => Immediate::I32(safe_read_sleb128(bytes, &mut self.pc, 32).ok()?),
+            I64_CONST => Immediate::I64(safe_read_sleb128(bytes, &mut self.pc, 64).ok()?),
+            F32_CONST => Immediate::F32(u32::from_le_bytes(self.fixed()?)),
+            F64_CONST => Immediate::F64(u64::from_le_bytes(self.fixed()?)),
+            _ => Immediate::None,
+        })
+    }
+}
+
+impl Iterator for Instructions<'_> {
+    type Item = Instruction;
+
+    fn next(&mut self) -> Option<Instruction> {
+        if self.pc >= self.end {
+            return None;
+        }
+        let pc = self.pc;
+        let opcode = self.bytes[pc];
+        self.pc += 1;
+        match self.immediate(opcode) {
+            Some(immediate) => Some(Instruction { pc, opcode, immediate }),
+            None => {
+                self.pc = self.end;
+                None
+            }
+        }
+    }
+}
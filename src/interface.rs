@@ -0,0 +1,134 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::module::{ExternType, ImportRef, Module};
+use crate::signature::{Signature, ValType};
+use crate::HashMap;
+
+/// Type of an import or export as seen from outside the module.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ExternDesc {
+    Func(Signature),
+    Table { min: u32, max: u32 },
+    Memory { min: u32, max: u32 },
+    Global { ty: ValType, mutable: bool },
+}
+
+/// An import named by `(module, field)`, see [`crate::Instance::instantiate_lenient`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ImportDesc {
+    pub module: String,
+    pub field: String,
+    pub desc: ExternDesc,
+}
+
+/// Differences between two modules' imports and exports, see [`Module::interface_diff`].
+///
+/// Exports are named by their export name, imports by `(module, field)`. A name
+/// present on both sides with a different [`ExternDesc`] is listed as changed.
+/// Every list is sorted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InterfaceDiff {
+    pub added_exports: Vec<String>,
+    pub removed_exports: Vec<String>,
+    pub changed_exports: Vec<String>,
+    pub added_imports: Vec<(String, String)>,
+    pub removed_imports: Vec<(String, String)>,
+    pub changed_imports: Vec<(String, String)>,
+}
+
+impl InterfaceDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_exports.is_empty()
+            && self.removed_exports.is_empty()
+            && self.changed_exports.is_empty()
+            && self.added_imports.is_empty()
+            && self.removed_imports.is_empty()
+            && self.changed_imports.is_empty()
+    }
+}
+
+impl Module {
+    /// Compares this module's imports and exports against `other`'s. Items only in
+    /// `other` are reported as added, items only in `self` as removed.
+    pub fn interface_diff(&self, other: &Module) -> InterfaceDiff {
+        let (added_exports, removed_exports, changed_exports) =
+            diff(&self.export_descs(), &other.export_descs());
+        let (added_imports, removed_imports, changed_imports) =
+            diff(&self.import_descs(), &other.import_descs());
+        InterfaceDiff {
+            added_exports,
+            removed_exports,
+            changed_exports,
+            added_imports,
+            removed_imports,
+            changed_imports,
+        }
+    }
+
+    fn export_descs(&self) -> HashMap<String, ExternDesc> {
+        let mut descs = HashMap::new();
+        for (name, export) in &self.exports {
+            let idx = export.idx as usize;
+            let desc = match export.extern_type {
+                ExternType::Func => {
+                    self.functions.get(idx).map(|f| ExternDesc::Func(Signature::clone(&f.ty)))
+                }
+                ExternType::Table => {
+                    self.table.as_ref().map(|t| ExternDesc::Table { min: t.min, max: t.max })
+                }
+                ExternType::Mem => {
+                    self.memory.as_ref().map(|m| ExternDesc::Memory { min: m.min, max: m.max })
+                }
+                ExternType::Global => self
+                    .globals
+                    .get(idx)
+                    .map(|g| ExternDesc::Global { ty: g.ty, mutable: g.is_mutable }),
+            };
+            if let Some(desc) = desc {
+                descs.insert(name.clone(), desc);
+            }
+        }
+        descs
+    }
+
+    pub(crate) fn import_descs(&self) -> HashMap<(String, String), ExternDesc> {
+        let mut descs = HashMap::new();
+        let mut add = |import: &Option<ImportRef>, desc: ExternDesc| {
+            if let Some(import) = import {
+                descs.insert((import.module.clone(), import.field.clone()), desc);
+            }
+        };
+        for f in &self.functions {
+            add(&f.import, ExternDesc::Func(Signature::clone(&f.ty)));
+        }
+        if let Some(t) = &self.table {
+            add(&t.import, ExternDesc::Table { min: t.min, max: t.max });
+        }
+        if let Some(m) = &self.memory {
+            add(&m.import, ExternDesc::Memory { min: m.min, max: m.max });
+        }
+        for g in &self.globals {
+            add(&g.import, ExternDesc::Global { ty: g.ty, mutable: g.is_mutable });
+        }
+        descs
+    }
+}
+
+/// Returns the `(added, removed, changed)` keys going from `old` to `new`, each sorted.
+fn diff<K: Clone + Ord + core::hash::Hash>(
+    old: &HashMap<K, ExternDesc>,
+    new: &HashMap<K, ExternDesc>,
+) -> (Vec<K>, Vec<K>, Vec<K>) {
+    let mut added: Vec<K> = new.keys().filter(|k| !old.contains_key(*k)).cloned().collect();
+    let mut removed: Vec<K> = old.keys().filter(|k| !new.contains_key(*k)).cloned().collect();
+    let mut changed: Vec<K> = old
+        .iter()
+        .filter(|(k, desc)| new.get(*k).is_some_and(|other| other != *desc))
+        .map(|(k, _)| k.clone())
+        .collect();
+    added.sort();
+    removed.sort();
+    changed.sort();
+    (added, removed, changed)
+}
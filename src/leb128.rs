@@ -107,19 +107,19 @@ pub fn read_leb128<T>(bytes: &[u8], pc: &mut usize) -> Result<T, Error>
 where
     T: TryFrom<u64>,
 {
+    // Fast path for validated code: skips encoding checks but never reads past the end
     let mut result: u64 = 0;
     let mut shift: u32 = 0;
-    let mut byte: u8;
-    unsafe {
-        loop {
-            byte = *bytes.get_unchecked(*pc);
-            *pc += 1;
-            result |= ((byte & 0x7f) as u64) << shift;
-            if byte & 0x80 == 0 {
-                return Ok(T::try_from(result).unwrap_unchecked());
-            }
-            shift += 7;
+    loop {
+        let Some(&byte) = bytes.get(*pc) else {
+            return Err(Error::malformed(UNEXPECTED_END));
+        };
+        *pc += 1;
+        result |= ((byte & 0x7f) as u64).checked_shl(shift).unwrap_or(0);
+        if byte & 0x80 == 0 {
+            return T::try_from(result).map_err(|_| Error::malformed(INT_TOO_LARGE));
         }
+        shift += 7;
     }
 }
 
@@ -130,20 +130,19 @@ where
 {
     let mut result: i64 = 0;
     let mut shift: u32 = 0;
-    let mut byte: u8;
-    unsafe {
-        loop {
-            byte = *bytes.get_unchecked(*pc);
-            *pc += 1;
-            result |= ((byte & 0x7f) as i64) << shift;
-            shift += 7;
-            if byte & 0x80 == 0 {
-                break;
-            }
+    let byte = loop {
+        let Some(&byte) = bytes.get(*pc) else {
+            return Err(Error::malformed(UNEXPECTED_END));
+        };
+        *pc += 1;
+        result |= ((byte & 0x7f) as i64).checked_shl(shift).unwrap_or(0);
+        shift += 7;
+        if byte & 0x80 == 0 {
+            break byte;
         }
-        if shift < 64 && (byte & 0x40) != 0 {
-            result |= (!0i64) << shift;
-        }
-        Ok(T::try_from(result).unwrap_unchecked())
+    };
+    if shift < 64 && (byte & 0x40) != 0 {
+        result |= (!0i64) << shift;
     }
+    T::try_from(result).map_err(|_| Error::malformed(INT_TOO_LARGE))
 }
@@ -1,30 +1,59 @@
+#![cfg_attr(not(feature = "std"), no_std)]
 #![allow(unsafe_code)]
+extern crate alloc;
+
 pub mod wasm_memory;
 
+pub mod cache;
+pub mod features;
+pub mod host;
 pub mod instance;
+pub mod instructions;
+pub mod interface;
 #[deny(unsafe_code)]
 pub mod module;
 pub mod signature;
+pub mod store;
+pub mod typed;
 pub mod validator;
 
 // Internal modules
 mod error;
+#[cfg(not(feature = "std"))]
+mod float;
 mod leb128;
 mod opcodes;
 
+// Maps and sets come from std when available, hashbrown otherwise
+#[cfg(not(feature = "std"))]
+pub(crate) use hashbrown::{HashMap, HashSet};
+#[cfg(feature = "std")]
+pub(crate) use std::collections::{HashMap, HashSet};
+
 // Core types
 pub use signature::{Signature, ValType};
 
 // Runtime types
+pub use host::{AbortHost, CollectingHost};
 pub use instance::{
-    ExportValue, Imports, Instance, RuntimeFunction, WasmGlobal, WasmTable, WasmValue,
+    Caller, DanglingImport, DeterminismConfig, ExecPool, ExportValue, Imports, Instance,
+    IntoHostResults, NanKind, RuntimeFunction, TrapState, WasmGlobal, WasmTable, WasmValue,
 };
 pub use signature::RuntimeSignature;
+pub use store::Store;
+pub use typed::{TypedFunc, WasmParams, WasmResults, WasmTy};
 
 // Main API types
-pub use module::Module;
+pub use cache::ModuleCache;
+pub use features::Features;
+pub use instructions::{BlockType, Immediate, Instruction};
+pub use interface::{ExternDesc, ImportDesc, InterfaceDiff};
+pub use module::{
+    CompileOptions, ElementMode, ElementSegment, Module, ModuleMetadata, TrapKind,
+    DEFAULT_MAX_NESTING_DEPTH,
+};
 pub use validator::Validator;
-pub use wasm_memory::WasmMemory;
+pub use wasm_memory::{global_memory_in_use, set_global_memory_limit, WasmMemory};
 
 // Utility types
-pub use error::Error;
+pub use error::{Error, ValidationRule};
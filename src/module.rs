@@ -1,11 +1,21 @@
-use std::collections::HashMap;
-use std::ops::Range;
-use std::rc::Rc;
-
+use alloc::borrow::ToOwned;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::cache::ModuleCache;
 use crate::error::*;
+use crate::features::Features;
+use crate::instructions::{Immediate, Instructions};
+use crate::interface::{ExternDesc, ImportDesc};
 use crate::leb128::*;
+use crate::opcodes::*;
 use crate::signature::*;
 use crate::validator::{v_const, Validator};
+use crate::wasm_memory::WasmMemory;
+use crate::{HashMap, HashSet};
 
 const MAGIC_HEADER: &[u8; 4] = b"\0asm";
 
@@ -46,10 +56,14 @@ impl ExternType {
 #[derive(Clone)]
 pub struct Function {
     pub body: Range<usize>,
-    pub ty: Signature,
+    /// Shared with `Module::types`, functions of the same type point at one signature
+    pub ty: Rc<Signature>,
     pub locals: Vec<ValType>,
     pub import: Option<ImportRef>,
     pub is_declared: bool,
+    /// Value stack slots a call needs at most: params, locals and the deepest operand
+    /// stack, not counting callees. Filled in by validation, 0 for imports.
+    pub max_stack_height: usize,
 }
 
 #[derive(Clone)]
@@ -83,7 +97,37 @@ pub struct Export {
 #[derive(Clone)]
 pub struct DataSegment {
     pub data_range: Range<usize>,
-    pub initializer_offset: usize,
+    /// Offset expression of an active segment, `None` for a passive one
+    pub initializer_offset: Option<usize>,
+}
+
+impl DataSegment {
+    /// Only copied into memory by `memory.init`, never at instantiation.
+    pub fn is_passive(&self) -> bool {
+        self.initializer_offset.is_none()
+    }
+}
+
+/// How an element segment is applied, taken from the segment's flags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ElementMode {
+    /// Copied into `table` at instantiation, at the offset computed by the constant
+    /// expression starting at `offset_expr` in the module bytes
+    Active { table: u32, offset_expr: usize },
+    /// Only copied into a table by `table.init`
+    Passive,
+    /// Never copied, only declares functions as referenceable
+    Declarative,
+}
+
+/// An element segment as [`Module::element_segments_iter`] reports it, whichever of
+/// the eight binary encodings it used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ElementSegment {
+    pub mode: ElementMode,
+    /// Function indices in segment order, `None` for a `ref.null` item
+    pub functions: Vec<Option<u32>>,
 }
 
 #[repr(C)]
@@ -241,11 +285,75 @@ impl SideTable {
     }
 }
 
+// ---------------- Compile Options ----------------
+/// Default for [`CompileOptions::max_nesting_depth`], far beyond what compilers emit
+/// even for large switch lowerings.
+pub const DEFAULT_MAX_NESTING_DEPTH: u32 = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompileOptions {
+    /// Reject any f32/f64 type or opcode, for integer-only (deterministic) embeddings.
+    pub forbid_float: bool,
+    /// Upper bounds on the function (imports included), type, global (imports
+    /// included), data segment and element segment counts, for compiling untrusted
+    /// modules. `None` means unbounded.
+    pub max_functions: Option<u32>,
+    pub max_types: Option<u32>,
+    pub max_globals: Option<u32>,
+    pub max_data_segments: Option<u32>,
+    pub max_element_segments: Option<u32>,
+    /// Skip a custom section whose name is malformed instead of rejecting the module.
+    /// The section's declared length must still fit in the module.
+    pub lenient_custom_sections: bool,
+    /// Non-standard: reject a type section listing the same signature twice.
+    pub require_canonical_types: bool,
+    /// Proposals whose instructions are accepted, all implemented ones by default.
+    pub features: Features,
+    /// Deepest block/loop/if nesting accepted within a function body, the body
+    /// itself not counted. Bounds the validator's and interpreter's control stacks.
+    pub max_nesting_depth: u32,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            forbid_float: false,
+            max_functions: None,
+            max_types: None,
+            max_globals: None,
+            max_data_segments: None,
+            max_element_segments: None,
+            lenient_custom_sections: false,
+            require_canonical_types: false,
+            features: Features::default(),
+            max_nesting_depth: DEFAULT_MAX_NESTING_DEPTH,
+        }
+    }
+}
+
+/// Class of runtime trap an instruction can raise, see [`Module::potential_traps`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum TrapKind {
+    /// `unreachable`
+    Unreachable,
+    /// Integer division or remainder by zero, and signed division overflow
+    IntegerDivision,
+    /// Non-saturating float to integer truncation of NaN or an out of range value
+    FloatTruncation,
+    /// Loads, stores and bulk memory instructions past the end of memory
+    MemoryAccess,
+    /// Table instructions past the end of the table
+    TableAccess,
+    /// `call_indirect` on an out of range, null or mismatched table entry
+    IndirectCall,
+}
+
 // ---------------- Module Structure ----------------
 #[derive(Default)]
 pub struct Module {
     pub bytes: Rc<Vec<u8>>,
-    pub types: Vec<Signature>,
+    pub types: Vec<Rc<Signature>>,
     pub imports: HashMap<String, HashMap<String, ExternType>>,
     pub table: Option<Table>,
     pub memory: Option<Memory>,
@@ -256,8 +364,28 @@ pub struct Module {
     pub element_count: u32,
     pub functions: Vec<Function>,
     pub n_data: u32,
+    /// Segment count announced by the data count section, if present
+    pub data_count: Option<u32>,
     pub data_segments: Vec<DataSegment>,
     pub side_table: SideTable,
+    pub options: CompileOptions,
+}
+
+/// The declarations of a compiled module without its bytecode, see [`Module::into_metadata`].
+///
+/// Function bodies and global initializers are offsets into the dropped bytes and
+/// can no longer be read; the module has to be compiled again to run it.
+pub struct ModuleMetadata {
+    pub types: Vec<Rc<Signature>>,
+    pub imports: HashMap<String, HashMap<String, ExternType>>,
+    pub table: Option<Table>,
+    pub memory: Option<Memory>,
+    pub globals: Vec<Global>,
+    pub exports: HashMap<String, Export>,
+    pub start: Option<u32>,
+    pub functions: Vec<Function>,
+    pub n_data: u32,
+    pub options: CompileOptions,
 }
 
 impl Module {
@@ -265,16 +393,192 @@ impl Module {
     pub const MAX_LOCALS: usize = 50000;
 
     pub fn compile(bytes: Vec<u8>) -> Result<Self, Error> {
+        Self::compile_with_options(bytes, CompileOptions::default())
+    }
+
+    pub fn compile_with_features(bytes: Vec<u8>, features: Features) -> Result<Self, Error> {
+        Self::compile_with_options(bytes, CompileOptions { features, ..Default::default() })
+    }
+
+    /// Like [`Module::compile`], but served from `cache` when the same bytes were
+    /// compiled through it before.
+    pub fn compile_cached(bytes: Vec<u8>, cache: &mut ModuleCache) -> Result<Rc<Self>, Error> {
+        cache.get_or_compile(bytes, CompileOptions::default())
+    }
+
+    pub fn compile_with_options(bytes: Vec<u8>, options: CompileOptions) -> Result<Self, Error> {
         // Other than bytecode and default start cursor, everything starts as empty/None
         let mut m = Module {
             bytes: Rc::new(bytes),
             side_table: SideTable::default(),
+            options,
             ..Default::default()
         };
         m.initialize()?;
         Ok(m)
     }
 
+    /// Instruction stream of a defined function (after its local declarations, up to
+    /// and including the final `end`). `None` for imported or unknown functions.
+    pub fn function_body_bytes(&self, idx: u32) -> Option<&[u8]> {
+        let func = self.functions.get(idx as usize)?;
+        if func.import.is_some() {
+            return None;
+        }
+        self.bytes.get(func.body.clone())
+    }
+
+    /// Decoded instructions of a defined function. `None` for imported or unknown functions.
+    pub fn instructions(&self, idx: u32) -> Option<Instructions<'_>> {
+        let body = self.function_body_bytes(idx).map(|_| &self.functions[idx as usize].body)?;
+        Some(Instructions::new(&self.bytes, &self.types, body.start, body.end))
+    }
+
+    /// Functions transitively reachable through direct calls from the start function,
+    /// exports and element segments. Anything placed in a table counts as a root since
+    /// `call_indirect` targets aren't tracked.
+    pub fn reachable_functions(&self) -> HashSet<u32> {
+        let mut reachable = HashSet::new();
+        let mut worklist: Vec<u32> = (0..self.functions.len() as u32)
+            .filter(|&idx| self.functions[idx as usize].is_declared)
+            .chain(self.start)
+            .collect();
+        while let Some(idx) = worklist.pop() {
+            if !reachable.insert(idx) {
+                continue;
+            }
+            for instr in self.instructions(idx).into_iter().flatten() {
+                if let (CALL, Immediate::Index(callee)) = (instr.opcode, &instr.immediate) {
+                    worklist.push(*callee);
+                }
+            }
+        }
+        reachable
+    }
+
+    /// Instructions of a defined function that can trap, as absolute pcs in
+    /// instruction order. Call depth and fuel exhaustion are left out since any
+    /// call or loop can hit them. Empty for imported or unknown functions.
+    pub fn potential_traps(&self, func_idx: u32) -> Vec<(usize, TrapKind)> {
+        let mut traps = Vec::new();
+        for instr in self.instructions(func_idx).into_iter().flatten() {
+            let kind = match (instr.opcode, &instr.immediate) {
+                (OP_UNREACHABLE, _) => TrapKind::Unreachable,
+                (I32_DIV_S..=I32_REM_U | I64_DIV_S..=I64_REM_U, _) => TrapKind::IntegerDivision,
+                (I32_TRUNC_F32_S..=I32_TRUNC_F64_U | I64_TRUNC_F32_S..=I64_TRUNC_F64_U, _) => {
+                    TrapKind::FloatTruncation
+                }
+                (I32_LOAD..=I64_STORE32, _) => TrapKind::MemoryAccess,
+                (
+                    MISC_PREFIX,
+                    Immediate::Misc { sub: MEMORY_INIT | MEMORY_COPY | MEMORY_FILL, .. },
+                ) => TrapKind::MemoryAccess,
+                (TABLE_GET | TABLE_SET, _) => TrapKind::TableAccess,
+                (MISC_PREFIX, Immediate::Misc { sub: TABLE_INIT | TABLE_FILL, .. }) => {
+                    TrapKind::TableAccess
+                }
+                (CALL_INDIRECT, _) => TrapKind::IndirectCall,
+                _ => continue,
+            };
+            traps.push((instr.pc, kind));
+        }
+        traps
+    }
+
+    /// Imported functions the exported function `export_name` can reach through
+    /// the static call graph. A reachable `call_indirect` conservatively pulls in
+    /// every function placed in the table by an element segment. Empty when
+    /// `export_name` isn't an exported function.
+    pub fn export_import_dependencies(&self, export_name: &str) -> HashSet<ImportDesc> {
+        let mut deps = HashSet::new();
+        let Some(export) = self.exports.get(export_name) else { return deps };
+        if !matches!(export.extern_type, ExternType::Func) {
+            return deps;
+        }
+        let mut visited = HashSet::new();
+        let mut worklist = vec![export.idx];
+        let mut table_added = false;
+        while let Some(idx) = worklist.pop() {
+            if !visited.insert(idx) {
+                continue;
+            }
+            let func = &self.functions[idx as usize];
+            if let Some(import) = &func.import {
+                deps.insert(ImportDesc {
+                    module: import.module.clone(),
+                    field: import.field.clone(),
+                    desc: ExternDesc::Func(Signature::clone(&func.ty)),
+                });
+                continue;
+            }
+            for instr in self.instructions(idx).into_iter().flatten() {
+                match (instr.opcode, &instr.immediate) {
+                    (CALL, Immediate::Index(callee)) => worklist.push(*callee),
+                    (CALL_INDIRECT, _) if !table_added => {
+                        table_added = true;
+                        worklist.extend(self.table_functions());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        deps
+    }
+
+    /// Function indices listed by the element segments, in section order.
+    fn table_functions(&self) -> Vec<u32> {
+        self.element_segments_iter().flat_map(|segment| segment.functions).flatten().collect()
+    }
+
+    /// The element segments in section order, with their mode and function indices.
+    pub fn element_segments_iter(&self) -> impl Iterator<Item = ElementSegment> + '_ {
+        let bytes = &self.bytes;
+        let mut it = self.element_start;
+        let mut remaining = self.element_count;
+        core::iter::from_fn(move || {
+            if remaining == 0 {
+                return None;
+            }
+            remaining -= 1;
+            // Segments were validated during parsing, so decoding can't fail here
+            let flags: u32 = read_leb128(bytes, &mut it).ok()?;
+            read_element_segment(flags, bytes, &mut it, &self.globals).ok()
+        })
+    }
+
+    /// Lower bound, in bytes, of what instantiating this module allocates up front:
+    /// the initial pages of a defined memory plus one handle per initial table slot
+    /// and one value per defined global. Imported entities are owned by their
+    /// exporter and are not counted.
+    pub fn estimated_memory_bytes(&self) -> u64 {
+        const SLOT_BYTES: u64 = core::mem::size_of::<u64>() as u64;
+        let mut total = 0u64;
+        if let Some(memory) = self.memory.as_ref().filter(|m| m.import.is_none()) {
+            total += memory.min as u64 * WasmMemory::PAGE_SIZE as u64;
+        }
+        if let Some(table) = self.table.as_ref().filter(|t| t.import.is_none()) {
+            total += table.min as u64 * SLOT_BYTES;
+        }
+        let n_globals = self.globals.iter().filter(|g| g.import.is_none()).count() as u64;
+        total + n_globals * SLOT_BYTES
+    }
+
+    /// Drops the bytecode and side table, keeping only the module's declarations.
+    pub fn into_metadata(self) -> ModuleMetadata {
+        ModuleMetadata {
+            types: self.types,
+            imports: self.imports,
+            table: self.table,
+            memory: self.memory,
+            globals: self.globals,
+            exports: self.exports,
+            start: self.start,
+            functions: self.functions,
+            n_data: self.n_data,
+            options: self.options,
+        }
+    }
+
     fn initialize(&mut self) -> Result<(), Error> {
         // Rc::clone to get a separate handle, avoids borrow conflict with &mut self in closures
         let bytes: &[u8] = &self.bytes.clone();
@@ -296,17 +600,29 @@ impl Module {
         }
         it += 4;
 
-        section(&mut it, bytes, 1, |it: &mut usize| self.parse_type_section(bytes, it))?;
-        section(&mut it, bytes, 2, |it: &mut usize| self.parse_import_section(bytes, it))?;
-        section(&mut it, bytes, 3, |it: &mut usize| self.parse_function_section(bytes, it))?;
-        section(&mut it, bytes, 4, |it: &mut usize| self.parse_table_section(bytes, it))?;
-        section(&mut it, bytes, 5, |it: &mut usize| self.parse_memory_section(bytes, it))?;
-        section(&mut it, bytes, 6, |it: &mut usize| self.parse_global_section(bytes, it))?;
-        section(&mut it, bytes, 7, |it: &mut usize| self.parse_export_section(bytes, it))?;
-        section(&mut it, bytes, 8, |it: &mut usize| self.parse_start_section(bytes, it))?;
-        section(&mut it, bytes, 9, |it: &mut usize| self.parse_element_section(bytes, it))?;
-        section(&mut it, bytes, 10, |it: &mut usize| self.parse_code_section(bytes, it))?;
-        section(&mut it, bytes, 11, |it: &mut usize| self.parse_data_section(bytes, it))?;
+        let lenient = self.options.lenient_custom_sections;
+        section(&mut it, bytes, 1, lenient, |it: &mut usize| self.parse_type_section(bytes, it))?;
+        section(&mut it, bytes, 2, lenient, |it: &mut usize| self.parse_import_section(bytes, it))?;
+        section(&mut it, bytes, 3, lenient, |it: &mut usize| {
+            self.parse_function_section(bytes, it)
+        })?;
+        section(&mut it, bytes, 4, lenient, |it: &mut usize| self.parse_table_section(bytes, it))?;
+        section(&mut it, bytes, 5, lenient, |it: &mut usize| self.parse_memory_section(bytes, it))?;
+        section(&mut it, bytes, 6, lenient, |it: &mut usize| self.parse_global_section(bytes, it))?;
+        section(&mut it, bytes, 7, lenient, |it: &mut usize| self.parse_export_section(bytes, it))?;
+        section(&mut it, bytes, 8, lenient, |it: &mut usize| self.parse_start_section(bytes, it))?;
+        section(&mut it, bytes, 9, lenient, |it: &mut usize| {
+            self.parse_element_section(bytes, it)
+        })?;
+        section(&mut it, bytes, 12, lenient, |it: &mut usize| {
+            self.parse_data_count_section(bytes, it)
+        })?;
+        section(&mut it, bytes, 10, lenient, |it: &mut usize| self.parse_code_section(bytes, it))?;
+        section(&mut it, bytes, 11, lenient, |it: &mut usize| self.parse_data_section(bytes, it))?;
+
+        if self.data_count.is_some_and(|n| n as usize != self.data_segments.len()) {
+            return Err(Error::malformed(DATA_COUNT_MISMATCH));
+        }
 
         // Check that all non-imported functions have code
         for func in &self.functions {
@@ -318,12 +634,32 @@ impl Module {
         if it < bytes.len() {
             return Err(Error::malformed(LENGTH_OUT_OF_BOUNDS));
         }
+
+        // Float opcodes are rejected while validating bodies, types are checked here
+        if self.options.forbid_float {
+            let is_float = |ty: &ValType| matches!(ty, ValType::F32 | ValType::F64);
+            let in_types = self.types.iter().any(|sig| {
+                sig.params.iter().any(is_float) || sig.result.as_ref().is_some_and(is_float)
+            });
+            let in_globals = self.globals.iter().any(|g| is_float(&g.ty));
+            let in_locals = self.functions.iter().any(|f| f.locals.iter().any(is_float));
+            if in_types || in_globals || in_locals {
+                return Err(Error::validation(FLOAT_FORBIDDEN));
+            }
+        }
         Ok(())
     }
 
+    /// Numeric types, and reference types if the reference types proposal is enabled
+    fn is_value_type(&self, byte: u8) -> bool {
+        is_val_type(byte)
+            || is_ref_type(byte) && self.options.features.contains(Features::REFERENCE_TYPES)
+    }
+
     fn parse_type_section(&mut self, bytes: &[u8], it: &mut usize) -> Result<(), Error> {
         let n_types: u32 = safe_read_leb128(bytes, it, 32)?;
-        self.types.reserve_exact(n_types as usize);
+        check_count_limit(n_types as usize, self.options.max_types, TOO_MANY_TYPES)?;
+        self.types.reserve_exact(capped_reserve(n_types, bytes, *it));
 
         for _i in 0..n_types as usize {
             if *it >= bytes.len() {
@@ -336,12 +672,12 @@ impl Module {
 
             let n_params: u32 = safe_read_leb128(bytes, it, 32)?;
             let mut sig = Signature::default();
-            sig.params.reserve_exact(n_params as usize);
+            sig.params.reserve_exact(capped_reserve(n_params, bytes, *it));
 
             for _ in 0..n_params {
                 let ty = read_byte(bytes, it)?;
-                if !is_val_type(ty) {
-                    return Err(Error::malformed(INVALID_VALUE_TYPE));
+                if !self.is_value_type(ty) {
+                    return Err(bad_val_type(ty, Error::malformed(INVALID_VALUE_TYPE)));
                 }
                 sig.params.push(val_type_from_byte(ty).unwrap());
             }
@@ -352,15 +688,21 @@ impl Module {
             }
             if n_results == 1 {
                 let ty = read_byte(bytes, it)?;
-                if !is_val_type(ty) {
-                    return Err(Error::malformed(INVALID_RESULT_TYPE));
+                if !self.is_value_type(ty) {
+                    return Err(bad_val_type(ty, Error::malformed(INVALID_RESULT_TYPE)));
                 }
                 sig.result = Some(val_type_from_byte(ty).unwrap());
             }
 
-            self.types.push(sig);
+            self.types.push(Rc::new(sig));
         }
 
+        if self.options.require_canonical_types {
+            let mut seen = HashSet::new();
+            if !self.types.iter().all(|sig| seen.insert(sig)) {
+                return Err(Error::validation(DUP_TYPE));
+            }
+        }
         Ok(())
     }
 
@@ -379,7 +721,7 @@ impl Module {
             if module_end > bytes.len() {
                 return Err(Error::malformed(UNEXPECTED_END));
             }
-            let module_name = std::str::from_utf8(&bytes[module_start..module_end])
+            let module_name = core::str::from_utf8(&bytes[module_start..module_end])
                 .map_err(|_| Error::malformed(INVALID_UTF8))?
                 .to_owned();
             *it = module_end;
@@ -391,7 +733,7 @@ impl Module {
             if field_end > bytes.len() {
                 return Err(Error::malformed(UNEXPECTED_END));
             }
-            let field_name = std::str::from_utf8(&bytes[field_start..field_end])
+            let field_name = core::str::from_utf8(&bytes[field_start..field_end])
                 .map_err(|_| Error::malformed(INVALID_UTF8))?
                 .to_owned();
             *it = field_end;
@@ -417,6 +759,7 @@ impl Module {
                         locals: vec![],
                         import,
                         is_declared: false,
+                        max_stack_height: 0,
                     });
                 }
                 ExternType::Table => {
@@ -440,8 +783,8 @@ impl Module {
                 }
                 ExternType::Global => {
                     let ty: u32 = safe_read_leb128(bytes, it, 32)?;
-                    if !is_val_type(ty as u8) {
-                        return Err(Error::malformed(INVALID_GLOBAL_TYPE));
+                    if !self.is_value_type(ty as u8) {
+                        return Err(bad_val_type(ty as u8, Error::malformed(INVALID_GLOBAL_TYPE)));
                     }
                     let mut_byte = read_byte(bytes, it)?;
                     let is_mutable = mutability_from_byte(mut_byte)?;
@@ -454,12 +797,16 @@ impl Module {
                 }
             }
         }
+        check_count_limit(self.functions.len(), self.options.max_functions, TOO_MANY_FUNCTIONS)?;
+        check_count_limit(self.globals.len(), self.options.max_globals, TOO_MANY_GLOBALS)?;
         Ok(())
     }
 
     fn parse_function_section(&mut self, bytes: &[u8], it: &mut usize) -> Result<(), Error> {
         let n_functions: u32 = safe_read_leb128(bytes, it, 32)?;
-        self.functions.reserve(n_functions as usize);
+        let total = self.functions.len() + n_functions as usize;
+        check_count_limit(total, self.options.max_functions, TOO_MANY_FUNCTIONS)?;
+        self.functions.reserve(capped_reserve(n_functions, bytes, *it));
 
         for _ in 0..n_functions {
             if *it >= bytes.len() {
@@ -475,6 +822,7 @@ impl Module {
                 locals: vec![],
                 import: None,
                 is_declared: false,
+                max_stack_height: 0,
             });
         }
         Ok(())
@@ -518,25 +866,25 @@ impl Module {
 
     fn parse_global_section(&mut self, bytes: &[u8], it: &mut usize) -> Result<(), Error> {
         let n_globals: u32 = safe_read_leb128(bytes, it, 32)?;
+        let total = self.globals.len() + n_globals as usize;
+        check_count_limit(total, self.options.max_globals, TOO_MANY_GLOBALS)?;
+        self.globals.reserve(capped_reserve(n_globals, bytes, *it));
 
         for _ in 0..n_globals {
             if *it >= bytes.len() {
                 return Err(Error::malformed(UNEXPECTED_END));
             }
             let ty = read_byte(bytes, it)?;
-            if !is_val_type(ty) {
-                return Err(Error::malformed(INVALID_GLOBAL_TYPE));
+            if !self.is_value_type(ty) {
+                return Err(bad_val_type(ty, Error::malformed(INVALID_GLOBAL_TYPE)));
             }
             let mut_byte = read_byte(bytes, it)?;
             let is_mutable = mutability_from_byte(mut_byte)?;
             let initializer_offset = *it;
-            self.globals.push(Global {
-                ty: val_type_from_byte(ty).unwrap(),
-                is_mutable,
-                initializer_offset,
-                import: None,
-            });
-            v_const(bytes, it, val_type_from_byte(ty).unwrap(), &self.globals)?;
+            // Validated before the global is added, so the initializer only sees earlier globals
+            let ty = val_type_from_byte(ty).unwrap();
+            v_const(bytes, it, ty, &self.globals)?;
+            self.globals.push(Global { ty, is_mutable, initializer_offset, import: None });
         }
         Ok(())
     }
@@ -555,7 +903,7 @@ impl Module {
             if name_end > bytes.len() {
                 return Err(Error::malformed(UNEXPECTED_END));
             }
-            let name = std::str::from_utf8(&bytes[name_start..name_end])
+            let name = core::str::from_utf8(&bytes[name_start..name_end])
                 .map_err(|_| Error::malformed(INVALID_UTF8))?
                 .to_owned();
             *it = name_end;
@@ -610,6 +958,11 @@ impl Module {
 
     fn parse_element_section(&mut self, bytes: &[u8], it: &mut usize) -> Result<(), Error> {
         let n_elements: u32 = safe_read_leb128(bytes, it, 32)?;
+        check_count_limit(
+            n_elements as usize,
+            self.options.max_element_segments,
+            TOO_MANY_ELEM_SEGMENTS,
+        )?;
         self.element_start = *it;
         self.element_count = n_elements;
 
@@ -617,18 +970,20 @@ impl Module {
             if *it >= bytes.len() {
                 return Err(Error::malformed(UNEXPECTED_END));
             }
+            // 0 is the MVP encoding, 1-7 add passive, declarative, explicit table and
+            // expression forms
             let flags: u32 = safe_read_leb128(bytes, it, 32)?;
-            if flags != 0 {
+            let bulk_memory = self.options.features.contains(Features::BULK_MEMORY);
+            if flags > 7 || (flags != 0 && !bulk_memory) {
                 return Err(Error::malformed(INVALID_VALUE_TYPE));
             }
-            if self.table.is_none() {
-                return Err(Error::validation(UNKNOWN_TABLE));
+            let segment = read_element_segment(flags, bytes, it, &self.globals)?;
+            if let ElementMode::Active { table, .. } = segment.mode {
+                if table != 0 || self.table.is_none() {
+                    return Err(Error::validation(UNKNOWN_TABLE));
+                }
             }
-            v_const(bytes, it, ValType::I32, &self.globals)?;
-
-            let n_elems: u32 = safe_read_leb128(bytes, it, 32)?;
-            for _ in 0..n_elems {
-                let elem_idx: u32 = safe_read_leb128(bytes, it, 32)?;
+            for elem_idx in segment.functions.into_iter().flatten() {
                 if (elem_idx as usize) >= self.functions.len() {
                     return Err(Error::validation(UNKNOWN_FUNC));
                 }
@@ -665,21 +1020,24 @@ impl Module {
                 n_local_decls -= 1;
                 let n_locals: u32 = safe_read_leb128(bytes, it, 32)?;
                 let ty = read_byte(bytes, it)?;
-                if !is_val_type(ty) {
-                    return Err(Error::validation(INVALID_LOCAL_TYPE));
+                if !self.is_value_type(ty) {
+                    return Err(bad_val_type(ty, Error::validation(INVALID_LOCAL_TYPE)));
                 }
-                for _ in 0..n_locals {
-                    let vt = val_type_from_byte(ty).unwrap();
-                    let function = &mut self.functions[i];
-                    function.locals.push(vt);
-                    if function.locals.len() > Module::MAX_LOCALS {
-                        return Err(Error::malformed(TOO_MANY_LOCALS));
-                    }
+                // Check the whole declaration up front so a huge count can't spin the push loop
+                let function = &mut self.functions[i];
+                if n_locals as usize > Module::MAX_LOCALS.saturating_sub(function.locals.len()) {
+                    return Err(Error::malformed(TOO_MANY_LOCALS));
                 }
+                let vt = val_type_from_byte(ty).unwrap();
+                function.locals.resize(function.locals.len() + n_locals as usize, vt);
             }
 
             let body_start = *it;
-            let body_length = function_length as usize - (body_start - func_start);
+            // Local declarations that run past the declared length leave no room for a body
+            let Some(body_length) = (function_length as usize).checked_sub(body_start - func_start)
+            else {
+                return Err(Error::malformed(SECTION_SIZE_MISMATCH));
+            };
             let body_end_expected = body_start + body_length;
 
             self.functions[i].body = body_start..body_end_expected;
@@ -695,24 +1053,48 @@ impl Module {
         Ok(())
     }
 
+    fn parse_data_count_section(&mut self, bytes: &[u8], it: &mut usize) -> Result<(), Error> {
+        if !self.options.features.contains(Features::BULK_MEMORY) {
+            return Err(Error::malformed(INVALID_SECTION_ID));
+        }
+        self.data_count = Some(safe_read_leb128(bytes, it, 32)?);
+        Ok(())
+    }
+
     fn parse_data_section(&mut self, bytes: &[u8], it: &mut usize) -> Result<(), Error> {
         let n_data_segments: u32 = safe_read_leb128(bytes, it, 32)?;
+        check_count_limit(
+            n_data_segments as usize,
+            self.options.max_data_segments,
+            TOO_MANY_DATA_SEGMENTS,
+        )?;
 
         for _ in 0..n_data_segments {
             if *it >= bytes.len() {
                 return Err(Error::malformed(UNEXPECTED_END));
             }
+            // 0: active in memory 0, 1: passive, 2: active with an explicit memory index
             let segment_flag: u32 = safe_read_leb128(bytes, it, 32)?;
-            if segment_flag != 0 {
-                return Err(Error::validation(INVALID_DATA_SEG_FLAG));
-            }
-            if self.memory.is_none() {
-                return Err(Error::validation(UNKNOWN_MEMORY));
+            let bulk_memory = self.options.features.contains(Features::BULK_MEMORY);
+            let initializer_offset = match segment_flag {
+                0 => Some(*it),
+                1 if bulk_memory => None,
+                2 if bulk_memory => {
+                    let memory_idx: u32 = safe_read_leb128(bytes, it, 32)?;
+                    if memory_idx != 0 {
+                        return Err(Error::validation(UNKNOWN_MEMORY));
+                    }
+                    Some(*it)
+                }
+                _ => return Err(Error::validation(INVALID_DATA_SEG_FLAG)),
+            };
+            if initializer_offset.is_some() {
+                if self.memory.is_none() {
+                    return Err(Error::validation(UNKNOWN_MEMORY));
+                }
+                v_const(bytes, it, ValType::I32, &self.globals)?;
             }
 
-            let initializer_offset = *it;
-            v_const(bytes, it, ValType::I32, &self.globals)?;
-
             let data_length: u32 = safe_read_leb128(bytes, it, 32)?;
             if *it + data_length as usize > bytes.len() {
                 return Err(Error::malformed(UNEXPECTED_END));
@@ -737,7 +1119,7 @@ fn encode_control_sig(params_len: u16, has_result: bool) -> RuntimeSignature {
 }
 
 // ---------------- Helper Functions ----------------
-fn ignore_custom_section(bytes: &[u8], it: &mut usize) -> Result<(), Error> {
+fn ignore_custom_section(bytes: &[u8], it: &mut usize, lenient: bool) -> Result<(), Error> {
     while *it < bytes.len() && peek_byte(bytes, it)? == 0 {
         // Guard: concatenated module (a new "\0asm" at current position)
         if *it + 4 <= bytes.len() {
@@ -752,35 +1134,56 @@ fn ignore_custom_section(bytes: &[u8], it: &mut usize) -> Result<(), Error> {
             return Err(Error::malformed(UNEXPECTED_END));
         }
         let section_start = *it;
+        let section_end = section_start + section_length as usize;
 
-        // Read name length
-        let name_len: u32 = safe_read_leb128(bytes, it, 32)?;
-        if *it + name_len as usize > bytes.len() {
-            return Err(Error::malformed(UNEXPECTED_END));
+        if let Err(e) = check_custom_section_name(bytes, it, section_end) {
+            if !lenient {
+                return Err(e);
+            }
         }
-        let name_start = *it;
-        *it += name_len as usize;
 
-        // Validate UTF-8 encoding
-        if std::str::from_utf8(&bytes[name_start..name_start + name_len as usize]).is_err() {
-            return Err(Error::malformed(INVALID_UTF8));
-        }
+        // Advance to end of section
+        *it = section_end;
+    }
+    Ok(())
+}
 
-        // Ensure we didn't overrun the declared section length
-        if section_start + (section_length as usize) < *it {
-            return Err(Error::malformed(UNEXPECTED_END));
-        }
+fn check_custom_section_name(
+    bytes: &[u8],
+    it: &mut usize,
+    section_end: usize,
+) -> Result<(), Error> {
+    let name_len: u32 = safe_read_leb128(bytes, it, 32)?;
+    if *it + name_len as usize > bytes.len() {
+        return Err(Error::malformed(UNEXPECTED_END));
+    }
+    let name_start = *it;
+    *it += name_len as usize;
 
-        // Advance to end of section
-        *it = section_start + section_length as usize;
+    // Validate UTF-8 encoding
+    if core::str::from_utf8(&bytes[name_start..name_start + name_len as usize]).is_err() {
+        return Err(Error::malformed(INVALID_UTF8));
+    }
+
+    // Ensure we didn't overrun the declared section length
+    if section_end < *it {
+        return Err(Error::malformed(UNEXPECTED_END));
     }
     Ok(())
 }
 
-fn section<F>(it: &mut usize, bytes: &[u8], id: u8, mut reader: F) -> Result<(), Error>
+fn section<F>(
+    it: &mut usize,
+    bytes: &[u8],
+    id: u8,
+    lenient: bool,
+    mut reader: F,
+) -> Result<(), Error>
 where
     F: FnMut(&mut usize) -> Result<(), Error>,
 {
+    // Custom sections may come before and after any other section
+    ignore_custom_section(bytes, it, lenient)?;
     if *it < bytes.len() && peek_byte(bytes, it)? == id {
         *it += 1;
         let section_length: u32 = safe_read_leb128(bytes, it, 32)?;
@@ -795,13 +1198,67 @@ where
         if *it < bytes.len() && peek_byte(bytes, it)? == id {
             return Err(Error::malformed(JUNK_AFTER_LAST));
         }
-    } else if *it < bytes.len() && peek_byte(bytes, it)? > 11 {
+    } else if *it < bytes.len() && peek_byte(bytes, it)? > 12 {
         return Err(Error::malformed(INVALID_SECTION_ID));
     }
-    ignore_custom_section(bytes, it)?;
+    ignore_custom_section(bytes, it, lenient)?;
     Ok(())
 }
 
+/// Decodes the element segment following `flags`. Bit 0 marks a passive or declarative
+/// segment, bit 1 an explicit table index (declarative when bit 0 is set too) and bit 2
+/// items given as expressions instead of function indices. Offset expressions are
+/// type checked, table and function indices are left to the caller.
+fn read_element_segment(
+    flags: u32,
+    bytes: &[u8],
+    it: &mut usize,
+    globals: &[Global],
+) -> Result<ElementSegment, Error> {
+    let expressions = flags & 0b100 != 0;
+    let mode = match flags & 0b011 {
+        0b001 => ElementMode::Passive,
+        0b011 => ElementMode::Declarative,
+        explicit => {
+            let table = if explicit != 0 { safe_read_leb128(bytes, it, 32)? } else { 0 };
+            let offset_expr = *it;
+            v_const(bytes, it, ValType::I32, globals)?;
+            ElementMode::Active { table, offset_expr }
+        }
+    };
+    // Flags 0 and 4 imply funcref, the others spell out the element kind or reference type
+    if flags & 0b011 != 0 {
+        let expected = if expressions { ValType::FuncRef as u8 } else { 0x00 };
+        if read_byte(bytes, it)? != expected {
+            return Err(Error::malformed(MALFORMED_REF_TYPE));
+        }
+    }
+    let n: u32 = safe_read_leb128(bytes, it, 32)?;
+    let mut functions = Vec::with_capacity(capped_reserve(n, bytes, *it));
+    for _ in 0..n {
+        functions.push(if expressions {
+            read_element_expr(bytes, it)?
+        } else {
+            Some(safe_read_leb128(bytes, it, 32)?)
+        });
+    }
+    Ok(ElementSegment { mode, functions })
+}
+
+/// An element segment item given as an expression, `ref.func` or `ref.null func`.
+fn read_element_expr(bytes: &[u8], it: &mut usize) -> Result<Option<u32>, Error> {
+    let item = match read_byte(bytes, it)? {
+        REF_FUNC => Some(safe_read_leb128(bytes, it, 32)?),
+        REF_NULL if read_byte(bytes, it)? == ValType::FuncRef as u8 => None,
+        REF_NULL => return Err(Error::validation(TYPE_MISMATCH)),
+        _ => return Err(Error::validation(CONST_EXP_REQUIRED)),
+    };
+    if read_byte(bytes, it)? != END {
+        return Err(Error::validation(CONST_EXP_REQUIRED));
+    }
+    Ok(item)
+}
+
 fn get_limits(bytes: &[u8], it: &mut usize, upper: u32) -> Result<(u32, u32), Error> {
     let flags: u32 = safe_read_leb128(bytes, it, 1)?;
     let initial: u32 = safe_read_leb128(bytes, it, 32)?;
@@ -829,6 +1286,19 @@ fn get_table_limits(bytes: &[u8], it: &mut usize) -> Result<(u32, u32), Error> {
 }
 
 #[inline]
+fn check_count_limit(count: usize, limit: Option<u32>, msg: &'static str) -> Result<(), Error> {
+    match limit {
+        Some(max) if count > max as usize => Err(Error::malformed(msg)),
+        _ => Ok(()),
+    }
+}
+
+/// Every entry takes at least one byte, so a declared count never needs more slots
+/// than there are bytes left. Keeps bogus counts from driving huge allocations.
+fn capped_reserve(count: u32, bytes: &[u8], it: usize) -> usize {
+    (count as usize).min(bytes.len().saturating_sub(it))
+}
+
 pub(crate) fn read_byte(bytes: &[u8], it: &mut usize) -> Result<u8, Error> {
     if *it >= bytes.len() {
         return Err(Error::malformed(UNEXPECTED_END));
@@ -838,6 +1308,13 @@ pub(crate) fn read_byte(bytes: &[u8], it: &mut usize) -> Result<u8, Error> {
     Ok(b)
 }
 
+/// Reads a fixed-width immediate such as a float constant's bits.
+pub(crate) fn read_array<const N: usize>(bytes: &[u8], it: &mut usize) -> Result<[u8; N], Error> {
+    let raw = bytes.get(*it..*it + N).ok_or(Error::malformed(UNEXPECTED_END))?;
+    *it += N;
+    Ok(raw.try_into().unwrap())
+}
+
 #[inline]
 pub(crate) fn peek_byte(bytes: &[u8], it: &usize) -> Result<u8, Error> {
     if *it >= bytes.len() {
@@ -20,6 +20,7 @@ pub const CALL_INDIRECT: u8 = 0x11;
 // Parametric
 pub const DROP: u8 = 0x1a;
 pub const SELECT: u8 = 0x1b;
+pub const SELECT_T: u8 = 0x1c;
 
 // Variable
 pub const LOCAL_GET: u8 = 0x20;
@@ -28,6 +29,10 @@ pub const LOCAL_TEE: u8 = 0x22;
 pub const GLOBAL_GET: u8 = 0x23;
 pub const GLOBAL_SET: u8 = 0x24;
 
+// Table
+pub const TABLE_GET: u8 = 0x25;
+pub const TABLE_SET: u8 = 0x26;
+
 // Memory load
 pub const I32_LOAD: u8 = 0x28;
 pub const I64_LOAD: u8 = 0x29;
@@ -207,3 +212,57 @@ pub const I32_REINTERPRET_F32: u8 = 0xbc;
 pub const I64_REINTERPRET_F64: u8 = 0xbd;
 pub const F32_REINTERPRET_I32: u8 = 0xbe;
 pub const F64_REINTERPRET_I64: u8 = 0xbf;
+
+// Sign extension
+pub const I32_EXTEND8_S: u8 = 0xc0;
+pub const I32_EXTEND16_S: u8 = 0xc1;
+pub const I64_EXTEND8_S: u8 = 0xc2;
+pub const I64_EXTEND16_S: u8 = 0xc3;
+pub const I64_EXTEND32_S: u8 = 0xc4;
+
+// Reference, only decoded as element segment items
+pub const REF_NULL: u8 = 0xd0;
+pub const REF_FUNC: u8 = 0xd2;
+
+// 0xfc prefixed, followed by a u32 sub-opcode
+pub const MISC_PREFIX: u8 = 0xfc;
+pub const I32_TRUNC_SAT_F32_S: u32 = 0x00;
+pub const I32_TRUNC_SAT_F32_U: u32 = 0x01;
+pub const I32_TRUNC_SAT_F64_S: u32 = 0x02;
+pub const I32_TRUNC_SAT_F64_U: u32 = 0x03;
+pub const I64_TRUNC_SAT_F32_S: u32 = 0x04;
+pub const I64_TRUNC_SAT_F32_U: u32 = 0x05;
+pub const I64_TRUNC_SAT_F64_S: u32 = 0x06;
+pub const I64_TRUNC_SAT_F64_U: u32 = 0x07;
+pub const MEMORY_INIT: u32 = 0x08;
+pub const DATA_DROP: u32 = 0x09;
+pub const MEMORY_COPY: u32 = 0x0a;
+pub const MEMORY_FILL: u32 = 0x0b;
+pub const TABLE_INIT: u32 = 0x0c;
+pub const ELEM_DROP: u32 = 0x0d;
+pub const TABLE_GROW: u32 = 0x0f;
+pub const TABLE_SIZE: u32 = 0x10;
+pub const TABLE_FILL: u32 = 0x11;
+/// One past the highest 0xfc sub-opcode implemented
+pub const MISC_OPCODE_COUNT: usize = 0x12;
+
+// Prefixes of proposals that aren't implemented
+pub const SIMD_PREFIX: u8 = 0xfd;
+pub const THREADS_PREFIX: u8 = 0xfe;
+
+/// Whether a 0xfc sub-opcode reads an f32/f64 value.
+pub fn is_float_misc_op(sub: u32) -> bool {
+    matches!(sub, I32_TRUNC_SAT_F32_S..=I64_TRUNC_SAT_F64_U)
+}
+
+/// Whether an opcode reads, writes or produces an f32/f64 value.
+pub fn is_float_op(op: u8) -> bool {
+    matches!(
+        op,
+        F32_LOAD | F64_LOAD | F32_STORE | F64_STORE | F32_CONST | F64_CONST
+            | 0x5b..=0x66 // float comparisons
+            | 0x8b..=0xa6 // float arithmetic
+            | 0xa8..=0xab // i32.trunc_f*
+            | 0xae..=0xbf // i64.trunc_f*, float conversions and reinterprets
+    )
+}
@@ -1,3 +1,7 @@
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+
 use crate::error::*;
 use crate::leb128::*;
 
@@ -8,14 +12,34 @@ pub enum ValType {
     I64 = 0x7e,
     F32 = 0x7d,
     F64 = 0x7c,
+    FuncRef = 0x70,
+    ExternRef = 0x6f,
     Any = 0xff,
 }
 
+/// SIMD's `v128`, recognised only so modules using it can be reported as unsupported.
+pub const V128: u8 = 0x7b;
+
+/// The error for a byte that isn't a value type, `otherwise` unless it names `v128`.
+#[cold]
+pub fn bad_val_type(byte: u8, otherwise: Error) -> Error {
+    if byte == V128 {
+        Error::malformed(UNSUPPORTED_FEATURE)
+    } else {
+        otherwise
+    }
+}
+
 #[inline(always)]
 pub fn is_val_type(byte: u8) -> bool {
     matches!(byte, 0x7c..=0x7f)
 }
 
+#[inline(always)]
+pub fn is_ref_type(byte: u8) -> bool {
+    matches!(byte, 0x6f | 0x70)
+}
+
 #[inline]
 pub fn val_type_from_byte(byte: u8) -> Option<ValType> {
     match byte {
@@ -23,19 +47,25 @@ pub fn val_type_from_byte(byte: u8) -> Option<ValType> {
         0x7e => Some(ValType::I64),
         0x7d => Some(ValType::F32),
         0x7c => Some(ValType::F64),
+        0x70 => Some(ValType::FuncRef),
+        0x6f => Some(ValType::ExternRef),
         0xff => Some(ValType::Any),
         _ => None,
     }
 }
 
-#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
 pub struct Signature {
     pub params: Vec<ValType>,
     pub result: Option<ValType>,
 }
 
 impl Signature {
-    pub fn read(types: &[Signature], bytes: &[u8], idx: &mut usize) -> Result<Signature, Error> {
+    pub fn read(
+        types: &[Rc<Signature>],
+        bytes: &[u8],
+        idx: &mut usize,
+    ) -> Result<Signature, Error> {
         const VOID: u8 = 0x40;
         if *idx >= bytes.len() {
             return Err(Error::malformed(UNEXPECTED_END));
@@ -52,11 +82,21 @@ impl Signature {
             if n < 0 || (n as usize) >= types.len() {
                 return Err(Error::malformed(INVALID_VALUE_TYPE));
             }
-            Ok(types[n as usize].clone())
+            Ok(Signature::clone(&types[n as usize]))
         }
     }
 }
 
+#[rustfmt::skip]
+impl Signature {
+    /// `(param count, result count)`
+    pub fn arity(&self) -> (usize, usize) { (self.params.len(), self.result_types().len()) }
+    /// Results as a slice, empty or a single type until multi-value results land
+    pub fn result_types(&self) -> &[ValType] { self.result.as_slice() }
+    /// Same params and results, the condition for an import or indirect call to match
+    pub fn matches(&self, other: &Signature) -> bool { self == other }
+}
+
 #[repr(transparent)]
 #[derive(Copy, Clone, Eq, PartialEq, Default)]
 pub struct RuntimeSignature(u32);
@@ -68,6 +108,7 @@ impl RuntimeSignature {
     const HAS_I64:    u32 = 1 << 18;
     const HAS_F32:    u32 = 1 << 19;
     const HAS_F64:    u32 = 1 << 20;
+    const HAS_REF:    u32 = 1 << 21;
 
     #[inline(always)] pub fn n_params(&self) -> u32 { self.0 & 0xFFFF }
     #[inline(always)] pub fn has_result(&self) -> bool { (self.0 & Self::HAS_RESULT) != 0 }
@@ -75,6 +116,7 @@ impl RuntimeSignature {
     #[inline(always)] pub fn has_i64(&self) -> bool { (self.0 & Self::HAS_I64) != 0 }
     #[inline(always)] pub fn has_f32(&self) -> bool { (self.0 & Self::HAS_F32) != 0 }
     #[inline(always)] pub fn has_f64(&self) -> bool { (self.0 & Self::HAS_F64) != 0 }
+    #[inline(always)] pub fn has_ref(&self) -> bool { (self.0 & Self::HAS_REF) != 0 }
 }
 
 impl RuntimeSignature {
@@ -125,6 +167,7 @@ fn set_type_bit32(bits: &mut u32, t: ValType) {
         ValType::I64 => *bits |= RuntimeSignature::HAS_I64,
         ValType::F32 => *bits |= RuntimeSignature::HAS_F32,
         ValType::F64 => *bits |= RuntimeSignature::HAS_F64,
-        _ => {}
+        ValType::FuncRef | ValType::ExternRef => *bits |= RuntimeSignature::HAS_REF,
+        ValType::Any => {}
     }
 }
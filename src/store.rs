@@ -0,0 +1,171 @@
+use alloc::rc::{Rc, Weak};
+use alloc::vec::Vec;
+use core::cell::{RefCell, RefMut};
+
+use crate::error::Error;
+use crate::instance::{Imports, Instance};
+use crate::module::Module;
+use crate::HashMap;
+
+/// Manages instance registry and reference counting
+pub(crate) struct InstanceManager {
+    registry: HashMap<u32, Weak<Instance>>,
+    refcounts: HashMap<u32, usize>,
+    next_id: u32,
+    /// Instances that failed to instantiate but have live funcref references
+    /// These are kept alive until their refcount drops to zero
+    zombie_instances: HashMap<u32, Rc<Instance>>,
+}
+
+impl Default for InstanceManager {
+    fn default() -> Self {
+        Self {
+            registry: HashMap::new(),
+            refcounts: HashMap::new(),
+            next_id: 1,
+            zombie_instances: HashMap::new(),
+        }
+    }
+}
+
+impl InstanceManager {
+    pub(crate) fn allocate_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub(crate) fn register_instance(&mut self, inst: &Rc<Instance>) {
+        self.registry.insert(inst.id, Rc::downgrade(inst));
+    }
+
+    pub(crate) fn get_instance(&self, id: u32) -> Option<Rc<Instance>> {
+        // First check if it's a zombie instance (failed instantiation but has live refs)
+        if let Some(zombie) = self.zombie_instances.get(&id) {
+            return Some(zombie.clone());
+        }
+        // Otherwise check the normal registry
+        self.registry.get(&id).and_then(|w| w.upgrade())
+    }
+
+    fn inc_ref(&mut self, owner_id: u32) {
+        *self.refcounts.entry(owner_id).or_insert(0) += 1;
+    }
+
+    /// Returns the zombie released by this decrement, so the caller can drop it
+    /// once the manager is no longer borrowed
+    fn dec_ref(&mut self, owner_id: u32) -> Option<Rc<Instance>> {
+        if let Some(count) = self.refcounts.get_mut(&owner_id) {
+            if *count > 0 {
+                *count -= 1;
+                // If refcount drops to zero, remove any zombie instance
+                if *count == 0 {
+                    return self.zombie_instances.remove(&owner_id);
+                }
+            }
+        }
+        None
+    }
+
+    /// Ids of registered instances that are still alive, zombies included, in ascending order
+    fn live_ids(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self
+            .registry
+            .iter()
+            .filter(|(_, w)| w.strong_count() > 0)
+            .map(|(id, _)| *id)
+            .chain(self.zombie_instances.keys().copied())
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    fn refcount(&self, owner_id: u32) -> usize {
+        self.refcounts.get(&owner_id).copied().unwrap_or(0)
+    }
+
+    fn has_refs(&self, owner_id: u32) -> bool {
+        self.refcount(owner_id) > 0
+    }
+
+    /// Hands `inst` back if nothing references it, so the caller can drop it (and the
+    /// funcrefs in its table) once the manager is no longer borrowed
+    pub(crate) fn add_zombie(&mut self, inst: Rc<Instance>) -> Option<Rc<Instance>> {
+        if self.has_refs(inst.id) {
+            self.zombie_instances.insert(inst.id, inst);
+            None
+        } else {
+            Some(inst)
+        }
+    }
+}
+
+/// Registry shared by a group of instances so funcrefs stored in tables can be
+/// dispatched across instances. Cloning a `Store` yields another handle to the
+/// same registry; instances and tables only resolve funcrefs from their own store.
+#[derive(Clone, Default)]
+pub struct Store {
+    inner: Rc<RefCell<InstanceManager>>,
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Instantiates `module` in this store, see [`Instance::instantiate`].
+    pub fn instantiate(&self, module: Rc<Module>, imports: &Imports) -> Result<Instance, Error> {
+        Instance::instantiate(self, module, imports)
+    }
+
+    pub(crate) fn with<R>(&self, f: impl FnOnce(&mut InstanceManager) -> R) -> R {
+        f(&mut self.inner.borrow_mut())
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakStore {
+        WeakStore(Rc::downgrade(&self.inner))
+    }
+
+    /// Whether `weak` is a handle to this store's registry.
+    pub(crate) fn is(&self, weak: &WeakStore) -> bool {
+        core::ptr::eq(Rc::as_ptr(&self.inner), weak.0.as_ptr())
+    }
+
+    /// Ids of every registered instance in this store that is still alive,
+    /// including instances kept alive only by funcrefs after a trapping start.
+    pub fn live_instance_ids(&self) -> Vec<u32> {
+        self.with(|mgr| mgr.live_ids())
+    }
+
+    /// Number of live funcref handles (table slots and copies) pointing into instance `id`.
+    pub fn instance_refcount(&self, id: u32) -> usize {
+        self.with(|mgr| mgr.refcount(id))
+    }
+}
+
+/// Non-owning store handle held by funcrefs, so tables never keep a store alive.
+#[derive(Clone, Default)]
+pub(crate) struct WeakStore(Weak<RefCell<InstanceManager>>);
+
+impl WeakStore {
+    pub(crate) fn inc_ref(&self, owner_id: u32) {
+        // The store may already be gone while tables are torn down
+        if let Some(inner) = self.0.upgrade() {
+            Self::borrow(&inner).inc_ref(owner_id);
+        }
+    }
+
+    pub(crate) fn dec_ref(&self, owner_id: u32) {
+        if let Some(inner) = self.0.upgrade() {
+            let released = Self::borrow(&inner).dec_ref(owner_id);
+            drop(released);
+        }
+    }
+
+    /// Skipping the update on a busy store would leave a refcount permanently off, so
+    /// funcrefs must never be cloned or dropped while the store is borrowed.
+    fn borrow(inner: &RefCell<InstanceManager>) -> RefMut<'_, InstanceManager> {
+        inner.try_borrow_mut().expect("funcref refcount updated while its store is borrowed")
+    }
+}
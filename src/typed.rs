@@ -0,0 +1,138 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+use crate::error::*;
+use crate::instance::{ExportValue, Instance, RuntimeFunction, WasmValue};
+use crate::signature::ValType;
+
+/// A Rust type standing for a wasm value type.
+pub trait WasmTy: Copy {
+    const TYPE: ValType;
+    fn into_value(self) -> WasmValue;
+    fn from_value(value: WasmValue) -> Self;
+}
+
+#[rustfmt::skip]
+mod impls {
+    use super::*;
+    impl WasmTy for i32 { const TYPE: ValType = ValType::I32; fn into_value(self) -> WasmValue { WasmValue::from_i32(self) } fn from_value(v: WasmValue) -> Self { v.as_i32() } }
+    impl WasmTy for i64 { const TYPE: ValType = ValType::I64; fn into_value(self) -> WasmValue { WasmValue::from_i64(self) } fn from_value(v: WasmValue) -> Self { v.as_i64() } }
+    impl WasmTy for f32 { const TYPE: ValType = ValType::F32; fn into_value(self) -> WasmValue { WasmValue::from_f32(self) } fn from_value(v: WasmValue) -> Self { v.as_f32() } }
+    impl WasmTy for f64 { const TYPE: ValType = ValType::F64; fn into_value(self) -> WasmValue { WasmValue::from_f64(self) } fn from_value(v: WasmValue) -> Self { v.as_f64() } }
+}
+
+/// Parameters of a typed function: `()`, a single [`WasmTy`] or a tuple of them.
+pub trait WasmParams {
+    fn types() -> Vec<ValType>;
+    fn into_values(self) -> Vec<WasmValue>;
+}
+
+impl WasmParams for () {
+    fn types() -> Vec<ValType> {
+        Vec::new()
+    }
+    fn into_values(self) -> Vec<WasmValue> {
+        Vec::new()
+    }
+}
+
+impl<T: WasmTy> WasmParams for T {
+    fn types() -> Vec<ValType> {
+        vec![T::TYPE]
+    }
+    fn into_values(self) -> Vec<WasmValue> {
+        vec![self.into_value()]
+    }
+}
+
+macro_rules! impl_params_for_tuple {
+    ($($t:ident),+) => {
+        impl<$($t: WasmTy),+> WasmParams for ($($t,)+) {
+            fn types() -> Vec<ValType> {
+                vec![$($t::TYPE),+]
+            }
+            #[allow(non_snake_case)]
+            fn into_values(self) -> Vec<WasmValue> {
+                let ($($t,)+) = self;
+                vec![$($t.into_value()),+]
+            }
+        }
+    };
+}
+
+impl_params_for_tuple!(A);
+impl_params_for_tuple!(A, B);
+impl_params_for_tuple!(A, B, C);
+impl_params_for_tuple!(A, B, C, D);
+impl_params_for_tuple!(A, B, C, D, E);
+impl_params_for_tuple!(A, B, C, D, E, F);
+
+/// Result of a typed function: `()` or a single [`WasmTy`].
+pub trait WasmResults: Sized {
+    fn ty() -> Option<ValType>;
+    /// Fails with "host result mismatch" if `values` holds no result where one is
+    /// expected, which only a host function breaking its signature can cause.
+    fn from_values(values: &[WasmValue]) -> Result<Self, Error>;
+}
+
+impl WasmResults for () {
+    fn ty() -> Option<ValType> {
+        None
+    }
+    fn from_values(_: &[WasmValue]) -> Result<Self, Error> {
+        Ok(())
+    }
+}
+
+impl<T: WasmTy> WasmResults for T {
+    fn ty() -> Option<ValType> {
+        Some(T::TYPE)
+    }
+    fn from_values(values: &[WasmValue]) -> Result<Self, Error> {
+        match values.first() {
+            Some(&value) => Ok(T::from_value(value)),
+            None => Err(Error::trap(HOST_RESULT_MISMATCH)),
+        }
+    }
+}
+
+/// An exported function whose signature was checked against `P` and `R` once, at
+/// lookup, see [`Instance::get_typed_func`].
+pub struct TypedFunc<'a, P, R> {
+    instance: &'a Instance,
+    func: RuntimeFunction,
+    _sig: PhantomData<fn(P) -> R>,
+}
+
+impl<P: WasmParams, R: WasmResults> TypedFunc<'_, P, R> {
+    pub fn call(&self, params: P) -> Result<R, Error> {
+        let results = self.instance.invoke(&self.func, &params.into_values())?;
+        R::from_values(&results)
+    }
+
+    /// The untyped function, e.g. to place it in an [`crate::Imports`] map.
+    pub fn func(&self) -> &RuntimeFunction {
+        &self.func
+    }
+}
+
+impl Instance {
+    /// The exported function `name` as a [`TypedFunc`] taking `P` and returning `R`.
+    /// Fails with "unknown export" if there is no such export and "incompatible export
+    /// type" if it isn't a function of exactly that signature.
+    pub fn get_typed_func<P: WasmParams, R: WasmResults>(
+        &self,
+        name: &str,
+    ) -> Result<TypedFunc<'_, P, R>, Error> {
+        let func = match self.exports.get(name) {
+            Some(ExportValue::Function(f)) => f.clone(),
+            Some(_) => return Err(Error::link(INCOMPATIBLE_EXPORT)),
+            None => return Err(Error::link(UNKNOWN_EXPORT)),
+        };
+        if func.param_types() != P::types() || func.result_type() != R::ty() {
+            return Err(Error::link(INCOMPATIBLE_EXPORT));
+        }
+        Ok(TypedFunc { instance: self, func, _sig: PhantomData })
+    }
+}
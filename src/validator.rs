@@ -1,4 +1,7 @@
+use alloc::vec::Vec;
+
 use crate::error::*;
+use crate::features::Features;
 use crate::leb128::*;
 use crate::module::*;
 use crate::opcodes::*;
@@ -27,11 +30,14 @@ pub struct ControlFrame {
 pub struct Stack {
     val_stack: Vec<ValType>,
     ctrl_stack: Vec<ControlFrame>,
+    max_depth: usize,
 }
 
 #[rustfmt::skip]
 impl Stack {
-    pub fn new() -> Self { Self { val_stack: Vec::with_capacity(1024), ctrl_stack: Vec::with_capacity(64) } }
+    pub fn new(max_depth: u32) -> Self {
+        Self { val_stack: Vec::with_capacity(1024), ctrl_stack: Vec::with_capacity(64), max_depth: max_depth as usize }
+    }
     pub fn size(&self) -> usize { self.val_stack.len() }
     pub fn push_val(&mut self, ty: ValType) { self.val_stack.push(ty); }
     pub fn push_vals(&mut self, types: &[ValType]) { self.val_stack.extend_from_slice(types); }
@@ -42,6 +48,12 @@ impl Stack {
     pub fn pop_frame(&mut self) -> Option<ControlFrame> { self.ctrl_stack.pop() }
 }
 
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_NESTING_DEPTH)
+    }
+}
+
 impl Stack {
     pub fn pop_val(&mut self) -> Result<ValType, Error> {
         if self.ctrl_stack.is_empty() {
@@ -91,6 +103,10 @@ impl Stack {
         control_type: ControlType,
         sig_pc: usize,
     ) -> Result<(), Error> {
+        // The function frame sits below every block, so the length is the new block's depth
+        if self.ctrl_stack.len() > self.max_depth {
+            return Err(Error::malformed(NESTING_TOO_DEEP));
+        }
         let frame = ControlFrame {
             sig: sig.clone(),
             height: self.val_stack.len(),
@@ -151,18 +167,12 @@ pub fn v_const(
             }
             F32_CONST => {
                 // f32.const
-                if *i + 4 > bytes.len() {
-                    return Err(Error::malformed(UNEXPECTED_END));
-                }
-                *i += 4;
+                read_array::<4>(bytes, i)?;
                 stack.push(ValType::F32);
             }
             F64_CONST => {
                 // f64.const
-                if *i + 8 > bytes.len() {
-                    return Err(Error::malformed(UNEXPECTED_END));
-                }
-                *i += 8;
+                read_array::<8>(bytes, i)?;
                 stack.push(ValType::F64);
             }
             I32_ADD..=I32_MUL => {
@@ -174,7 +184,7 @@ pub fn v_const(
                     return Err(Error::validation(TYPE_MISMATCH));
                 }
             }
-            I64_CTZ..=I64_ADD => {
+            I64_ADD..=I64_MUL => {
                 // i64 add, sub, mul
                 if stack.len() < 2
                     || stack.pop().unwrap() != ValType::I64
@@ -183,6 +193,7 @@ pub fn v_const(
                     return Err(Error::validation(TYPE_MISMATCH));
                 }
             }
+            SIMD_PREFIX | THREADS_PREFIX => return Err(Error::malformed(UNSUPPORTED_FEATURE)),
             other => {
                 let is_valid_instruction =
                     get_validators()[other as usize] as *const () != v_missing as *const ();
@@ -215,7 +226,7 @@ impl<'a> Validator<'a> {
         let func = self.module.functions[func_idx].clone();
         let bytes = self.module.bytes.clone();
         let mut i: usize = func.body.start;
-        let mut s = Stack::new();
+        let mut s = Stack::new(self.module.options.max_nesting_depth);
 
         // Push function parameters onto stack first
         s.push_vals(&func.ty.params);
@@ -223,7 +234,7 @@ impl<'a> Validator<'a> {
         // Function frame - special case, doesn't use push_ctrl
         // Height is set after parameters are pushed
         s.push_frame(ControlFrame {
-            sig: func.ty.clone(),
+            sig: Signature::clone(&func.ty),
             height: func.ty.params.len(), // Stack height after params
             unreachable: false,
             control_type: ControlType::Function,
@@ -231,9 +242,17 @@ impl<'a> Validator<'a> {
         });
 
         // Validation loop
+        let mut max_height = s.size();
         loop {
             let opcode = read_byte(&bytes, &mut i)?;
+            if self.module.options.forbid_float && is_float_op(opcode) {
+                return Err(Error::validation(FLOAT_FORBIDDEN));
+            }
+            if !self.module.options.features.contains(Features::for_opcode(opcode)) {
+                return Err(Error::malformed(UNKNOWN_INSTRUCTION));
+            }
             get_validators()[opcode as usize](self.module, &mut i, &func, &mut s)?;
+            max_height = max_height.max(s.size());
             if s.frame_count() == 0 {
                 break;
             }
@@ -246,6 +265,9 @@ impl<'a> Validator<'a> {
         if i != func.body.end {
             return Err(Error::malformed(SECTION_SIZE_MISMATCH));
         }
+        // Params sit at the bottom of the operand stack here, declared locals don't
+        self.module.functions[func_idx].max_stack_height =
+            func.locals.len() + (max_height - func.ty.params.len());
         Ok(())
     }
 }
@@ -257,6 +279,23 @@ fn v_missing(_: &mut Module, _: &mut usize, _: &Function, _: &mut Stack) -> Resu
     Err(Error::malformed(UNKNOWN_INSTRUCTION))
 }
 
+fn v_unsupported(_: &mut Module, _: &mut usize, _: &Function, _: &mut Stack) -> Result<(), Error> {
+    Err(Error::malformed(UNSUPPORTED_FEATURE))
+}
+
+/// Reads the sub-opcode after a 0xfc prefix and dispatches to the secondary table
+fn v_misc(m: &mut Module, i: &mut usize, f: &Function, s: &mut Stack) -> Result<(), Error> {
+    let sub: u32 = safe_read_leb128(&m.bytes, i, 32)?;
+    if m.options.forbid_float && is_float_misc_op(sub) {
+        return Err(Error::validation(FLOAT_FORBIDDEN));
+    }
+    if !m.options.features.contains(Features::for_misc_opcode(sub)) {
+        return Err(Error::malformed(UNKNOWN_INSTRUCTION));
+    }
+    let validator = get_misc_validators().get(sub as usize).copied().unwrap_or(v_missing);
+    validator(m, i, f, s)
+}
+
 // ---------------- Control Flow Validators ----------------
 fn v_unreachable(_: &mut Module, _: &mut usize, _: &Function, s: &mut Stack) -> Result<(), Error> {
     s.unreachable();
@@ -267,9 +306,24 @@ fn v_nop(_: &mut Module, _: &mut usize, _: &Function, _: &mut Stack) -> Result<(
     Ok(())
 }
 
+/// Block types that index the type section belong to the multi-value proposal,
+/// reference result types to the reference types proposal
+fn read_block_type(m: &Module, i: &mut usize) -> Result<Signature, Error> {
+    let features = m.options.features;
+    let byte = m.bytes.get(*i).copied();
+    if byte.is_some_and(is_ref_type) && !features.contains(Features::REFERENCE_TYPES) {
+        return Err(Error::malformed(INVALID_VALUE_TYPE));
+    }
+    let is_index = byte.is_some_and(|b| b != 0x40 && val_type_from_byte(b).is_none());
+    if is_index && !features.contains(Features::MULTI_VALUE) {
+        return Err(Error::malformed(UNKNOWN_INSTRUCTION));
+    }
+    Signature::read(&m.types, &m.bytes, i)
+}
+
 fn v_block(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result<(), Error> {
     let sig_pc = *i;
-    let sig = Signature::read(&m.types, &m.bytes, i)?;
+    let sig = read_block_type(m, i)?;
     let block_start = *i;
     s.pop_vals(&sig.params)?;
     let params_len = sig.params.len() as u16;
@@ -281,7 +335,7 @@ fn v_block(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result
 
 fn v_loop(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result<(), Error> {
     let sig_pc = *i;
-    let sig = Signature::read(&m.types, &m.bytes, i)?;
+    let sig = read_block_type(m, i)?;
     let loop_body_pc = *i; // body starts here
     s.pop_vals(&sig.params)?;
     let params_len = sig.params.len() as u16;
@@ -293,7 +347,7 @@ fn v_loop(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result<
 
 fn v_if(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result<(), Error> {
     let sig_pc = *i;
-    let sig = Signature::read(&m.types, &m.bytes, i)?;
+    let sig = read_block_type(m, i)?;
     s.pop_val_expect(ValType::I32)?;
     s.pop_vals(&sig.params)?;
     let if_body_pc = *i;
@@ -517,7 +571,7 @@ fn v_select(_: &mut Module, _: &mut usize, _: &Function, s: &mut Stack) -> Resul
     let t1 = s.pop_val()?;
     let t2 = s.pop_val()?;
 
-    // For WASM 1.0, only numeric types are allowed
+    // Untyped select only takes numeric operands, references need select t
     if !is_val_type(t1 as u8) && t1 != ValType::Any {
         return Err(Error::validation(TYPE_MISMATCH));
     }
@@ -536,6 +590,23 @@ fn v_select(_: &mut Module, _: &mut usize, _: &Function, s: &mut Stack) -> Resul
     Ok(())
 }
 
+fn v_select_t(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result<(), Error> {
+    let n_types: u32 = safe_read_leb128(&m.bytes, i, 32)?;
+    if n_types != 1 {
+        return Err(Error::validation(INVALID_RESULT_ARITY));
+    }
+    let byte = read_byte(&m.bytes, i)?;
+    if !is_val_type(byte) && !is_ref_type(byte) {
+        return Err(Error::malformed(INVALID_VALUE_TYPE));
+    }
+    let ty = val_type_from_byte(byte).unwrap();
+    s.pop_val_expect(ValType::I32)?;
+    s.pop_val_expect(ty)?;
+    s.pop_val_expect(ty)?;
+    s.push_val(ty);
+    Ok(())
+}
+
 // ---------------- Variable Instructions ----------------
 fn v_local_get(m: &mut Module, i: &mut usize, f: &Function, s: &mut Stack) -> Result<(), Error> {
     let local_idx: u32 = safe_read_leb128(&m.bytes, i, 32)?;
@@ -586,6 +657,48 @@ fn v_global_set(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> R
     Ok(())
 }
 
+// ---------------- Table Instructions ----------------
+/// Table index immediate, only table 0 exists until multiple tables are supported
+fn read_table_idx(m: &Module, i: &mut usize) -> Result<(), Error> {
+    let table_idx: u32 = safe_read_leb128(&m.bytes, i, 32)?;
+    if table_idx != 0 || m.table.is_none() {
+        return Err(Error::validation(UNKNOWN_TABLE));
+    }
+    Ok(())
+}
+
+fn v_table_get(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result<(), Error> {
+    read_table_idx(m, i)?;
+    s.pop_val_expect(ValType::I32)?;
+    s.push_val(ValType::FuncRef);
+    Ok(())
+}
+
+fn v_table_set(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result<(), Error> {
+    read_table_idx(m, i)?;
+    s.pop_vals(&[ValType::I32, ValType::FuncRef])?;
+    Ok(())
+}
+
+fn v_table_grow(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result<(), Error> {
+    read_table_idx(m, i)?;
+    s.pop_vals(&[ValType::FuncRef, ValType::I32])?;
+    s.push_val(ValType::I32);
+    Ok(())
+}
+
+fn v_table_size(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result<(), Error> {
+    read_table_idx(m, i)?;
+    s.push_val(ValType::I32);
+    Ok(())
+}
+
+fn v_table_fill(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result<(), Error> {
+    read_table_idx(m, i)?;
+    s.pop_vals(&[ValType::I32, ValType::FuncRef, ValType::I32])?;
+    Ok(())
+}
+
 // ---------------- Memory Instructions ----------------
 macro_rules! assert_valid_memory {
     ($i:expr, $m:expr) => {
@@ -611,6 +724,66 @@ fn v_memory_grow(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) ->
     Ok(())
 }
 
+/// Data segment index immediate, which needs the data count section to be checked
+/// before the data section itself is read
+fn read_data_idx(m: &Module, i: &mut usize) -> Result<u32, Error> {
+    let data_idx: u32 = safe_read_leb128(&m.bytes, i, 32)?;
+    let count = m.data_count.ok_or(Error::malformed(DATA_COUNT_REQUIRED))?;
+    if data_idx >= count {
+        return Err(Error::validation(UNKNOWN_DATA_SEGMENT));
+    }
+    Ok(data_idx)
+}
+
+fn v_memory_init(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result<(), Error> {
+    read_data_idx(m, i)?;
+    assert_valid_memory!(i, m);
+    s.pop_vals(&[ValType::I32; 3])?;
+    Ok(())
+}
+
+fn v_data_drop(m: &mut Module, i: &mut usize, _: &Function, _: &mut Stack) -> Result<(), Error> {
+    read_data_idx(m, i)?;
+    Ok(())
+}
+
+/// Element segment index immediate, the element section is read before any code
+fn read_elem_idx(m: &Module, i: &mut usize) -> Result<u32, Error> {
+    let elem_idx: u32 = safe_read_leb128(&m.bytes, i, 32)?;
+    if elem_idx >= m.element_count {
+        return Err(Error::validation(UNKNOWN_ELEM_SEGMENT));
+    }
+    Ok(elem_idx)
+}
+
+fn v_table_init(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result<(), Error> {
+    read_elem_idx(m, i)?;
+    let table_idx: u32 = safe_read_leb128(&m.bytes, i, 32)?;
+    if table_idx != 0 || m.table.is_none() {
+        return Err(Error::validation(UNKNOWN_TABLE));
+    }
+    s.pop_vals(&[ValType::I32; 3])?;
+    Ok(())
+}
+
+fn v_elem_drop(m: &mut Module, i: &mut usize, _: &Function, _: &mut Stack) -> Result<(), Error> {
+    read_elem_idx(m, i)?;
+    Ok(())
+}
+
+fn v_memory_copy(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result<(), Error> {
+    assert_valid_memory!(i, m); // destination
+    assert_valid_memory!(i, m); // source
+    s.pop_vals(&[ValType::I32; 3])?;
+    Ok(())
+}
+
+fn v_memory_fill(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result<(), Error> {
+    assert_valid_memory!(i, m);
+    s.pop_vals(&[ValType::I32; 3])?;
+    Ok(())
+}
+
 // ---------------- Constant Instructions ----------------
 fn v_i32const(m: &mut Module, i: &mut usize, _: &Function, s: &mut Stack) -> Result<(), Error> {
     let _val: i32 = safe_read_sleb128(&m.bytes, i, 32)?;
@@ -680,6 +853,22 @@ numeric!(v_i64_f64, &[ValType::I64], &[ValType::F64]);
 numeric!(v_f32_f64, &[ValType::F32], &[ValType::F64]);
 
 // ---------------- Memory Load/Store Operations ----------------
+/// Bit 6 of a memarg's alignment field announces an explicit memory index under
+/// multi-memory, which isn't supported, so it is malformed for loads and stores alike.
+fn read_align_bits(m: &Module, i: &mut usize) -> Result<u32, Error> {
+    let align_bits: u32 = safe_read_leb128(&m.bytes, i, 32)?;
+    if align_bits & (1 << 6) != 0 {
+        return Err(Error::malformed(MALFORMED_MEMOP_FLAGS));
+    }
+    if m.memory.is_none() {
+        return Err(Error::validation(UNKNOWN_MEMORY));
+    }
+    if align_bits >= 32 {
+        return Err(Error::malformed(INT_TOO_LARGE));
+    }
+    Ok(align_bits)
+}
+
 fn v_load(
     m: &mut Module,
     i: &mut usize,
@@ -688,13 +877,7 @@ fn v_load(
     _: &Function,
     s: &mut Stack,
 ) -> Result<(), Error> {
-    let align_bits: u32 = safe_read_leb128(&m.bytes, i, 32)?;
-    if m.memory.is_none() {
-        return Err(Error::validation(UNKNOWN_MEMORY));
-    }
-    if align_bits >= 32 {
-        return Err(Error::malformed(INT_TOO_LARGE));
-    }
+    let align_bits = read_align_bits(m, i)?;
     let _off: u32 = safe_read_leb128(&m.bytes, i, 32)?;
     let align = 1u64 << align_bits;
     if align > natural_align as u64 {
@@ -713,15 +896,7 @@ fn v_store(
     _: &Function,
     s: &mut Stack,
 ) -> Result<(), Error> {
-    let mut align_bits: u32 = safe_read_leb128(&m.bytes, i, 32)?;
-    if (1 << 6) & align_bits != 0 {
-        align_bits = safe_read_leb128(&m.bytes, i, 32)?;
-    } else if m.memory.is_none() {
-        return Err(Error::validation(UNKNOWN_MEMORY));
-    }
-    if align_bits >= 32 {
-        return Err(Error::malformed(INT_TOO_LARGE));
-    }
+    let align_bits = read_align_bits(m, i)?;
     let _off: u32 = safe_read_leb128(&m.bytes, i, 32)?;
     let align = 1u64 << align_bits;
     if align > natural_align as u64 {
@@ -814,10 +989,10 @@ fn v_call_indirect(
 // ---------------- Validator Table ----------------
 #[rustfmt::skip]
 #[allow(clippy::all)]
-fn build_validators_table() -> [ValidatorFn; 256] {
+const fn build_validators_table() -> [ValidatorFn; 256] {
     let mut t: [ValidatorFn; 256] = [v_missing; 256];
     macro_rules! op { ($op:expr, $f:expr) => { t[$op as usize] = $f; }; }
-    macro_rules! ops { ($lo:expr, $hi:expr, $f:expr) => { for i in $lo as usize..=$hi as usize { t[i] = $f; } }; }
+    macro_rules! ops { ($lo:expr, $hi:expr, $f:expr) => { let mut i = $lo as usize; while i <= $hi as usize { t[i] = $f; i += 1; } }; }
 
     op!(OP_UNREACHABLE, v_unreachable); op!(NOP, v_nop);
     op!(BLOCK, v_block);                op!(LOOP, v_loop);
@@ -827,9 +1002,11 @@ fn build_validators_table() -> [ValidatorFn; 256] {
     op!(RETURN, v_return);              op!(CALL, v_call);
     op!(CALL_INDIRECT, v_call_indirect);
     op!(DROP, v_drop);                  op!(SELECT, v_select);
+    op!(SELECT_T, v_select_t);
     op!(LOCAL_GET, v_local_get);        op!(LOCAL_SET, v_local_set);
     op!(LOCAL_TEE, v_local_tee);        op!(GLOBAL_GET, v_global_get);
-    op!(GLOBAL_SET, v_global_set);      op!(MEMORY_SIZE, v_memory_size);
+    op!(GLOBAL_SET, v_global_set);      op!(TABLE_GET, v_table_get);
+    op!(TABLE_SET, v_table_set);        op!(MEMORY_SIZE, v_memory_size);
     op!(MEMORY_GROW, v_memory_grow);
     op!(I32_LOAD, v_i32load);           op!(I64_LOAD, v_i64load);
     op!(F32_LOAD, v_f32load);           op!(F64_LOAD, v_f64load);
@@ -871,11 +1048,37 @@ fn build_validators_table() -> [ValidatorFn; 256] {
     op!(F64_PROMOTE_F32, v_f32_f64);    op!(I32_REINTERPRET_F32, v_f32_i32);
     op!(I64_REINTERPRET_F64, v_f64_i64);op!(F32_REINTERPRET_I32, v_i32_f32);
     op!(F64_REINTERPRET_I64, v_i64_f64);
+    op!(I32_EXTEND8_S, v_i32_i32);      op!(I32_EXTEND16_S, v_i32_i32);
+    op!(I64_EXTEND8_S, v_i64_i64);      op!(I64_EXTEND16_S, v_i64_i64);
+    op!(I64_EXTEND32_S, v_i64_i64);
+    op!(MISC_PREFIX, v_misc);
+    op!(SIMD_PREFIX, v_unsupported);    op!(THREADS_PREFIX, v_unsupported);
     t
 }
 
 fn get_validators() -> &'static [ValidatorFn; 256] {
-    static VALIDATORS: std::sync::LazyLock<Box<[ValidatorFn; 256]>> =
-        std::sync::LazyLock::new(|| Box::new(build_validators_table()));
+    static VALIDATORS: [ValidatorFn; 256] = build_validators_table();
+    &VALIDATORS
+}
+
+#[rustfmt::skip]
+const fn build_misc_validators_table() -> [ValidatorFn; MISC_OPCODE_COUNT] {
+    let mut t: [ValidatorFn; MISC_OPCODE_COUNT] = [v_missing; MISC_OPCODE_COUNT];
+    macro_rules! op { ($op:expr, $f:expr) => { t[$op as usize] = $f; }; }
+
+    op!(I32_TRUNC_SAT_F32_S, v_f32_i32);    op!(I32_TRUNC_SAT_F32_U, v_f32_i32);
+    op!(I32_TRUNC_SAT_F64_S, v_f64_i32);    op!(I32_TRUNC_SAT_F64_U, v_f64_i32);
+    op!(I64_TRUNC_SAT_F32_S, v_f32_i64);    op!(I64_TRUNC_SAT_F32_U, v_f32_i64);
+    op!(I64_TRUNC_SAT_F64_S, v_f64_i64);    op!(I64_TRUNC_SAT_F64_U, v_f64_i64);
+    op!(MEMORY_INIT, v_memory_init);        op!(DATA_DROP, v_data_drop);
+    op!(MEMORY_COPY, v_memory_copy);        op!(MEMORY_FILL, v_memory_fill);
+    op!(TABLE_INIT, v_table_init);          op!(ELEM_DROP, v_elem_drop);
+    op!(TABLE_GROW, v_table_grow);          op!(TABLE_SIZE, v_table_size);
+    op!(TABLE_FILL, v_table_fill);
+    t
+}
+
+fn get_misc_validators() -> &'static [ValidatorFn; MISC_OPCODE_COUNT] {
+    static VALIDATORS: [ValidatorFn; MISC_OPCODE_COUNT] = build_misc_validators_table();
     &VALIDATORS
 }
@@ -1,21 +1,51 @@
-use crate::error::OOB_MEMORY_ACCESS;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::error::{
+    GLOBAL_MEMORY_LIMIT, MEMORY_SIZE_LIMIT, MIN_GREATER_THAN_MAX, OOB_MEMORY_ACCESS,
+    PARTIAL_MEMORY_PAGE, READONLY_MEMORY,
+};
+
+// Process-wide accounting of linear-memory bytes held by every live WasmMemory
+static MEMORY_LIMIT: AtomicU64 = AtomicU64::new(u64::MAX);
+static MEMORY_IN_USE: AtomicU64 = AtomicU64::new(0);
+
+/// Caps the linear-memory bytes all live memories in the process may hold together.
+/// Memories that already exist are not shrunk, only new allocations and growth are refused.
+pub fn set_global_memory_limit(bytes: u64) {
+    MEMORY_LIMIT.store(bytes, Ordering::Relaxed);
+}
+
+/// Linear-memory bytes currently held by all live memories in the process.
+pub fn global_memory_in_use() -> u64 {
+    MEMORY_IN_USE.load(Ordering::Relaxed)
+}
+
+fn try_reserve(bytes: u64) -> bool {
+    MEMORY_IN_USE
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            let total = used.checked_add(bytes)?;
+            (total <= MEMORY_LIMIT.load(Ordering::Relaxed)).then_some(total)
+        })
+        .is_ok()
+}
+
+fn pages_to_bytes(pages: u32) -> u64 {
+    pages as u64 * WasmMemory::PAGE_SIZE as u64
+}
 
 macro_rules! impl_unsigned {
     ($type:ty, $size:literal, $load_name:ident, $store_name:ident) => {
         #[inline(always)]
         pub fn $load_name(&self, ptr: u32, offset: u32) -> Result<$type, &'static str> {
-            let addr = (ptr as usize).checked_add(offset as usize).ok_or(OOB_MEMORY_ACCESS)?;
-            if addr.saturating_add($size) > self.data.len() {
-                return Err(OOB_MEMORY_ACCESS);
-            }
+            let addr = effective_addr(ptr, offset, $size, self.data.len())?;
             unsafe { Ok((self.data.as_ptr().add(addr) as *const $type).read_unaligned()) }
         }
         #[inline(always)]
         pub fn $store_name(&mut self, ptr: u32, offset: u32, v: $type) -> Result<(), &'static str> {
-            let addr = (ptr as usize).checked_add(offset as usize).ok_or(OOB_MEMORY_ACCESS)?;
-            if addr.saturating_add($size) > self.data.len() {
-                return Err(OOB_MEMORY_ACCESS);
-            }
+            self.check_writable()?;
+            let addr = effective_addr(ptr, offset, $size, self.data.len())?;
             unsafe {
                 (self.data.as_mut_ptr().add(addr) as *mut $type).write_unaligned(v);
             }
@@ -24,6 +54,19 @@ macro_rules! impl_unsigned {
     };
 }
 
+/// Computes `ptr + offset` and checks that `size` bytes fit below `len`.
+///
+/// The arithmetic is done in u64 so it cannot wrap on hosts where `usize` is
+/// 32 bits: `ptr + offset + size` is at most `2 * u32::MAX + 8`.
+#[inline(always)]
+fn effective_addr(ptr: u32, offset: u32, size: u64, len: usize) -> Result<usize, &'static str> {
+    let addr = ptr as u64 + offset as u64;
+    if addr + size > len as u64 {
+        return Err(OOB_MEMORY_ACCESS);
+    }
+    Ok(addr as usize)
+}
+
 macro_rules! impl_signed_load {
     ($name:ident, $target:ty, $source:ident) => {
         #[inline(always)]
@@ -37,16 +80,54 @@ pub struct WasmMemory {
     data: Vec<u8>,
     current: u32,
     maximum: u32,
+    readonly: bool,
 }
 
 impl WasmMemory {
     pub const MAX_PAGES: u32 = 65536;
     pub const PAGE_SIZE: u32 = 65536;
 
+    /// Allocates `initial` pages. The bytes count towards the global memory limit but
+    /// are never refused, use [`WasmMemory::try_new`] to respect the limit.
     pub fn new(initial: u32, maximum: u32) -> Self {
+        MEMORY_IN_USE.fetch_add(pages_to_bytes(initial), Ordering::Relaxed);
+        Self::alloc(initial, maximum)
+    }
+
+    /// Like [`WasmMemory::new`], but fails if the allocation would exceed the global memory limit.
+    pub fn try_new(initial: u32, maximum: u32) -> Result<Self, &'static str> {
+        if !try_reserve(pages_to_bytes(initial)) {
+            return Err(GLOBAL_MEMORY_LIMIT);
+        }
+        Ok(Self::alloc(initial, maximum))
+    }
+
+    /// Adopts `data` as the memory's initial contents without copying, e.g. to restore a
+    /// snapshot. Its length must be a whole number of pages no larger than `max_pages`,
+    /// and like [`WasmMemory::try_new`] it counts towards the global memory limit.
+    pub fn from_bytes(data: Vec<u8>, max_pages: u32) -> Result<Self, &'static str> {
+        let page_size = Self::PAGE_SIZE as usize;
+        if !data.len().is_multiple_of(page_size) {
+            return Err(PARTIAL_MEMORY_PAGE);
+        }
+        let pages = data.len() / page_size;
+        if pages > Self::MAX_PAGES as usize || max_pages > Self::MAX_PAGES {
+            return Err(MEMORY_SIZE_LIMIT);
+        }
+        let pages = pages as u32;
+        if pages > max_pages {
+            return Err(MIN_GREATER_THAN_MAX);
+        }
+        if !try_reserve(pages_to_bytes(pages)) {
+            return Err(GLOBAL_MEMORY_LIMIT);
+        }
+        Ok(Self { data, current: pages, maximum: max_pages, readonly: false })
+    }
+
+    fn alloc(initial: u32, maximum: u32) -> Self {
         let maximum = maximum.min(Self::MAX_PAGES);
         let data = vec![0; (initial as usize) * (Self::PAGE_SIZE as usize)];
-        Self { data, current: initial, maximum }
+        Self { data, current: initial, maximum, readonly: false }
     }
 
     pub fn size(&self) -> u32 {
@@ -55,12 +136,44 @@ impl WasmMemory {
     pub fn max(&self) -> u32 {
         self.maximum
     }
+    /// The whole memory, `size()` pages long.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Freezes or thaws the memory. While readonly, stores and [`WasmMemory::write_bytes`]
+    /// fail with a readonly error and `grow` refuses any non-zero delta; loads are unaffected.
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.readonly = readonly;
+    }
+
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    #[inline(always)]
+    fn check_writable(&self) -> Result<(), &'static str> {
+        if self.readonly {
+            return Err(READONLY_MEMORY);
+        }
+        Ok(())
+    }
 
     pub fn grow(&mut self, delta: u32) -> u32 {
         if delta == 0 {
             return self.current;
         }
-        if delta > self.maximum.saturating_sub(self.current) {
+        if self.readonly || delta > self.maximum.saturating_sub(self.current) {
+            return u32::MAX;
+        }
+        let bytes = pages_to_bytes(delta);
+        if !try_reserve(bytes) {
+            return u32::MAX;
+        }
+        // Growing toward a 4GiB maximum can outrun the allocator, which must fail the grow
+        // rather than abort the process
+        if self.data.try_reserve_exact(bytes as usize).is_err() {
+            MEMORY_IN_USE.fetch_sub(bytes, Ordering::Relaxed);
             return u32::MAX;
         }
         let old = self.current;
@@ -94,14 +207,58 @@ impl WasmMemory {
     pub fn store_f64(&mut self, ptr: u32, offset: u32, v: f64) -> Result<(), &'static str> {
         self.store_u64(ptr, offset, v.to_bits())
     }
+    /// `len` bytes starting at `offset`.
+    pub fn read_bytes(&self, offset: u32, len: u32) -> Result<&[u8], &'static str> {
+        let start = effective_addr(offset, 0, len as u64, self.data.len())?;
+        Ok(&self.data[start..start + len as usize])
+    }
     #[inline(always)]
     pub fn write_bytes(&mut self, offset: u32, bytes: &[u8]) -> Result<(), &'static str> {
-        let start = offset as usize;
-        let end = start.checked_add(bytes.len()).ok_or(OOB_MEMORY_ACCESS)?;
-        if end > self.data.len() {
-            return Err(OOB_MEMORY_ACCESS);
-        }
-        self.data[start..end].copy_from_slice(bytes);
+        self.check_writable()?;
+        let start = effective_addr(offset, 0, bytes.len() as u64, self.data.len())?;
+        self.data[start..start + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// `memory.fill`: sets `len` bytes from `dst` to `val`, nothing is written if the
+    /// range is out of bounds.
+    pub fn fill(&mut self, dst: u32, val: u8, len: u32) -> Result<(), &'static str> {
+        self.check_writable()?;
+        let dst = effective_addr(dst, 0, len as u64, self.data.len())?;
+        self.data[dst..dst + len as usize].fill(val);
+        Ok(())
+    }
+
+    /// `memory.copy`: copies `len` bytes from `src` to `dst` as if through a temporary
+    /// buffer, so the ranges may overlap. Both are checked before anything is written.
+    pub fn copy(&mut self, dst: u32, src: u32, len: u32) -> Result<(), &'static str> {
+        self.check_writable()?;
+        let src = effective_addr(src, 0, len as u64, self.data.len())?;
+        let dst = effective_addr(dst, 0, len as u64, self.data.len())?;
+        self.data.copy_within(src..src + len as usize, dst);
         Ok(())
     }
+
+    /// `memory.init`: copies `segment[src..src + len]` to `dst`. Both ranges are checked
+    /// before anything is written, so an out-of-bounds init has no partial effect.
+    pub fn init(
+        &mut self,
+        dst: u32,
+        segment: &[u8],
+        src: u32,
+        len: u32,
+    ) -> Result<(), &'static str> {
+        self.check_writable()?;
+        let src = effective_addr(src, 0, len as u64, segment.len())?;
+        let dst = effective_addr(dst, 0, len as u64, self.data.len())?;
+        let len = len as usize;
+        self.data[dst..dst + len].copy_from_slice(&segment[src..src + len]);
+        Ok(())
+    }
+}
+
+impl Drop for WasmMemory {
+    fn drop(&mut self) {
+        MEMORY_IN_USE.fetch_sub(pages_to_bytes(self.current), Ordering::Relaxed);
+    }
 }
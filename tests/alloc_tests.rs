@@ -0,0 +1,65 @@
+// Counts every heap allocation in the process, so it lives in its own test binary
+// with a single test to keep other tests from allocating concurrently.
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use common::{func, instantiate};
+use wagmi::{ExecPool, Imports, WasmValue};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+#[test]
+fn pooled_invocations_reuse_their_scratch_space() {
+    let inst = instantiate(
+        r#"(module
+            (memory 1)
+            (func $fib (param i32) (result i32)
+                (if (result i32) (i32.lt_u (local.get 0) (i32.const 2))
+                    (then (local.get 0))
+                    (else (i32.add (call $fib (i32.sub (local.get 0) (i32.const 1)))
+                                   (call $fib (i32.sub (local.get 0) (i32.const 2)))))))
+            (func (export "run") (param i32) (result i32)
+                (i32.store (i32.const 0) (call $fib (local.get 0)))
+                (i32.load (i32.const 0))))"#,
+        &Imports::new(),
+    );
+    let run = func(&inst, "run");
+    let mut pool = ExecPool::new();
+    // The first call sizes the pool for the deepest recursion
+    assert_eq!(
+        inst.invoke_pooled(&run, &[WasmValue::from_i32(15)], &mut pool).unwrap()[0].as_i32(),
+        610
+    );
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for n in 0..=15 {
+        let results = inst.invoke_pooled(&run, &[WasmValue::from_i32(n)], &mut pool).unwrap();
+        assert_eq!(results.len(), 1);
+    }
+    assert_eq!(ALLOCATIONS.load(Ordering::Relaxed), before);
+
+    // Unpooled calls allocate fresh stacks every time
+    inst.invoke(&run, &[WasmValue::from_i32(3)]).unwrap();
+    assert!(ALLOCATIONS.load(Ordering::Relaxed) > before);
+}
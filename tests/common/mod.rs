@@ -0,0 +1,112 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use wagmi::{ExportValue, Imports, Instance, Module, RuntimeFunction, Store};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Compiles WAT source to a wasm binary using the bundled wat2wasm.
+///
+/// Validation is left to wagmi (`--no-check`) so tests can build invalid modules,
+/// and all proposals are enabled so post-MVP encodings can be produced.
+pub fn wat(src: &str) -> Vec<u8> {
+    let wat2wasm = if cfg!(target_os = "macos") {
+        Path::new("tools/osx/wat2wasm")
+    } else if cfg!(target_os = "linux") {
+        Path::new("tools/linux/wat2wasm")
+    } else {
+        panic!("Unsupported OS for wat2wasm")
+    };
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let stem = format!("wagmi-test-{}-{}", std::process::id(), id);
+    let wat_path = env::temp_dir().join(format!("{}.wat", stem));
+    let wasm_path = env::temp_dir().join(format!("{}.wasm", stem));
+    fs::write(&wat_path, src).expect("failed to write wat");
+
+    let output = Command::new(wat2wasm)
+        .arg(&wat_path)
+        .arg("--no-check")
+        .arg("--enable-all")
+        .arg("-o")
+        .arg(&wasm_path)
+        .output()
+        .expect("failed to run wat2wasm");
+    let _ = fs::remove_file(&wat_path);
+    if !output.status.success() {
+        panic!("wat2wasm failed: {}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    let bytes = fs::read(&wasm_path).expect("failed to read wasm");
+    let _ = fs::remove_file(&wasm_path);
+    bytes
+}
+
+pub fn compile(src: &str) -> Module {
+    Module::compile(wat(src)).expect("compile failed")
+}
+
+/// Instantiates `src` in a store of its own.
+pub fn instantiate(src: &str, imports: &Imports) -> Instance {
+    instantiate_in(&Store::new(), src, imports)
+}
+
+/// Instantiates `src` in `store`, for instances that dispatch funcrefs between each other.
+pub fn instantiate_in(store: &Store, src: &str, imports: &Imports) -> Instance {
+    store.instantiate(Rc::new(compile(src)), imports).expect("instantiate failed")
+}
+
+pub fn func(inst: &Instance, name: &str) -> RuntimeFunction {
+    match inst.exports.get(name) {
+        Some(ExportValue::Function(f)) => f.clone(),
+        _ => panic!("export '{}' is not a function", name),
+    }
+}
+
+pub fn imports_of(module: &str, entries: Vec<(&str, ExportValue)>) -> Imports {
+    let mut fields = HashMap::new();
+    for (name, value) in entries {
+        fields.insert(name.to_string(), value);
+    }
+    let mut imports = Imports::new();
+    imports.insert(module.to_string(), fields);
+    imports
+}
+
+/// A module with the given `(section id, payload)` sections, in order, each with its
+/// size filled in. For binaries wat2wasm can't or won't produce, e.g. malformed ones.
+pub fn module_with_sections(sections: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+    for (id, payload) in sections {
+        bytes.push(*id);
+        leb128(payload.len(), &mut bytes);
+        bytes.extend_from_slice(payload);
+    }
+    bytes
+}
+
+/// Code section payload with a single function, `body` being its locals and code.
+pub fn code_with_body(body: &[u8]) -> Vec<u8> {
+    let mut payload = vec![0x01];
+    leb128(body.len(), &mut payload);
+    payload.extend_from_slice(body);
+    payload
+}
+
+fn leb128(mut n: usize, out: &mut Vec<u8>) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            return out.push(byte);
+        }
+        out.push(byte | 0x80);
+    }
+}
@@ -0,0 +1,233 @@
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use common::{func, imports_of, instantiate};
+use wagmi::wasm_memory::WasmMemory;
+use wagmi::{
+    host, AbortHost, CollectingHost, Error, ExportValue, RuntimeFunction, ValType, WasmValue,
+};
+
+#[test]
+fn collecting_host_gathers_yielded_stream() {
+    let collector = CollectingHost::new();
+    let imports =
+        imports_of("env", vec![("yield", ExportValue::Function(collector.function(ValType::I32)))]);
+    let inst = instantiate(
+        r#"(module
+            (import "env" "yield" (func $yield (param i32)))
+            (func (export "run") (local $i i32)
+                (loop $l
+                    (call $yield (local.get $i))
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (br_if $l (i32.lt_u (local.get $i) (i32.const 10))))))"#,
+        &imports,
+    );
+
+    inst.invoke(&func(&inst, "run"), &[]).unwrap();
+    let values: Vec<i32> = collector.take().iter().map(|v| v.as_i32()).collect();
+    assert_eq!(values, (0..10).collect::<Vec<_>>());
+    assert!(collector.is_empty());
+}
+
+#[test]
+fn abort_host_traps_with_the_guest_message() {
+    let memory = Rc::new(RefCell::new(WasmMemory::new(1, 1)));
+    let abort = AbortHost::new();
+    let shared = memory.clone();
+    let getter = move || Some(shared.clone());
+    let imports = imports_of(
+        "env",
+        vec![
+            ("memory", ExportValue::Memory(memory)),
+            ("abort", ExportValue::Function(abort.handler(getter.clone()))),
+            ("panic", ExportValue::Function(abort.handler_with_location(getter))),
+        ],
+    );
+    let inst = instantiate(
+        r#"(module
+            (import "env" "memory" (memory 1))
+            (import "env" "abort" (func $abort (param i32 i32)))
+            (import "env" "panic" (func $panic (param i32 i32 i32 i32 i32)))
+            (data (i32.const 16) "index out of bounds")
+            (data (i32.const 64) "src/lib.rs")
+            (func (export "abort") (call $abort (i32.const 16) (i32.const 19)))
+            (func (export "panic") (call $panic (i32.const 16) (i32.const 5) (i32.const 64) (i32.const 10) (i32.const 42)))
+            (func (export "bad") (call $abort (i32.const 65530) (i32.const 100))))"#,
+        &imports,
+    );
+    assert_eq!(abort.message(), None);
+
+    let trap = |text: &str| Some(Error::HostTrap(format!("guest aborted: {}", text)));
+    assert_eq!(inst.invoke(&func(&inst, "abort"), &[]).err(), trap("index out of bounds"));
+    assert_eq!(abort.message().as_deref(), Some("index out of bounds"));
+
+    assert_eq!(inst.invoke(&func(&inst, "panic"), &[]).err(), trap("index at src/lib.rs:42"));
+    assert_eq!(abort.message().as_deref(), Some("index at src/lib.rs:42"));
+
+    // An unreadable message still aborts
+    assert!(matches!(inst.invoke(&func(&inst, "bad"), &[]), Err(Error::Trap("guest aborted"))));
+    assert_eq!(abort.message(), None);
+}
+
+#[test]
+fn abort_handler_decodes_the_message_for_the_embedder() {
+    let memory = Rc::new(RefCell::new(WasmMemory::new(1, 1)));
+    let shared = memory.clone();
+    let (handler, abort) = host::abort_handler(move || Some(shared.clone()));
+    let inst = instantiate(
+        r#"(module
+            (import "env" "memory" (memory 1))
+            (import "env" "abort" (func $abort (param i32 i32)))
+            (data (i32.const 8) "assertion failed")
+            (func (export "run") (call $abort (i32.const 8) (i32.const 16))))"#,
+        &imports_of(
+            "env",
+            vec![
+                ("memory", ExportValue::Memory(memory)),
+                ("abort", ExportValue::Function(handler)),
+            ],
+        ),
+    );
+    let err = inst.invoke(&func(&inst, "run"), &[]).err().unwrap();
+    assert_eq!(err.to_string(), "guest aborted: assertion failed");
+    assert_eq!(abort.message().as_deref(), Some("assertion failed"));
+}
+
+#[test]
+fn host_results_beyond_the_declared_one_trap() {
+    let pair = RuntimeFunction::new_host(vec![ValType::I32], Some(ValType::I32), |args| {
+        [args[0], WasmValue::from_i32(args[0].as_i32() * 2)]
+    });
+    let inst = instantiate(
+        r#"(module
+            (import "env" "unit" (func $unit))
+            (import "env" "one" (func $one (result i32)))
+            (func (export "sum") (result i32) (call $unit) (i32.add (call $one) (call $one))))"#,
+        &imports_of(
+            "env",
+            vec![
+                ("unit", ExportValue::Function(RuntimeFunction::new_host(vec![], None, |_| ()))),
+                (
+                    "one",
+                    ExportValue::Function(RuntimeFunction::new_host(
+                        vec![],
+                        Some(ValType::I32),
+                        |_| WasmValue::from_i32(20),
+                    )),
+                ),
+            ],
+        ),
+    );
+    assert_eq!(inst.invoke(&func(&inst, "sum"), &[]).unwrap()[0].as_i32(), 40);
+
+    // Multiple results are not supported, the surplus is rejected rather than dropped
+    let results = inst.invoke(&pair, &[WasmValue::from_i32(21)]);
+    assert_eq!(results.err(), Some(Error::Trap("host result mismatch")));
+}
+
+#[test]
+fn host_errors_unwind_nested_and_indirect_calls() {
+    let write =
+        RuntimeFunction::new_host(vec![ValType::I32], Some(ValType::I32), |args| {
+            match args[0].as_i32() {
+                n if n >= 0 => Ok(vec![WasmValue::from_i32(n)]),
+                _ => Err(Error::trap("io failure")),
+            }
+        });
+    let inst = instantiate(
+        r#"(module
+            (import "env" "write" (func $write (param i32) (result i32)))
+            (type $w (func (param i32) (result i32)))
+            (table 1 funcref)
+            (elem (i32.const 0) $write)
+            (func $inner (param i32) (result i32)
+                (i32.add (i32.const 1) (call $write (local.get 0))))
+            (func (export "direct") (param i32) (result i32) (call $inner (local.get 0)))
+            (func (export "indirect") (param i32) (result i32)
+                (call_indirect (type $w) (local.get 0) (i32.const 0))))"#,
+        &imports_of("env", vec![("write", ExportValue::Function(write))]),
+    );
+    let call = |name: &str, arg: i32| {
+        inst.invoke(&func(&inst, name), &[WasmValue::from_i32(arg)]).map(|r| r[0].as_i32())
+    };
+    assert_eq!(call("direct", -1), Err(Error::Trap("io failure")));
+    assert_eq!(call("indirect", -1), Err(Error::Trap("io failure")));
+    // A trap leaves the instance usable
+    assert_eq!(call("direct", 4), Ok(5));
+    assert_eq!(call("indirect", 4), Ok(4));
+}
+
+#[test]
+fn host_functions_see_the_calling_instance() {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let sink = seen.clone();
+    let log =
+        RuntimeFunction::new_host_with_caller(vec![ValType::I32], None, move |caller, args| {
+            let memory = caller.memory().expect("caller has a memory").borrow();
+            let tail = memory.read_bytes(args[0].as_u32(), 64).unwrap();
+            let end = tail.iter().position(|&b| b == 0).unwrap();
+            sink.borrow_mut().push(String::from_utf8_lossy(&tail[..end]).into_owned());
+            // The caller's globals and exports are visible too
+            let counter = &caller.globals()[0];
+            counter.value.set(WasmValue::from_i32(counter.value.get().as_i32() + 1));
+            assert!(matches!(caller.export("run"), Some(ExportValue::Function(_))));
+        });
+    let inst = instantiate(
+        r#"(module
+            (import "env" "log" (func $log (param i32)))
+            (memory 1)
+            (global $calls (export "calls") (mut i32) (i32.const 0))
+            (data (i32.const 8) "first\00second\00")
+            (func (export "run") (result i32)
+                (call $log (i32.const 8))
+                (call $log (i32.const 14))
+                (global.get $calls)))"#,
+        &imports_of("env", vec![("log", ExportValue::Function(log))]),
+    );
+    assert_eq!(inst.invoke(&func(&inst, "run"), &[]).unwrap()[0].as_i32(), 2);
+    assert_eq!(*seen.borrow(), ["first", "second"]);
+}
+
+#[test]
+fn host_results_must_match_the_declared_count() {
+    let forgetful = RuntimeFunction::new_host(vec![], Some(ValType::I32), |_| None);
+    let chatty = RuntimeFunction::new_host(vec![], None, |_| WasmValue::from_i32(1));
+    let inst = instantiate(
+        r#"(module
+            (import "env" "forgetful" (func $forgetful (result i32)))
+            (import "env" "chatty" (func $chatty))
+            (func (export "get") (result i32) (i32.const 5) (call $forgetful) (i32.add))
+            (func (export "notify") (call $chatty)))"#,
+        &imports_of(
+            "env",
+            vec![
+                ("forgetful", ExportValue::Function(forgetful)),
+                ("chatty", ExportValue::Function(chatty)),
+            ],
+        ),
+    );
+    // A surplus traps like a shortfall does, strict mode or not
+    let mismatch = Some(Error::Trap("host result mismatch"));
+    for name in ["get", "notify"] {
+        assert_eq!(inst.invoke(&func(&inst, name), &[]).err(), mismatch, "{}", name);
+    }
+}
+
+#[test]
+fn strict_host_results_trap_on_a_64_bit_value_for_a_32_bit_result() {
+    let wide =
+        RuntimeFunction::new_host(vec![], Some(ValType::I32), |_| WasmValue::from_u64(1 << 32 | 7));
+    let inst = instantiate(
+        r#"(module
+            (import "env" "wide" (func $wide (result i32)))
+            (func (export "get") (result i32) (call $wide)))"#,
+        &imports_of("env", vec![("wide", ExportValue::Function(wide))]),
+    );
+    let get = func(&inst, "get");
+    assert_eq!(inst.invoke(&get, &[]).unwrap()[0].as_i32(), 7);
+
+    inst.set_strict_host_results(true);
+    assert_eq!(inst.invoke(&get, &[]).err(), Some(Error::Trap("host result mismatch")));
+}
@@ -0,0 +1,1818 @@
+mod common;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use common::{compile, func, imports_of, instantiate, instantiate_in};
+use wagmi::{
+    DanglingImport, DeterminismConfig, Error, ExportValue, ExternDesc, ImportDesc, Imports,
+    Instance, Module, RuntimeFunction, Signature, Store, ValType, WasmGlobal, WasmMemory,
+    WasmTable, WasmValue,
+};
+
+#[test]
+fn data_segment_past_initial_memory_fails_to_link() {
+    // One initial page, max of two: the segment would fit after a grow but must not trigger one
+    let module = compile(r#"(module (memory 1 2) (data (i32.const 65535) "ab"))"#);
+    match Store::new().instantiate(Rc::new(module), &Imports::new()) {
+        Err(e) => assert_eq!(e, Error::Link("data segment does not fit")),
+        Ok(_) => panic!("data segment past initial memory should not link"),
+    }
+
+    let module = compile(r#"(module (memory 1 2) (data (i32.const 65534) "ab"))"#);
+    assert!(Store::new().instantiate(Rc::new(module), &Imports::new()).is_ok());
+}
+
+#[test]
+fn canonicalize_signed_zero_is_opt_in() {
+    let inst = instantiate(
+        r#"(module (func (export "neg") (param f32) (result f32) (f32.neg (local.get 0))))"#,
+        &Imports::new(),
+    );
+    let neg = func(&inst, "neg");
+    let args = [WasmValue::from_f32(0.0)];
+
+    let spec = inst.invoke(&neg, &args).unwrap()[0].as_f32();
+    assert!(spec == 0.0 && spec.is_sign_negative());
+
+    inst.set_determinism(DeterminismConfig {
+        canonicalize_signed_zero: true,
+        ..Default::default()
+    });
+    let canonical = inst.invoke(&neg, &args).unwrap()[0].as_f32();
+    assert!(canonical == 0.0 && canonical.is_sign_positive());
+}
+
+#[test]
+fn determinism_config_canonicalizes_nans() {
+    let inst = instantiate(
+        r#"(module
+            (func (export "add") (param f64 f64) (result f64) (f64.add (local.get 0) (local.get 1)))
+            (func (export "neg") (param f32) (result f32) (f32.neg (local.get 0)))
+            (func (export "demote") (param f64) (result f32) (f32.demote_f64 (local.get 0))))"#,
+        &Imports::new(),
+    );
+    let (add, neg, demote) = (func(&inst, "add"), func(&inst, "neg"), func(&inst, "demote"));
+    let payload = f64::from_bits(0xfff0_0000_dead_beef);
+    let args = [WasmValue::from_f64(payload), WasmValue::from_f64(1.0)];
+
+    // Spec behavior lets the payload and sign through
+    assert_ne!(inst.invoke(&add, &args).unwrap()[0].as_f64().to_bits(), f64::NAN.to_bits());
+
+    let config = DeterminismConfig { canonicalize_nan: true, ..Default::default() };
+    inst.set_determinism(config);
+    assert_eq!(inst.determinism(), config);
+
+    assert_eq!(inst.invoke(&add, &args).unwrap()[0].as_f64().to_bits(), 0x7ff8_0000_0000_0000);
+    let negated = inst.invoke(&neg, &[WasmValue::from_f32(f32::NAN)]).unwrap()[0].as_f32();
+    assert_eq!(negated.to_bits(), 0x7fc0_0000);
+    let demoted = inst.invoke(&demote, &[WasmValue::from_f64(payload)]).unwrap()[0].as_f32();
+    assert_eq!(demoted.to_bits(), 0x7fc0_0000);
+    // Non-NaN results are untouched
+    let args = [WasmValue::from_f64(1.5), WasmValue::from_f64(-0.0)];
+    assert_eq!(inst.invoke(&add, &args).unwrap()[0].as_f64(), 1.5);
+}
+
+#[test]
+fn store_registry_reports_live_instances_and_refcounts() {
+    let store = Store::new();
+    let with_table = Rc::new(instantiate_in(
+        &store,
+        r#"(module (table 3 funcref) (func $f) (elem (i32.const 0) $f $f $f))"#,
+        &Imports::new(),
+    ));
+    let plain = Rc::new(instantiate_in(&store, r#"(module (func))"#, &Imports::new()));
+    Instance::register_external_instance(&with_table);
+    Instance::register_external_instance(&plain);
+
+    let live = store.live_instance_ids();
+    assert!(live.contains(&with_table.id) && live.contains(&plain.id));
+    assert_eq!(store.instance_refcount(with_table.id), 3);
+    assert_eq!(store.instance_refcount(plain.id), 0);
+
+    let plain_id = plain.id;
+    drop(plain);
+    assert!(!store.live_instance_ids().contains(&plain_id));
+}
+
+#[test]
+fn br_to_loop_feeds_back_loop_params() {
+    let inst = instantiate(
+        r#"(module
+            (func (export "br_if") (param $n i32) (result i32)
+                i32.const 0
+                loop $l (param i32) (result i32)
+                    i32.const 1
+                    i32.add
+                    local.get $n
+                    i32.const 1
+                    i32.sub
+                    local.tee $n
+                    br_if $l
+                end)
+            (func (export "br") (param $n i32) (result i32) (local $tmp i32)
+                i32.const 0
+                loop $l (param i32) (result i32)
+                    i32.const 1
+                    i32.add
+                    local.get $n
+                    i32.const 1
+                    i32.sub
+                    local.tee $n
+                    if (param i32) (result i32)
+                        ;; leave junk below the carried value, the branch must drop it
+                        local.set $tmp
+                        i32.const 100
+                        local.get $tmp
+                        br $l
+                    end
+                end))"#,
+        &Imports::new(),
+    );
+    for name in ["br_if", "br"] {
+        let f = func(&inst, name);
+        let out = inst.invoke(&f, &[WasmValue::from_i32(5)]).unwrap();
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].as_i32(), 5, "{}", name);
+    }
+}
+
+#[test]
+fn truncated_bytecode_reports_unexpected_end() {
+    let src = r#"(module
+        (memory 1)
+        (func $id (param i32) (result i32) (local.get 0))
+        (func (export "run") (result i32)
+            (drop (f32.const 1.5))
+            (drop (f64.const -2.25))
+            (drop (i64.const 0x7fff_ffff_ffff))
+            (drop (memory.size))
+            (i32.store offset=1024 (i32.const 8) (i32.const 0x12345))
+            (drop (select (result i32) (i32.const 1) (i32.const 2) (i32.const 0)))
+            (call $id (i32.load offset=1024 (i32.const 8)))))"#;
+    let full = compile(src);
+    let body = full.functions[1].body.clone();
+
+    // Every cut inside the body leaves an instruction or its immediates partially missing
+    for cut in body.start..body.end {
+        let mut module = compile(src);
+        module.bytes = Rc::new(full.bytes[..cut].to_vec());
+        let inst = Store::new().instantiate(Rc::new(module), &Imports::new()).unwrap();
+        let result = inst.invoke(&func(&inst, "run"), &[]);
+        assert_eq!(
+            result.err(),
+            Some(Error::Malformed("unexpected end of section or function")),
+            "cut at {}",
+            cut
+        );
+    }
+}
+
+#[test]
+fn branches_carrying_three_values_move_them_over_overlapping_slots() {
+    // Each iteration leaves junk below the three carried values, so the branch moves
+    // them down by fewer slots than it carries and the source and target overlap
+    let looped = |junk: &str, branch: &str| {
+        format!(
+            r#"(param $sel i32) (result i32) (local $n i32) (local $a i32) (local $b i32)
+                i32.const 4
+                i32.const 1
+                i32.const 1
+                loop $l (param i32 i32 i32)
+                    local.set $b
+                    local.set $a
+                    local.set $n
+                    (if (i32.eqz (local.get $n))
+                        (then (return (i32.add (i32.mul (local.get $a) (i32.const 1000)) (local.get $b)))))
+                    {junk}
+                    (i32.sub (local.get $n) (i32.const 1))
+                    (i32.add (local.get $a) (local.get $b))
+                    local.get $a
+                    {branch}
+                end
+                unreachable"#
+        )
+    };
+    let inst = instantiate(
+        &format!(
+            r#"(module
+                (func (export "br") {})
+                (func (export "br_if") {})
+                (func (export "br_table") {}))"#,
+            looped("i32.const 77", "br $l"),
+            looped("i32.const 77 i32.const 66", "(br_if $l (i32.const 1)) unreachable"),
+            looped("i32.const 77 i32.const 66", "(br_table $l $l (local.get $sel))"),
+        ),
+        &Imports::new(),
+    );
+    // Fibonacci pairs: (1, 1) -> (2, 1) -> (3, 2) -> (5, 3) -> (8, 5)
+    for name in ["br", "br_if", "br_table"] {
+        for sel in [0, 5] {
+            let out = inst.invoke(&func(&inst, name), &[WasmValue::from_i32(sel)]).unwrap();
+            assert_eq!(out[0].as_i32(), 8005, "{name}");
+        }
+    }
+}
+
+#[test]
+fn expect_trap_returns_trap_message() {
+    let inst = instantiate(
+        r#"(module (func (export "div") (param i32 i32) (result i32)
+            (i32.div_s (local.get 0) (local.get 1))))"#,
+        &Imports::new(),
+    );
+    let div = func(&inst, "div");
+    let msg = inst.expect_trap(&div, &[WasmValue::from_i32(7), WasmValue::from_i32(0)]);
+    assert_eq!(msg, "integer divide by zero");
+}
+
+#[test]
+#[should_panic(expected = "expected a trap")]
+fn expect_trap_panics_when_function_returns() {
+    let inst = instantiate(r#"(module (func (export "ok")))"#, &Imports::new());
+    inst.expect_trap(&func(&inst, "ok"), &[]);
+}
+
+#[test]
+fn explicit_store_tracks_its_own_instances() {
+    let store = Store::new();
+    let module =
+        Rc::new(compile(r#"(module (table 1 funcref) (func $f) (elem (i32.const 0) $f))"#));
+    let inst = Rc::new(store.instantiate(module, &Imports::new()).unwrap());
+    Instance::register_external_instance(&inst);
+
+    assert_eq!(store.live_instance_ids(), vec![inst.id]);
+    assert_eq!(store.instance_refcount(inst.id), 1);
+    drop(inst);
+    assert!(store.live_instance_ids().is_empty());
+}
+
+#[test]
+fn wrap_and_extend_handle_boundary_values() {
+    let inst = instantiate(
+        r#"(module
+            (func (export "wrap") (param i64) (result i32) (i32.wrap_i64 (local.get 0)))
+            (func (export "extend_s") (param i32) (result i64) (i64.extend_i32_s (local.get 0)))
+            (func (export "extend_u") (param i32) (result i64) (i64.extend_i32_u (local.get 0))))"#,
+        &Imports::new(),
+    );
+    let call = |name, arg| inst.invoke(&func(&inst, name), &[arg]).unwrap()[0];
+
+    for (input, expected) in [
+        (0x1_0000_0001u64, 1u32),
+        (0xffff_ffff_0000_0000, 0),
+        (0x0000_0000_ffff_ffff, 0xffff_ffff),
+        (0x8000_0000_8000_0000, 0x8000_0000),
+        (u64::MAX, u32::MAX),
+    ] {
+        assert_eq!(call("wrap", WasmValue::from_u64(input)).as_u32(), expected, "{:#x}", input);
+    }
+    for (input, signed, unsigned) in [
+        (-1i32, -1i64, 0xffff_ffffi64),
+        (i32::MIN, i32::MIN as i64, 0x8000_0000),
+        (i32::MAX, i32::MAX as i64, i32::MAX as i64),
+        (0, 0, 0),
+    ] {
+        assert_eq!(call("extend_s", WasmValue::from_i32(input)).as_i64(), signed, "{}", input);
+        assert_eq!(call("extend_u", WasmValue::from_i32(input)).as_i64(), unsigned, "{}", input);
+    }
+}
+
+#[test]
+fn saturating_truncations_clamp_instead_of_trapping() {
+    let inst = instantiate(
+        r#"(module
+            (func (export "i32_f32_s") (param f32) (result i32) (i32.trunc_sat_f32_s (local.get 0)))
+            (func (export "i32_f32_u") (param f32) (result i32) (i32.trunc_sat_f32_u (local.get 0)))
+            (func (export "i32_f64_s") (param f64) (result i32) (i32.trunc_sat_f64_s (local.get 0)))
+            (func (export "i32_f64_u") (param f64) (result i32) (i32.trunc_sat_f64_u (local.get 0)))
+            (func (export "i64_f32_s") (param f32) (result i64) (i64.trunc_sat_f32_s (local.get 0)))
+            (func (export "i64_f32_u") (param f32) (result i64) (i64.trunc_sat_f32_u (local.get 0)))
+            (func (export "i64_f64_s") (param f64) (result i64) (i64.trunc_sat_f64_s (local.get 0)))
+            (func (export "i64_f64_u") (param f64) (result i64) (i64.trunc_sat_f64_u (local.get 0))))"#,
+        &Imports::new(),
+    );
+    let call = |name, arg| inst.invoke(&func(&inst, name), &[arg]).unwrap()[0];
+    let f32s = |name, input: f32| call(name, WasmValue::from_f32(input));
+    let f64s = |name, input: f64| call(name, WasmValue::from_f64(input));
+
+    // Boundary values from the spec's conversions.wast
+    for (name, input, expected) in [
+        ("i32_f32_s", -1.9, -1),
+        ("i32_f32_s", 2147483520.0, 2147483520),
+        ("i32_f32_s", 2147483648.0, i32::MAX),
+        ("i32_f32_s", -2147483904.0, i32::MIN),
+        ("i32_f32_s", f32::INFINITY, i32::MAX),
+        ("i32_f32_s", f32::NAN, 0),
+        ("i32_f32_u", -0.9, 0),
+        ("i32_f32_u", 4294967040.0, -256),
+        ("i32_f32_u", 4294967296.0, -1),
+        ("i32_f32_u", f32::NEG_INFINITY, 0),
+        ("i32_f32_u", -f32::NAN, 0),
+    ] {
+        assert_eq!(f32s(name, input).as_i32(), expected, "{} {}", name, input);
+    }
+    for (name, input, expected) in [
+        ("i32_f64_s", 2147483647.9, i32::MAX),
+        ("i32_f64_s", -2147483648.9, i32::MIN),
+        ("i32_f64_s", 1e100, i32::MAX),
+        ("i32_f64_s", f64::NAN, 0),
+        ("i32_f64_u", 4294967295.9, -1),
+        ("i32_f64_u", 4294967296.0, -1),
+        ("i32_f64_u", -1e100, 0),
+        ("i32_f64_u", f64::NAN, 0),
+    ] {
+        assert_eq!(f64s(name, input).as_i32(), expected, "{} {}", name, input);
+    }
+    for (name, input, expected) in [
+        ("i64_f32_s", 9223371487098961920.0, 9223371487098961920),
+        ("i64_f32_s", 9223372036854775808.0, i64::MAX),
+        ("i64_f32_s", -9223373136366403584.0, i64::MIN),
+        ("i64_f32_s", f32::NAN, 0),
+        ("i64_f32_u", 18446742974197923840.0, -1099511627776),
+        ("i64_f32_u", 18446744073709551616.0, -1),
+        ("i64_f32_u", -1.0, 0),
+        ("i64_f32_u", f32::NAN, 0),
+    ] {
+        assert_eq!(f32s(name, input).as_i64(), expected, "{} {}", name, input);
+    }
+    for (name, input, expected) in [
+        ("i64_f64_s", 9223372036854774784.0, 9223372036854774784),
+        ("i64_f64_s", 9223372036854775808.0, i64::MAX),
+        ("i64_f64_s", -9223372036854777856.0, i64::MIN),
+        ("i64_f64_s", f64::INFINITY, i64::MAX),
+        ("i64_f64_s", f64::NAN, 0),
+        ("i64_f64_u", 18446744073709549568.0, -2048),
+        ("i64_f64_u", 18446744073709551616.0, -1),
+        ("i64_f64_u", f64::NEG_INFINITY, 0),
+        ("i64_f64_u", f64::NAN, 0),
+    ] {
+        assert_eq!(f64s(name, input).as_i64(), expected, "{} {}", name, input);
+    }
+}
+
+#[test]
+fn sign_extension_operators_extend_the_low_bits() {
+    let inst = instantiate(
+        r#"(module
+            (func (export "i32.8") (param i32) (result i32) (i32.extend8_s (local.get 0)))
+            (func (export "i32.16") (param i32) (result i32) (i32.extend16_s (local.get 0)))
+            (func (export "i64.8") (param i64) (result i64) (i64.extend8_s (local.get 0)))
+            (func (export "i64.16") (param i64) (result i64) (i64.extend16_s (local.get 0)))
+            (func (export "i64.32") (param i64) (result i64) (i64.extend32_s (local.get 0))))"#,
+        &Imports::new(),
+    );
+    let call = |name, arg| inst.invoke(&func(&inst, name), &[arg]).unwrap()[0];
+
+    for (name, input, expected) in [
+        ("i32.8", 0x7f, 0x7f),
+        ("i32.8", 0x80, -0x80),
+        ("i32.8", 0x1234_56ff, -1),
+        ("i32.16", 0x7fff, 0x7fff),
+        ("i32.16", 0x0001_8000, -0x8000),
+    ] {
+        assert_eq!(
+            call(name, WasmValue::from_i32(input)).as_i32(),
+            expected,
+            "{} {:#x}",
+            name,
+            input
+        );
+    }
+    for (name, input, expected) in [
+        ("i64.8", 0x80, -0x80),
+        ("i64.8", 0x0123_4567_89ab_cd7f, 0x7f),
+        ("i64.16", 0xffff_ffff_ffff_7fff_u64 as i64, 0x7fff),
+        ("i64.16", 0x8000, -0x8000),
+        ("i64.32", 0x8000_0000, -0x8000_0000),
+        ("i64.32", 0xffff_ffff_7fff_ffff_u64 as i64, 0x7fff_ffff),
+    ] {
+        assert_eq!(
+            call(name, WasmValue::from_i64(input)).as_i64(),
+            expected,
+            "{} {:#x}",
+            name,
+            input
+        );
+    }
+}
+
+#[test]
+fn pass_bytes_writes_through_guest_allocator() {
+    let inst = instantiate(
+        r#"(module
+            (memory 1)
+            (global $heap (mut i32) (i32.const 1024))
+            (func (export "alloc") (param $len i32) (result i32)
+                (global.get $heap)
+                (global.set $heap (i32.add (global.get $heap) (local.get $len))))
+            (func (export "sum") (param $ptr i32) (param $len i32) (result i32) (local $acc i32)
+                (block $done
+                    (loop $next
+                        (br_if $done (i32.eqz (local.get $len)))
+                        (local.set $acc (i32.add (local.get $acc) (i32.load8_u (local.get $ptr))))
+                        (local.set $ptr (i32.add (local.get $ptr) (i32.const 1)))
+                        (local.set $len (i32.sub (local.get $len) (i32.const 1)))
+                        (br $next)))
+                (local.get $acc)))"#,
+        &Imports::new(),
+    );
+    let (ptr, len) = inst.pass_bytes("alloc", &[1, 2, 3, 250]).unwrap();
+    assert_eq!((ptr, len), (1024, 4));
+    let args = [WasmValue::from_u32(ptr), WasmValue::from_u32(len)];
+    assert_eq!(inst.invoke(&func(&inst, "sum"), &args).unwrap()[0].as_i32(), 256);
+
+    // The allocator advanced, so a second buffer lands after the first
+    assert_eq!(inst.pass_bytes("alloc", b"hi").unwrap(), (1028, 2));
+    assert_eq!(inst.pass_bytes("missing", b"hi").err(), Some(Error::Link("unknown export")));
+    assert_eq!(inst.pass_bytes("sum", b"hi").err(), Some(Error::Link("incompatible export type")));
+}
+
+#[test]
+fn memory_events_record_successful_grows() {
+    let inst = instantiate(
+        r#"(module (memory 1 4)
+            (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0))))"#,
+        &Imports::new(),
+    );
+    let grow = func(&inst, "grow");
+    let grow_by = |n| inst.invoke(&grow, &[WasmValue::from_i32(n)]).unwrap()[0].as_i32();
+
+    assert_eq!(grow_by(1), 1);
+    assert!(inst.memory_events().is_empty(), "recording is off by default");
+
+    inst.record_memory_events(2);
+    assert_eq!(grow_by(1), 2);
+    assert_eq!(grow_by(8), -1);
+    assert_eq!(grow_by(1), 3);
+    assert_eq!(inst.memory_events(), vec![(2, 3), (3, 4)]);
+
+    // Oldest entries fall out once the buffer is full
+    assert_eq!(grow_by(0), 4);
+    assert_eq!(inst.memory_events(), vec![(3, 4), (4, 4)]);
+}
+
+#[test]
+fn data_segments_are_copied_in_order() {
+    let big: String = (0..4096).map(|i| format!("\\{:02x}", i % 251)).collect();
+    let inst = instantiate(
+        &format!(
+            r#"(module (memory 1)
+                (data (i32.const 100) "{}")
+                (data (i32.const 102) "xy")
+                (func (export "load") (param i32) (result i32) (i32.load8_u (local.get 0))))"#,
+            big
+        ),
+        &Imports::new(),
+    );
+    let load = func(&inst, "load");
+    let byte_at = |addr: i32| inst.invoke(&load, &[WasmValue::from_i32(addr)]).unwrap()[0].as_i32();
+
+    assert_eq!(byte_at(99), 0);
+    assert_eq!(byte_at(100), 0);
+    assert_eq!(byte_at(101), 1);
+    // The later segment overwrites the earlier one
+    assert_eq!(byte_at(102), b'x' as i32);
+    assert_eq!(byte_at(103), b'y' as i32);
+    assert_eq!(byte_at(104), 4);
+    assert_eq!(byte_at(100 + 4095), 4095 % 251);
+    assert_eq!(byte_at(100 + 4096), 0);
+}
+
+#[test]
+fn dangling_import_hook_fires_before_trap() {
+    let store = Store::new();
+    let exporter = Rc::new(instantiate_in(
+        &store,
+        r#"(module (func (export "answer") (result i32) (i32.const 42)))"#,
+        &Imports::new(),
+    ));
+    let RuntimeFunction::OwnedWasm { runtime_sig, sig, .. } = func(&exporter, "answer") else {
+        panic!("expected an owned wasm function");
+    };
+    let imported = RuntimeFunction::ImportedWasm {
+        runtime_sig,
+        sig,
+        owner: Rc::downgrade(&exporter),
+        function_index: 0,
+    };
+    let importer = instantiate_in(
+        &store,
+        r#"(module (import "lib" "answer" (func $answer (result i32)))
+            (export "answer" (func $answer))
+            (func (export "call") (result i32) (call $answer)))"#,
+        &imports_of("lib", vec![("answer", ExportValue::Function(imported))]),
+    );
+    let failed = Rc::new(RefCell::new(Vec::new()));
+    let seen = failed.clone();
+    importer.set_dangling_import_hook(move |dangling| seen.borrow_mut().push(dangling.clone()));
+
+    assert_eq!(importer.invoke(&func(&importer, "call"), &[]).unwrap()[0].as_i32(), 42);
+    assert!(failed.borrow().is_empty());
+
+    // Called from wasm and invoked directly through the re-export alike
+    drop(exporter);
+    let no_impl = Some(Error::Trap("function has no implementation"));
+    assert_eq!(importer.invoke(&func(&importer, "call"), &[]).err(), no_impl);
+    assert_eq!(importer.invoke(&func(&importer, "answer"), &[]).err(), no_impl);
+    let answer = DanglingImport {
+        import: Some(ImportDesc {
+            module: "lib".into(),
+            field: "answer".into(),
+            desc: ExternDesc::Func(Signature { params: vec![], result: Some(ValType::I32) }),
+        }),
+        function_index: Some(0),
+    };
+    assert_eq!(*failed.borrow(), [answer.clone(), answer]);
+
+    // A lenient stub has no exporter to name
+    let module = Rc::new(compile(
+        r#"(module (import "env" "log" (func $log)) (func (export "run") (call $log)))"#,
+    ));
+    let (inst, _) = Instance::instantiate_lenient(&store, module, &Imports::new()).unwrap();
+    let seen = failed.clone();
+    inst.set_dangling_import_hook(move |dangling| seen.borrow_mut().push(dangling.clone()));
+    failed.borrow_mut().clear();
+    assert_eq!(inst.invoke(&func(&inst, "run"), &[]).err(), no_impl);
+    let log = DanglingImport {
+        import: Some(ImportDesc {
+            module: "env".into(),
+            field: "log".into(),
+            desc: ExternDesc::Func(Signature { params: vec![], result: None }),
+        }),
+        function_index: None,
+    };
+    assert_eq!(*failed.borrow(), [log]);
+}
+
+#[test]
+fn readonly_memory_traps_wasm_stores() {
+    let inst = instantiate(
+        r#"(module (memory (export "mem") 1 2)
+            (func (export "store") (i32.store (i32.const 8) (i32.const 7)))
+            (func (export "load") (result i32) (i32.load (i32.const 8)))
+            (func (export "grow") (result i32) (memory.grow (i32.const 1))))"#,
+        &Imports::new(),
+    );
+    let Some(ExportValue::Memory(mem)) = inst.exports.get("mem") else {
+        panic!("expected an exported memory");
+    };
+    let (store, load, grow) = (func(&inst, "store"), func(&inst, "load"), func(&inst, "grow"));
+
+    mem.borrow_mut().set_readonly(true);
+    assert_eq!(inst.invoke(&store, &[]).err(), Some(Error::Trap("write to readonly memory")));
+    assert_eq!(mem.borrow_mut().write_bytes(0, &[1]), Err("write to readonly memory"));
+    assert_eq!(inst.invoke(&grow, &[]).unwrap()[0].as_i32(), -1);
+    assert_eq!(inst.invoke(&load, &[]).unwrap()[0].as_i32(), 0);
+
+    mem.borrow_mut().set_readonly(false);
+    assert!(inst.invoke(&store, &[]).is_ok());
+    assert_eq!(inst.invoke(&load, &[]).unwrap()[0].as_i32(), 7);
+}
+
+#[test]
+fn relaxed_div_by_zero_yields_zero() {
+    let inst = instantiate(
+        r#"(module
+            (func (export "div_s") (param i32 i32) (result i32) (i32.div_s (local.get 0) (local.get 1)))
+            (func (export "rem_u") (param i64 i64) (result i64) (i64.rem_u (local.get 0) (local.get 1))))"#,
+        &Imports::new(),
+    );
+    let (div_s, rem_u) = (func(&inst, "div_s"), func(&inst, "rem_u"));
+    let i32_args = |a, b| [WasmValue::from_i32(a), WasmValue::from_i32(b)];
+    let i64_args = [WasmValue::from_i64(5), WasmValue::from_i64(0)];
+    let div_by_zero = Some(Error::Trap("integer divide by zero"));
+
+    assert_eq!(inst.invoke(&div_s, &i32_args(5, 0)).err(), div_by_zero);
+    assert_eq!(inst.invoke(&rem_u, &i64_args).err(), div_by_zero);
+
+    inst.set_determinism(DeterminismConfig { relaxed_div_by_zero: true, ..Default::default() });
+    assert_eq!(inst.invoke(&div_s, &i32_args(5, 0)).unwrap()[0].as_i32(), 0);
+    assert_eq!(inst.invoke(&rem_u, &i64_args).unwrap()[0].as_i64(), 0);
+    assert_eq!(inst.invoke(&div_s, &i32_args(7, 2)).unwrap()[0].as_i32(), 3);
+    assert_eq!(
+        inst.invoke(&div_s, &i32_args(i32::MIN, -1)).err(),
+        Some(Error::Trap("integer overflow"))
+    );
+}
+
+#[test]
+fn invoke_into_reuses_the_callers_buffer() {
+    let inst = instantiate(
+        r#"(module
+            (func (export "add") (param i32 i32) (result i32) (i32.add (local.get 0) (local.get 1)))
+            (func (export "trap") (result i32) unreachable))"#,
+        &Imports::new(),
+    );
+    let (add, trap) = (func(&inst, "add"), func(&inst, "trap"));
+    let mut out = vec![WasmValue::from_i32(-1); 3];
+
+    inst.invoke_into(&add, &[WasmValue::from_i32(2), WasmValue::from_i32(3)], &mut out).unwrap();
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0].as_i32(), 5);
+
+    let capacity = out.capacity();
+    for i in 0..100 {
+        inst.invoke_into(&add, &[WasmValue::from_i32(i), WasmValue::from_i32(1)], &mut out)
+            .unwrap();
+        assert_eq!(out[0].as_i32(), i + 1);
+    }
+    assert_eq!(out.capacity(), capacity);
+
+    assert_eq!(inst.invoke_into(&trap, &[], &mut out), Err(Error::Trap("unreachable")));
+    assert!(out.is_empty());
+}
+
+#[test]
+fn element_offsets_near_u32_max_do_not_wrap() {
+    let link = |offset: i32, funcs: &str| {
+        let module = compile(&format!(
+            r#"(module (table 4 funcref) (func $f) (elem (i32.const {}) {}))"#,
+            offset, funcs
+        ));
+        Store::new().instantiate(Rc::new(module), &Imports::new()).err()
+    };
+    let does_not_fit = Some(Error::Link("elements segment does not fit"));
+
+    // -1 is u32::MAX: offset + 2 would wrap to 1 in u32 arithmetic
+    assert_eq!(link(-1, "$f $f"), does_not_fit);
+    assert_eq!(link(-1, "$f"), does_not_fit);
+    assert_eq!(link(-2, "$f $f"), does_not_fit);
+    assert_eq!(link(-1, ""), does_not_fit);
+    assert_eq!(link(2, "$f $f"), None);
+    assert_eq!(link(3, "$f $f"), does_not_fit);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "local index 1 out of frame range")]
+fn debug_builds_catch_out_of_frame_local_access() {
+    let mut module =
+        compile(r#"(module (func (export "get") (param i32) (result i32) local.get 0))"#);
+    // Retarget the validated local.get 0 at a local the frame doesn't have
+    let body = module.functions[0].body.clone();
+    let bytes = Rc::get_mut(&mut module.bytes).unwrap();
+    let at = body.clone().find(|&i| bytes[i] == 0x20 && bytes[i + 1] == 0x00).unwrap();
+    bytes[at + 1] = 0x01;
+
+    let inst = Store::new().instantiate(Rc::new(module), &Imports::new()).unwrap();
+    let _ = inst.invoke(&func(&inst, "get"), &[WasmValue::from_i32(1)]);
+}
+
+#[test]
+fn table_init_checks_both_ranges_before_writing() {
+    let store = Store::new();
+    let mut table = WasmTable::new(&store, 4, 4);
+    let segment: Vec<WasmValue> = (1..=3).map(|i| WasmValue::from_u64(i << 32 | 1)).collect();
+    let raw =
+        |table: &WasmTable| (0..4).map(|i| table.get(i).unwrap().as_u64()).collect::<Vec<_>>();
+    let oob = Err("out of bounds table access");
+
+    assert_eq!(table.init(2, &segment, 0, 3), oob);
+    assert_eq!(table.init(0, &segment, 1, 3), oob);
+    assert_eq!(raw(&table), vec![0; 4]);
+
+    assert!(table.init(1, &segment, 1, 2).is_ok());
+    assert_eq!(raw(&table), vec![0, 2 << 32 | 1, 3 << 32 | 1, 0]);
+    assert!(table.init(4, &segment, 3, 0).is_ok());
+}
+
+#[test]
+fn func_index_resolves_function_exports() {
+    let log = RuntimeFunction::new_host(vec![ValType::I32], None, |_| None);
+    let inst = instantiate(
+        r#"(module
+            (import "env" "log" (func (param i32)))
+            (memory (export "mem") 1)
+            (func $a (export "a"))
+            (func $b (export "b") (export "also_b") (result i32) (i32.const 2)))"#,
+        &imports_of("env", vec![("log", ExportValue::Function(log))]),
+    );
+    let pc_start = |f: &RuntimeFunction| match f {
+        RuntimeFunction::OwnedWasm { pc_start, .. } => *pc_start,
+        _ => panic!("expected an owned wasm function"),
+    };
+
+    assert_eq!(inst.func_index("a"), Some(1));
+    assert_eq!(inst.func_index("b"), Some(2));
+    assert_eq!(inst.func_index("also_b"), Some(2));
+    assert_eq!(pc_start(&inst.functions[2]), pc_start(&func(&inst, "b")));
+    assert_eq!(inst.func_index("mem"), None);
+    assert_eq!(inst.func_index("missing"), None);
+}
+
+#[test]
+fn applied_data_records_resolved_offsets() {
+    let base =
+        WasmGlobal { ty: ValType::I32, mutable: false, value: WasmValue::from_i32(1024).into() };
+    let inst = instantiate(
+        r#"(module
+            (import "env" "base" (global $base i32))
+            (memory 1)
+            (data (i32.const 16) "abc")
+            (data (global.get $base) "hello")
+            (data (i32.const 0) ""))"#,
+        &imports_of("env", vec![("base", ExportValue::Global(Rc::new(base)))]),
+    );
+    assert_eq!(inst.applied_data(), &[(16, 3), (1024, 5), (0, 0)]);
+    assert!(instantiate(r#"(module (memory 1))"#, &Imports::new()).applied_data().is_empty());
+}
+
+#[test]
+fn trap_state_captures_the_trapping_frame() {
+    let inst = instantiate(
+        r#"(module
+            (func $inner (param i32) (result i32) (local i64)
+                (local.set 1 (i64.const 9))
+                i32.const 7 i32.const 8 i32.const 100
+                (if (i32.eqz (local.get 0)) (then unreachable))
+                i32.add i32.add)
+            (func (export "outer") (param i32) (result i32)
+                (i32.add (i32.const 5) (call $inner (i32.sub (local.get 0) (i32.const 3))))))"#,
+        &Imports::new(),
+    );
+    let outer = func(&inst, "outer");
+    assert!(inst.invoke(&outer, &[WasmValue::from_i32(3)]).is_err());
+    assert!(inst.last_trap_state().is_none(), "capturing is opt-in");
+
+    inst.capture_trap_state(Some(2));
+    assert_eq!(inst.invoke(&outer, &[WasmValue::from_i32(4)]).unwrap()[0].as_i32(), 120);
+    assert!(inst.last_trap_state().is_none());
+
+    assert!(inst.invoke(&outer, &[WasmValue::from_i32(3)]).is_err());
+    let state = inst.last_trap_state().unwrap();
+    assert_eq!(state.call_depth, 2);
+    assert_eq!(state.locals.len(), 2);
+    assert_eq!(state.locals[0].as_i32(), 0);
+    assert_eq!(state.locals[1].as_i64(), 9);
+    let top: Vec<i32> = state.stack_top.iter().map(|v| v.as_i32()).collect();
+    assert_eq!(top, [8, 100]);
+
+    inst.capture_trap_state(None);
+    assert!(inst.last_trap_state().is_none());
+}
+
+#[test]
+fn host_calls_replay_from_a_recorded_log() {
+    let clock = Rc::new(Cell::new(1000i64));
+    let now = {
+        let clock = clock.clone();
+        RuntimeFunction::new_host(vec![], Some(ValType::I64), move |_| {
+            clock.set(clock.get() + 7);
+            Some(WasmValue::from_i64(clock.get()))
+        })
+    };
+    let inst = instantiate(
+        r#"(module
+            (import "env" "now" (func $now (result i64)))
+            (func (export "elapsed") (result i64)
+                (i64.sub (call $now) (call $now))))"#,
+        &imports_of("env", vec![("now", ExportValue::Function(now))]),
+    );
+    let elapsed = func(&inst, "elapsed");
+    let run = || inst.invoke(&elapsed, &[]).map(|r| r[0].as_i64());
+
+    inst.record_host_calls();
+    let recorded = run().unwrap();
+    let log = inst.take_host_log();
+    assert_eq!(log.iter().map(|v| v[0].as_i64()).collect::<Vec<_>>(), [1007, 1014]);
+
+    // The live clock has moved on, the replayed run sees the recorded times
+    clock.set(5000);
+    inst.replay_host_calls(log.clone());
+    assert_eq!(run().unwrap(), recorded);
+    assert_eq!(clock.get(), 5000);
+    assert!(inst.take_host_log().is_empty());
+
+    // A replay that runs out of entries diverges instead of calling the host
+    inst.replay_host_calls(log[..1].to_vec());
+    assert_eq!(run().err(), Some(Error::Trap("host call diverged from replay log")));
+    assert_eq!(clock.get(), 5000);
+
+    inst.take_host_log();
+    assert_eq!(run().unwrap(), -7);
+    assert_eq!(clock.get(), 5014);
+}
+
+#[test]
+fn replayed_host_results_are_checked_like_live_ones() {
+    let calls = Rc::new(Cell::new(0));
+    let wide = {
+        let calls = calls.clone();
+        RuntimeFunction::new_host(vec![], Some(ValType::I32), move |_| {
+            calls.set(calls.get() + 1);
+            WasmValue::from_u64(1 << 32 | calls.get() as u64)
+        })
+    };
+    let inst = instantiate(
+        r#"(module
+            (import "env" "wide" (func $wide (result i32)))
+            (func (export "sum") (result i32) (i32.add (call $wide) (call $wide))))"#,
+        &imports_of("env", vec![("wide", ExportValue::Function(wide))]),
+    );
+    let sum = func(&inst, "sum");
+    let run = || inst.invoke(&sum, &[]).map(|r| r[0].as_i32());
+
+    inst.record_host_calls();
+    assert_eq!(run(), Ok(3));
+    let log = inst.take_host_log();
+    assert_eq!(log.iter().map(Vec::len).collect::<Vec<_>>(), [1, 1]);
+    inst.replay_host_calls(log.clone());
+    assert_eq!(run(), Ok(3));
+    assert_eq!(calls.get(), 2);
+    assert!(inst.take_host_log().is_empty());
+
+    // The high bits are rejected in strict mode, live and replayed alike
+    inst.set_strict_host_results(true);
+    assert_eq!(run().err(), Some(Error::Trap("host result mismatch")));
+    inst.replay_host_calls(log);
+    assert_eq!(run().err(), Some(Error::Trap("host result mismatch")));
+    assert_eq!(calls.get(), 3);
+}
+
+#[test]
+fn call_indirect_compares_param_order() {
+    let inst = instantiate(
+        r#"(module
+            (type $if (func (param i32 f32) (result i32)))
+            (type $fi (func (param f32 i32) (result i32)))
+            (type $if_again (func (param i32 f32) (result i32)))
+            (table 2 funcref)
+            (elem (i32.const 0) $a $b)
+            (func $a (type $if) (local.get 0))
+            (func $b (type $fi) (local.get 1))
+            (func (export "call_if") (param i32) (result i32)
+                (call_indirect (type $if) (i32.const 5) (f32.const 1) (local.get 0)))
+            (func (export "call_if_again") (param i32) (result i32)
+                (call_indirect (type $if_again) (i32.const 6) (f32.const 1) (local.get 0))))"#,
+        &Imports::new(),
+    );
+    let call = |name, slot| inst.invoke(&func(&inst, name), &[WasmValue::from_i32(slot)]);
+    let mismatch = Some(Error::Trap("indirect call type mismatch"));
+
+    assert_eq!(call("call_if", 0).unwrap()[0].as_i32(), 5);
+    assert_eq!(call("call_if", 1).err(), mismatch);
+    // A separately declared but identical type still matches
+    assert_eq!(call("call_if_again", 0).unwrap()[0].as_i32(), 6);
+    assert_eq!(call("call_if_again", 1).err(), mismatch);
+}
+
+#[test]
+fn lenient_instantiation_reports_stubbed_imports() {
+    let module = Rc::new(compile(
+        r#"(module
+            (import "env" "present" (func $present (result i32)))
+            (import "env" "missing" (func $missing (param i64) (result i32)))
+            (import "host" "counter" (global $counter (mut i32)))
+            (import "host" "mem" (memory 1 2))
+            (func (export "present") (result i32) (call $present))
+            (func (export "missing") (result i32) (call $missing (i64.const 1)))
+            (func (export "counter") (result i32) (global.get $counter))
+            (func (export "pages") (result i32) (memory.size)))"#,
+    ));
+    let present =
+        RuntimeFunction::new_host(vec![], Some(ValType::I32), |_| Some(WasmValue::from_i32(3)));
+    let imports = imports_of("env", vec![("present", ExportValue::Function(present))]);
+    assert_eq!(
+        Store::new().instantiate(module.clone(), &imports).err(),
+        Some(Error::Link("unknown import"))
+    );
+
+    let (inst, stubbed) = Instance::instantiate_lenient(&Store::new(), module, &imports).unwrap();
+    let names: Vec<_> = stubbed.iter().map(|i| (i.module.as_str(), i.field.as_str())).collect();
+    assert_eq!(names, [("env", "missing"), ("host", "counter"), ("host", "mem")]);
+    assert_eq!(
+        stubbed[0].desc,
+        ExternDesc::Func(Signature { params: vec![ValType::I64], result: Some(ValType::I32) })
+    );
+    assert_eq!(stubbed[1].desc, ExternDesc::Global { ty: ValType::I32, mutable: true });
+    assert_eq!(stubbed[2].desc, ExternDesc::Memory { min: 1, max: 2 });
+
+    let call = |name| inst.invoke(&func(&inst, name), &[]);
+    assert_eq!(call("present").unwrap()[0].as_i32(), 3);
+    assert_eq!(call("missing").err(), Some(Error::Trap("function has no implementation")));
+    assert_eq!(call("counter").unwrap()[0].as_i32(), 0);
+    assert_eq!(call("pages").unwrap()[0].as_i32(), 1);
+}
+
+#[test]
+fn start_trap_can_hand_back_the_instance() {
+    let module = Rc::new(compile(
+        r#"(module
+            (global $progress (export "progress") (mut i32) (i32.const 0))
+            (memory (export "mem") 1)
+            (func $start
+                (global.set $progress (i32.const 42))
+                (i32.store8 (i32.const 3) (i32.const 7))
+                unreachable)
+            (start $start))"#,
+    ));
+    let trapped = Some(Error::Uninstantiable("unreachable"));
+    assert_eq!(Store::new().instantiate(module.clone(), &Imports::new()).err(), trapped);
+
+    let Err((err, Some(inst))) =
+        Instance::instantiate_capturing_start_trap(&Store::new(), module.clone(), &Imports::new())
+    else {
+        panic!("expected the start trap with its instance");
+    };
+    assert_eq!(Some(err), trapped);
+    assert_eq!(inst.globals[0].value.get().as_i32(), 42);
+    assert_eq!(inst.memory.as_ref().unwrap().borrow().load_u8(3, 0).unwrap(), 7);
+    // No funcrefs point into it, so the store kept no zombie and the handle is the only owner
+    assert_eq!(Rc::strong_count(&inst), 1);
+
+    // Failures before the start function runs have no instance to return
+    let unlinked = Rc::new(compile(r#"(module (import "env" "f" (func)))"#));
+    let Err((err, None)) =
+        Instance::instantiate_capturing_start_trap(&Store::new(), unlinked, &Imports::new())
+    else {
+        panic!("expected a link error without an instance");
+    };
+    assert_eq!(err, Error::Link("unknown import"));
+}
+
+#[test]
+fn unreferenced_start_trap_instance_releases_its_funcrefs() {
+    let store = Store::new();
+    let lib = Rc::new(instantiate_in(&store, r#"(module (func (export "f")))"#, &Imports::new()));
+    Instance::register_external_instance(&lib);
+    let RuntimeFunction::OwnedWasm { runtime_sig, sig, .. } = func(&lib, "f") else {
+        panic!("expected an owned wasm function");
+    };
+    let f = RuntimeFunction::ImportedWasm {
+        runtime_sig,
+        sig,
+        owner: Rc::downgrade(&lib),
+        function_index: 0,
+    };
+    let module = Rc::new(compile(
+        r#"(module
+            (import "lib" "f" (func $f))
+            (table 2 funcref)
+            (elem (i32.const 0) $f $f)
+            (func $start unreachable)
+            (start $start))"#,
+    ));
+
+    // The failed instance is dropped with nothing pointing into it, and so is its table
+    let imports = imports_of("lib", vec![("f", ExportValue::Function(f))]);
+    assert!(store.instantiate(module, &imports).is_err());
+    assert_eq!(store.instance_refcount(lib.id), 0);
+}
+
+#[test]
+fn mutated_modules_trap_instead_of_panicking() {
+    let src = r#"(module
+        (memory 1)
+        (global $g (mut i32) (i32.const 0))
+        (table 2 funcref)
+        (elem (i32.const 0) $inc $pick)
+        (func $inc (param i32) (result i32) (i32.add (local.get 0) (i32.const 1)))
+        (func $pick (param i32) (result i32)
+            (block $out
+                (block $zero (br_table $zero $out (local.get 0)))
+                (return (i32.const 10)))
+            (if (result i32) (local.get 0)
+                (then (call $inc (local.get 0)))
+                (else (i32.const 2))))
+        (func (export "run") (param i32) (result i32)
+            (global.set $g (call_indirect (param i32) (result i32) (local.get 0) (local.get 0)))
+            (i32.store (i32.const 8) (global.get $g))
+            (drop (select (i32.const 1) (i32.const 2) (local.get 0)))
+            (i32.load (i32.const 8))))"#;
+    let original = compile(src);
+    let code = original.functions[0].body.start..original.functions[2].body.end;
+
+    // xorshift, so failures reproduce
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    let mut ran = 0;
+    for _ in 0..4000 {
+        let mut bytes = original.bytes.to_vec();
+        for _ in 0..1 + next() % 3 {
+            let at = code.start + (next() as usize) % code.len();
+            bytes[at] = next() as u8;
+        }
+        let Ok(module) = Module::compile(bytes) else { continue };
+        // Only loops can spin forever, recursion ends at the call depth limit
+        let has_loop = (0..module.functions.len() as u32)
+            .filter_map(|i| module.instructions(i))
+            .any(|mut body| body.any(|instr| instr.opcode == 0x03));
+        if has_loop {
+            continue;
+        }
+        let Ok(inst) = Store::new().instantiate(Rc::new(module), &Imports::new()) else { continue };
+        let Some(ExportValue::Function(run)) = inst.exports.get("run") else { continue };
+        for arg in [0, 1, 5] {
+            let args = vec![WasmValue::from_i32(arg); run.param_count()];
+            let _ = inst.invoke(run, &args);
+        }
+        ran += 1;
+    }
+    assert!(ran > 100, "only {} mutants ran", ran);
+}
+
+#[test]
+fn function_handle_reaches_unexported_functions() {
+    let inst = instantiate(
+        r#"(module
+            (import "env" "seed" (func (result i32)))
+            (func $double (param i32) (result i32) (i32.mul (local.get 0) (i32.const 2)))
+            (func (export "run") (result i32) (call $double (call 0))))"#,
+        &imports_of(
+            "env",
+            vec![(
+                "seed",
+                ExportValue::Function(RuntimeFunction::new_host(
+                    vec![],
+                    Some(ValType::I32),
+                    |_| Some(WasmValue::from_i32(21)),
+                )),
+            )],
+        ),
+    );
+    let double = inst.function_handle(1).unwrap();
+    assert_eq!(double.param_count(), 1);
+    assert_eq!(inst.invoke(&double, &[WasmValue::from_i32(8)]).unwrap()[0].as_i32(), 16);
+
+    // Imports come first in the index space
+    let seed = inst.function_handle(0).unwrap();
+    assert_eq!(inst.invoke(&seed, &[]).unwrap()[0].as_i32(), 21);
+    assert!(inst.function_handle(3).is_none());
+}
+
+#[test]
+fn fuel_spans_invocations() {
+    // i32.const and end, two instructions per call
+    let inst = instantiate(
+        r#"(module (func (export "one") (result i32) (i32.const 1)))"#,
+        &Imports::new(),
+    );
+    let one = func(&inst, "one");
+    assert_eq!(inst.fuel(), None);
+
+    inst.set_fuel(5);
+    assert_eq!(inst.invoke(&one, &[]).unwrap()[0].as_i32(), 1);
+    assert_eq!(inst.invoke(&one, &[]).unwrap()[0].as_i32(), 1);
+    assert_eq!(inst.fuel(), Some(1));
+    assert!(matches!(inst.invoke(&one, &[]), Err(Error::Trap("out of fuel"))));
+    assert_eq!(inst.fuel(), Some(0));
+    assert!(matches!(inst.invoke(&one, &[]), Err(Error::Trap("out of fuel"))));
+
+    inst.clear_fuel();
+    assert_eq!(inst.invoke(&one, &[]).unwrap()[0].as_i32(), 1);
+    assert_eq!(inst.fuel(), None);
+}
+
+#[test]
+fn fuel_traps_a_tight_loop_after_exactly_the_budget() {
+    // Six instructions per iteration, the branch re-enters through the loop header
+    let inst = instantiate(
+        r#"(module
+            (global $n (export "n") (mut i32) (i32.const 0))
+            (func (export "spin")
+                (loop $l
+                    (global.set $n (i32.add (global.get $n) (i32.const 1)))
+                    (br $l))))"#,
+        &Imports::new(),
+    );
+    let spin = func(&inst, "spin");
+    for (fuel, iterations) in [(48, 8), (52, 8), (53, 9)] {
+        inst.globals[0].value.set(WasmValue::from_i32(0));
+        inst.set_fuel(fuel);
+        assert_eq!(inst.fuel_consumed(), 0);
+        assert!(matches!(inst.invoke(&spin, &[]), Err(Error::Trap("out of fuel"))));
+        assert_eq!(inst.fuel_consumed(), fuel);
+        assert_eq!(inst.globals[0].value.get().as_i32(), iterations);
+    }
+    inst.clear_fuel();
+    assert_eq!(inst.fuel_consumed(), 0);
+}
+
+#[test]
+fn bulk_memory_copy_and_fill_run_in_wasm() {
+    let inst = instantiate(
+        r#"(module
+            (memory (export "mem") 1)
+            (data (i32.const 0) "hello")
+            (func (export "copy") (param i32 i32 i32)
+                (memory.copy (local.get 0) (local.get 1) (local.get 2)))
+            (func (export "fill") (param i32 i32 i32)
+                (memory.fill (local.get 0) (local.get 1) (local.get 2)))
+            (func (export "load") (param i32) (result i32) (i32.load8_u (local.get 0))))"#,
+        &Imports::new(),
+    );
+    let call = |name, args: [i32; 3]| {
+        let args = args.map(WasmValue::from_i32);
+        inst.invoke(&func(&inst, name), &args).map(|_| ())
+    };
+    let bytes = |n: i32| -> Vec<u8> {
+        let load = func(&inst, "load");
+        (0..n)
+            .map(|i| inst.invoke(&load, &[WasmValue::from_i32(i)]).unwrap()[0].as_i32() as u8)
+            .collect()
+    };
+
+    call("copy", [1, 0, 5]).unwrap();
+    assert_eq!(bytes(6), b"hhello");
+    call("fill", [0, 0x2a, 2]).unwrap();
+    assert_eq!(bytes(6), b"**ello");
+    // The fill value is truncated to a byte
+    call("fill", [5, 0x121, 1]).unwrap();
+    assert_eq!(bytes(6), b"**ell!");
+
+    assert!(matches!(call("copy", [65535, 0, 2]), Err(Error::Trap("out of bounds memory access"))));
+    assert!(matches!(call("fill", [-1, 0, 1]), Err(Error::Trap("out of bounds memory access"))));
+    assert_eq!(bytes(6), b"**ell!");
+}
+
+#[test]
+fn passive_data_segments_feed_memory_init_until_dropped() {
+    let inst = instantiate(
+        r#"(module
+            (memory 1)
+            (data $active (i32.const 0) "xy")
+            (data $passive "hello")
+            (func (export "init") (param i32 i32 i32)
+                (memory.init $passive (local.get 0) (local.get 1) (local.get 2)))
+            (func (export "init_active") (param i32 i32 i32)
+                (memory.init $active (local.get 0) (local.get 1) (local.get 2)))
+            (func (export "drop") (data.drop $passive))
+            (func (export "load") (param i32) (result i32) (i32.load8_u (local.get 0))))"#,
+        &Imports::new(),
+    );
+    let call = |name, args: &[i32]| {
+        let args: Vec<WasmValue> = args.iter().map(|&a| WasmValue::from_i32(a)).collect();
+        inst.invoke(&func(&inst, name), &args).map(|_| ())
+    };
+    let bytes = |at: i32, n: i32| -> Vec<u8> {
+        let load = func(&inst, "load");
+        (at..at + n)
+            .map(|i| inst.invoke(&load, &[WasmValue::from_i32(i)]).unwrap()[0].as_i32() as u8)
+            .collect()
+    };
+    let oob = |r| matches!(r, Err(Error::Trap("out of bounds memory access")));
+
+    // Only the active segment is written at instantiation
+    assert_eq!(bytes(0, 8), b"xy\0\0\0\0\0\0");
+    assert_eq!(inst.applied_data(), &[(0, 2)]);
+
+    call("init", &[4, 1, 3]).unwrap();
+    assert_eq!(bytes(0, 8), b"xy\0\0ell\0");
+    assert!(oob(call("init", &[0, 3, 3])));
+    // Active segments are dropped once applied, empty inits still succeed
+    assert!(oob(call("init_active", &[0, 0, 1])));
+    call("init_active", &[0, 0, 0]).unwrap();
+
+    call("drop", &[]).unwrap();
+    call("drop", &[]).unwrap();
+    assert!(oob(call("init", &[0, 0, 1])));
+    call("init", &[0, 0, 0]).unwrap();
+    assert_eq!(bytes(0, 8), b"xy\0\0ell\0");
+}
+
+#[test]
+fn passive_element_segments_feed_table_init_until_dropped() {
+    let inst = instantiate(
+        r#"(module
+            (table 4 funcref)
+            (type $r (func (result i32)))
+            (func $one (result i32) (i32.const 1))
+            (func $two (result i32) (i32.const 2))
+            (elem $active (i32.const 0) $one)
+            (elem $passive func $one $two)
+            (func (export "init") (param i32 i32 i32)
+                (table.init $passive (local.get 0) (local.get 1) (local.get 2)))
+            (func (export "init_active") (param i32 i32 i32)
+                (table.init $active (local.get 0) (local.get 1) (local.get 2)))
+            (func (export "drop") (elem.drop $passive))
+            (func (export "call") (param i32) (result i32) (call_indirect (type $r) (local.get 0))))"#,
+        &Imports::new(),
+    );
+    let call = |name, args: &[i32]| {
+        let args: Vec<WasmValue> = args.iter().map(|&a| WasmValue::from_i32(a)).collect();
+        inst.invoke(&func(&inst, name), &args).map(|r| r.first().map(|v| v.as_i32()))
+    };
+    let oob = |r| matches!(r, Err(Error::Trap("out of bounds table access")));
+
+    // Only the active segment is written at instantiation
+    assert_eq!(call("call", &[0]), Ok(Some(1)));
+    assert_eq!(call("call", &[2]), Err(Error::Trap("uninitialized element")));
+
+    call("init", &[2, 0, 2]).unwrap();
+    assert_eq!(call("call", &[2]), Ok(Some(1)));
+    assert_eq!(call("call", &[3]), Ok(Some(2)));
+    assert!(oob(call("init", &[3, 0, 2])));
+    assert!(oob(call("init", &[0, 1, 2])));
+    // Active segments are dropped once applied, empty inits still succeed
+    assert!(oob(call("init_active", &[0, 0, 1])));
+    call("init_active", &[0, 0, 0]).unwrap();
+
+    call("drop", &[]).unwrap();
+    call("drop", &[]).unwrap();
+    assert!(oob(call("init", &[0, 0, 1])));
+    call("init", &[0, 0, 0]).unwrap();
+    assert_eq!(call("call", &[3]), Ok(Some(2)));
+}
+
+#[test]
+fn passive_element_segments_count_as_funcref_holders() {
+    let store = Store::new();
+    let lib = Rc::new(instantiate_in(
+        &store,
+        r#"(module (func (export "f") (result i32) (i32.const 5)))"#,
+        &Imports::new(),
+    ));
+    Instance::register_external_instance(&lib);
+    let RuntimeFunction::OwnedWasm { runtime_sig, sig, .. } = func(&lib, "f") else {
+        panic!("expected an owned wasm function");
+    };
+    let f = RuntimeFunction::ImportedWasm {
+        runtime_sig,
+        sig,
+        owner: Rc::downgrade(&lib),
+        function_index: 0,
+    };
+    let user = instantiate_in(
+        &store,
+        r#"(module
+            (import "lib" "f" (func $f (result i32)))
+            (table 1 funcref)
+            (elem $passive func $f)
+            (func (export "drop") (elem.drop $passive)))"#,
+        &imports_of("lib", vec![("f", ExportValue::Function(f))]),
+    );
+
+    // The segment keeps the owner counted until elem.drop releases it
+    assert_eq!(store.instance_refcount(lib.id), 1);
+    user.invoke(&func(&user, "drop"), &[]).unwrap();
+    assert_eq!(store.instance_refcount(lib.id), 0);
+}
+
+#[test]
+fn table_instructions_grow_and_store_elements() {
+    let inst = instantiate(
+        r#"(module
+            (type $r (func (result i32)))
+            (table $t 2 funcref)
+            (elem (i32.const 0) $seven)
+            (func $seven (result i32) (i32.const 7))
+            (func (export "size") (result i32) (table.size $t))
+            (func (export "grow") (param i32) (result i32)
+                (table.grow $t (table.get $t (i32.const 0)) (local.get 0)))
+            (func (export "copy") (param i32 i32) (table.set $t (local.get 1) (table.get $t (local.get 0))))
+            (func (export "clear") (param i32 i32) (local funcref)
+                (table.fill $t (local.get 0) (local.get 2) (local.get 1)))
+            (func (export "call") (param i32) (result i32) (call_indirect (type $r) (local.get 0))))"#,
+        &Imports::new(),
+    );
+    let call = |name, args: &[i32]| {
+        let args: Vec<WasmValue> = args.iter().map(|&a| WasmValue::from_i32(a)).collect();
+        inst.invoke(&func(&inst, name), &args).map(|r| r.first().map(|v| v.as_i32()))
+    };
+    let trap = |r, msg| matches!(r, Err(Error::Trap(m)) if m == msg);
+
+    assert_eq!(call("size", &[]).unwrap(), Some(2));
+    // Grown slots are filled with the given element, the old size comes back
+    assert_eq!(call("grow", &[3]).unwrap(), Some(2));
+    assert_eq!(call("size", &[]).unwrap(), Some(5));
+    assert_eq!(call("call", &[4]).unwrap(), Some(7));
+    assert_eq!(call("grow", &[0]).unwrap(), Some(5));
+    assert_eq!(call("grow", &[-1]).unwrap(), Some(-1));
+
+    assert!(trap(call("call", &[1]), "uninitialized element"));
+    call("copy", &[0, 1]).unwrap();
+    assert_eq!(call("call", &[1]).unwrap(), Some(7));
+
+    call("clear", &[1, 3]).unwrap();
+    assert!(trap(call("call", &[1]), "uninitialized element"));
+    assert!(trap(call("call", &[3]), "uninitialized element"));
+    assert_eq!(call("call", &[4]).unwrap(), Some(7));
+
+    assert!(trap(call("copy", &[5, 0]), "out of bounds table access"));
+    assert!(trap(call("copy", &[0, 5]), "out of bounds table access"));
+    assert!(trap(call("clear", &[4, 2]), "out of bounds table access"));
+    assert_eq!(call("call", &[4]).unwrap(), Some(7));
+}
+
+#[test]
+fn table_fill_and_grow_respect_bounds() {
+    let store = Store::new();
+    let mut table = WasmTable::new(&store, 2, 4);
+    let raw = |table: &WasmTable| {
+        (0..table.size()).map(|i| table.get(i).unwrap().as_u64()).collect::<Vec<_>>()
+    };
+    let oob = Err("out of bounds table access");
+    let value = WasmValue::from_u64(1 << 32 | 1);
+
+    assert_eq!(table.fill(1, value, 2), oob);
+    assert_eq!(table.fill(u32::MAX, value, 2), oob);
+    assert_eq!(raw(&table), vec![0; 2]);
+    assert!(table.fill(1, value, 1).is_ok());
+    assert!(table.fill(2, value, 0).is_ok());
+    assert_eq!(raw(&table), vec![0, 1 << 32 | 1]);
+
+    assert_eq!(table.grow(3, value), u32::MAX);
+    assert_eq!(table.grow(2, WasmValue::from_u64(0)), 2);
+    assert_eq!(raw(&table), vec![0, 1 << 32 | 1, 0, 0]);
+
+    // Only the declared maximum limits growth
+    let mut unbounded = WasmTable::new(&store, 0, u32::MAX);
+    assert_eq!(unbounded.grow(10_000_001, WasmValue::from_u64(0)), 0);
+    assert_eq!(unbounded.size(), 10_000_001);
+}
+
+#[test]
+fn func_ref_handles_can_populate_tables_at_runtime() {
+    let store = Store::new();
+    let provider = Rc::new(instantiate_in(
+        &store,
+        r#"(module (func (export "seven") (result i32) (i32.const 7)))"#,
+        &Imports::new(),
+    ));
+    Instance::register_external_instance(&provider);
+    let RuntimeFunction::OwnedWasm { runtime_sig, sig, .. } = func(&provider, "seven") else {
+        panic!("expected an owned wasm function");
+    };
+    let seven = RuntimeFunction::ImportedWasm {
+        runtime_sig,
+        sig,
+        owner: Rc::downgrade(&provider),
+        function_index: 0,
+    };
+    let inst = Rc::new(instantiate_in(
+        &store,
+        r#"(module
+            (import "p" "seven" (func $seven (result i32)))
+            (type $r (func (result i32)))
+            (table (export "table") 2 funcref)
+            (memory (export "mem") 1)
+            (func (export "forty_two") (result i32) (i32.const 42))
+            (export "seven" (func $seven))
+            (func (export "dispatch") (param i32) (result i32)
+                (call_indirect (type $r) (local.get 0))))"#,
+        &imports_of("p", vec![("seven", ExportValue::Function(seven))]),
+    ));
+    Instance::register_external_instance(&inst);
+    let Some(ExportValue::Table(table)) = inst.exports.get("table") else { panic!() };
+
+    let own = inst.func_ref_handle("forty_two").unwrap();
+    assert_eq!(own, ((inst.id as u64) << 32) | 2);
+    // A re-exported import resolves to the instance that defines it
+    let reexported = inst.func_ref_handle("seven").unwrap();
+    assert_eq!(reexported >> 32, provider.id as u64);
+    table.borrow_mut().set(0, WasmValue::from_u64(own)).unwrap();
+    table.borrow_mut().set(1, WasmValue::from_u64(reexported)).unwrap();
+
+    let dispatch = func(&inst, "dispatch");
+    assert_eq!(inst.invoke(&dispatch, &[WasmValue::from_i32(0)]).unwrap()[0].as_i32(), 42);
+    assert_eq!(inst.invoke(&dispatch, &[WasmValue::from_i32(1)]).unwrap()[0].as_i32(), 7);
+    assert_eq!(inst.func_ref_handle("mem"), None);
+    assert_eq!(inst.func_ref_handle("missing"), None);
+}
+
+#[test]
+fn maximum_declared_limits_allocate_only_the_minimum() {
+    let inst = instantiate(
+        r#"(module
+            (memory (export "mem") 1 65536)
+            (table $t (export "table") 1 0xffffffff funcref)
+            (func (export "grow_memory") (param i32) (result i32) (memory.grow (local.get 0)))
+            (func (export "grow_table") (param i32) (result i32)
+                (table.grow $t (table.get $t (i32.const 0)) (local.get 0))))"#,
+        &Imports::new(),
+    );
+    let (Some(ExportValue::Memory(mem)), Some(ExportValue::Table(table))) =
+        (inst.exports.get("mem"), inst.exports.get("table"))
+    else {
+        panic!("expected memory and table exports");
+    };
+    assert_eq!((mem.borrow().size(), mem.borrow().max()), (1, 65536));
+    assert_eq!(table.borrow().size(), 1);
+
+    let grow = |name: &str, delta: u32| {
+        inst.invoke(&func(&inst, name), &[WasmValue::from_u32(delta)]).unwrap()[0].as_i32()
+    };
+    // Past the declared maximum
+    assert_eq!(grow("grow_memory", 65536), -1);
+    assert_eq!(grow("grow_memory", u32::MAX), -1);
+    assert_eq!(grow("grow_table", u32::MAX), -1);
+    assert_eq!((mem.borrow().size(), table.borrow().size()), (1, 1));
+
+    assert_eq!(grow("grow_memory", 1), 1);
+    assert_eq!(grow("grow_table", 3), 1);
+    assert_eq!((mem.borrow().size(), table.borrow().size()), (2, 4));
+}
+
+#[test]
+fn eval_expr_runs_straight_line_code_against_inputs() {
+    let inst = instantiate(
+        r#"(module (memory 1) (global (mut i32) (i32.const 10)) (data (i32.const 4) "\2a"))"#,
+        &Imports::new(),
+    );
+    let inputs = [WasmValue::from_i32(40), WasmValue::from_i32(2)];
+
+    // local.get 0; local.get 1; i32.add; end
+    let sum = inst.eval_expr(&[0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b], &inputs).unwrap();
+    assert_eq!(sum.iter().map(|v| v.as_i32()).collect::<Vec<_>>(), [42]);
+
+    // i32.const 4; i32.load8_u; global.get 0; i32.mul; local.get 1; end
+    let values = inst
+        .eval_expr(&[0x41, 0x04, 0x2d, 0x00, 0x00, 0x23, 0x00, 0x6c, 0x20, 0x01, 0x0b], &inputs)
+        .unwrap();
+    assert_eq!(values.iter().map(|v| v.as_i32()).collect::<Vec<_>>(), [420, 2]);
+    // i32.const 7; global.set 0; end
+    assert!(inst.eval_expr(&[0x41, 0x07, 0x24, 0x00, 0x0b], &[]).unwrap().is_empty());
+    assert_eq!(inst.globals[0].value.get().as_i32(), 7);
+    // local.get 0; local.get 1; i32.div_s; end
+    let div = [0x20, 0x00, 0x20, 0x01, 0x6d, 0x0b];
+    let zero = [WasmValue::from_i32(1), WasmValue::from_i32(0)];
+    assert_eq!(inst.eval_expr(&div, &zero).err(), Some(Error::Trap("integer divide by zero")));
+
+    let rejected = |bytes: &[u8]| inst.eval_expr(bytes, &inputs).err();
+    // block end end, call 0 end
+    let straight_line = Some(Error::Validation("straight-line expression required"));
+    assert_eq!(rejected(&[0x02, 0x40, 0x0b, 0x0b]), straight_line);
+    assert_eq!(rejected(&[0x10, 0x00, 0x0b]), straight_line);
+    assert_eq!(rejected(&[0x20, 0x00, 0x6a, 0x0b]), Some(Error::Validation("type mismatch")));
+    assert_eq!(rejected(&[0x20, 0x02, 0x0b]), Some(Error::Validation("unknown local")));
+    assert_eq!(rejected(&[0x20, 0x00]), Some(Error::Malformed("END opcode expected")));
+}
+
+#[test]
+fn state_equal_compares_memory_and_globals() {
+    let module = Rc::new(compile(
+        r#"(module
+            (memory 1)
+            (global $g (mut i64) (i64.const 0))
+            (func (export "step") (param i32)
+                (i32.store (local.get 0) (i32.add (i32.load (local.get 0)) (i32.const 3)))
+                (global.set $g (i64.add (global.get $g) (i64.extend_i32_u (local.get 0))))))"#,
+    ));
+    let a = Store::new().instantiate(module.clone(), &Imports::new()).unwrap();
+    let b = Store::new().instantiate(module, &Imports::new()).unwrap();
+    let step = |inst: &Instance, addr: i32| {
+        inst.invoke(&func(inst, "step"), &[WasmValue::from_i32(addr)]).unwrap();
+    };
+    for addr in [0, 8, 8, 100] {
+        step(&a, addr);
+        step(&b, addr);
+    }
+    assert!(a.state_equal(&b));
+
+    // Same global total, different memory
+    step(&a, 4);
+    step(&b, 2);
+    step(&b, 2);
+    assert!(!a.state_equal(&b));
+    assert_eq!(a.globals[0].value.get().as_i64(), b.globals[0].value.get().as_i64());
+
+    let c = instantiate(r#"(module (memory 1) (global (mut i64) (i64.const 0)))"#, &Imports::new());
+    let d = instantiate(r#"(module (memory 1) (global (mut i64) (i64.const 1)))"#, &Imports::new());
+    let e = instantiate(r#"(module (global (mut i64) (i64.const 0)))"#, &Imports::new());
+    assert!(!c.state_equal(&d));
+    assert!(!c.state_equal(&e));
+    assert!(c.state_equal(&c));
+}
+
+#[test]
+fn traps_in_indirectly_called_foreign_functions_propagate() {
+    // The table's funcrefs dispatch through the calling instance's store, so both share one
+    let store = Store::new();
+    let lib = Rc::new(instantiate_in(
+        &store,
+        r#"(module
+            (table (export "table") 2 funcref)
+            (elem (i32.const 0) $div)
+            (func $div (param i32) (result i32) (i32.div_s (i32.const 100) (local.get 0))))"#,
+        &Imports::new(),
+    ));
+    Instance::register_external_instance(&lib);
+    let app = Rc::new(instantiate_in(
+        &store,
+        r#"(module
+            (import "lib" "table" (table 2 funcref))
+            (type $unary (func (param i32) (result i32)))
+            (type $nullary (func (result i32)))
+            (func (export "apply") (param i32) (result i32)
+                (call_indirect (type $unary) (local.get 0) (i32.const 0)))
+            (func (export "mistyped") (result i32) (call_indirect (type $nullary) (i32.const 0)))
+            (func (export "slot") (param i32) (result i32)
+                (call_indirect (type $unary) (i32.const 1) (local.get 0))))"#,
+        &imports_of("lib", vec![("table", lib.exports["table"].clone())]),
+    ));
+    Instance::register_external_instance(&app);
+    let call = |name: &str, args: &[WasmValue]| {
+        app.invoke(&func(&app, name), args).map(|r| r[0].as_i32()).err()
+    };
+
+    assert_eq!(call("apply", &[WasmValue::from_i32(4)]), None);
+    assert_eq!(
+        call("apply", &[WasmValue::from_i32(0)]),
+        Some(Error::Trap("integer divide by zero"))
+    );
+    assert_eq!(call("mistyped", &[]), Some(Error::Trap("indirect call type mismatch")));
+    assert_eq!(call("slot", &[WasmValue::from_i32(1)]), Some(Error::Trap("uninitialized element")));
+    // A handle naming a function its owner doesn't have
+    let ExportValue::Table(table) = &lib.exports["table"] else { panic!() };
+    table.borrow_mut().set(1, WasmValue::from_u64(((lib.id as u64) << 32) | 99)).unwrap();
+    assert_eq!(call("slot", &[WasmValue::from_i32(1)]), Some(Error::Trap("undefined element")));
+}
+
+#[test]
+fn tables_and_functions_from_another_store_are_rejected() {
+    // Both stores number their instances from 1, so lib and app share an id
+    let lib = Rc::new(instantiate(
+        r#"(module
+            (table (export "table") 1 funcref)
+            (elem (i32.const 0) $lib_f)
+            (func $lib_f (export "f") (result i32) (i32.const 111)))"#,
+        &Imports::new(),
+    ));
+    Instance::register_external_instance(&lib);
+    let app_src = r#"(module
+        (import "lib" "table" (table 1 funcref))
+        (type $r (func (result i32)))
+        (func (result i32) (i32.const 222))
+        (func (export "call") (result i32) (call_indirect (type $r) (i32.const 0))))"#;
+    let table = imports_of("lib", vec![("table", lib.exports["table"].clone())]);
+    let app = Store::new().instantiate(Rc::new(compile(app_src)), &table);
+    assert_eq!(app.err(), Some(Error::Link("incompatible import type")));
+    // The same module links and dispatches to lib's function within lib's store
+    let app = Rc::new(instantiate_in(lib.store(), app_src, &table));
+    assert_eq!(app.invoke(&func(&app, "call"), &[]).unwrap()[0].as_i32(), 111);
+
+    let RuntimeFunction::OwnedWasm { runtime_sig, sig, .. } = func(&lib, "f") else {
+        panic!("expected an owned wasm function");
+    };
+    let f = RuntimeFunction::ImportedWasm {
+        runtime_sig,
+        sig,
+        owner: Rc::downgrade(&lib),
+        function_index: 0,
+    };
+    let src = r#"(module (import "lib" "f" (func (result i32))))"#;
+    let imports = imports_of("lib", vec![("f", ExportValue::Function(f))]);
+    let err = Store::new().instantiate(Rc::new(compile(src)), &imports).err();
+    assert_eq!(err, Some(Error::Link("incompatible import type")));
+    assert!(lib.store().instantiate(Rc::new(compile(src)), &imports).is_ok());
+}
+
+#[test]
+fn truncated_or_altered_initializers_fail_to_instantiate() {
+    // The initializer under test is the last thing in each module, so cutting the
+    // module anywhere inside it leaves a truncated constant expression
+    for init in [
+        "(i32.const -1000000)",
+        "(i64.const 0x7fffffffffffffff)",
+        "(f32.const 1.5)",
+        "(f64.const -2.25)",
+        "(i64.mul (i64.const 6) (i64.sub (i64.const 10) (i64.const 3)))",
+    ] {
+        let ty = &init[1..4];
+        let compiled =
+            compile(&format!("(module (global i32 (i32.const 7)) (global {ty} {init}))"));
+        let offset = compiled.globals[1].initializer_offset;
+        let bytes = compiled.bytes.clone();
+        assert_eq!(bytes.last(), Some(&0x0b));
+        assert!(Store::new().instantiate(Rc::new(compiled), &Imports::new()).is_ok());
+
+        for cut in offset..bytes.len() {
+            let mut module =
+                compile(&format!("(module (global i32 (i32.const 7)) (global {ty} {init}))"));
+            module.bytes = Rc::new(bytes[..cut].to_vec());
+            let result = Store::new().instantiate(Rc::new(module), &Imports::new());
+            assert!(result.is_err(), "{init} cut at {cut} instantiated");
+        }
+    }
+
+    // Initializers rewritten to leave nothing on the stack
+    for replacement in [0x0b, 0x6a] {
+        let mut module = compile("(module (global i32 (i32.const 7)))");
+        let offset = module.globals[0].initializer_offset;
+        Rc::make_mut(&mut module.bytes)[offset] = replacement;
+        let result = Store::new().instantiate(Rc::new(module), &Imports::new());
+        assert_eq!(result.err(), Some(Error::Validation("type mismatch")));
+    }
+}
+
+#[test]
+fn memory_imports_without_a_max_accept_any_provided_max() {
+    let link = |import: &str, max| {
+        let module = Rc::new(compile(&format!(
+            r#"(module (import "env" "mem" {import}) (func (export "pages") (result i32) (memory.size)))"#
+        )));
+        let memory = Rc::new(RefCell::new(WasmMemory::new(2, max)));
+        let imports = imports_of("env", vec![("mem", ExportValue::Memory(memory))]);
+        Store::new().instantiate(module, &imports)
+    };
+
+    for max in [2, 3, 100, WasmMemory::MAX_PAGES] {
+        let inst = link("(memory 1)", max).unwrap();
+        assert_eq!(inst.invoke(&func(&inst, "pages"), &[]).unwrap()[0].as_i32(), 2);
+    }
+    assert!(link("(memory 1 3)", 3).is_ok());
+    assert_eq!(link("(memory 1 3)", 4).err(), Some(Error::Link("incompatible import type")));
+    assert_eq!(link("(memory 3)", 100).err(), Some(Error::Link("incompatible import type")));
+}
+
+#[test]
+fn typed_funcs_check_the_signature_at_lookup() {
+    let inst = instantiate(
+        r#"(module
+            (memory (export "mem") 1)
+            (func (export "add") (param i32 i32) (result i32) (i32.add (local.get 0) (local.get 1)))
+            (func (export "mix") (param i64 f32 f64) (result f64)
+                (f64.add (f64.convert_i64_s (local.get 0))
+                    (f64.add (f64.promote_f32 (local.get 1)) (local.get 2))))
+            (func (export "answer") (result i32) (i32.const 42))
+            (func (export "nothing"))
+            (func (export "trap") (param i32) unreachable))"#,
+        &Imports::new(),
+    );
+
+    let add = inst.get_typed_func::<(i32, i32), i32>("add").unwrap();
+    assert_eq!(add.call((40, 2)).unwrap(), 42);
+    assert_eq!(add.call((i32::MAX, 1)).unwrap(), i32::MIN);
+    let mix = inst.get_typed_func::<(i64, f32, f64), f64>("mix").unwrap();
+    assert_eq!(mix.call((1, 0.5, 0.25)).unwrap(), 1.75);
+    assert_eq!(inst.get_typed_func::<(), i32>("answer").unwrap().call(()).unwrap(), 42);
+    inst.get_typed_func::<(), ()>("nothing").unwrap().call(()).unwrap();
+    let trap = inst.get_typed_func::<i32, ()>("trap").unwrap();
+    assert_eq!(trap.call(0).err(), Some(Error::Trap("unreachable")));
+
+    let incompatible = Some(Error::Link("incompatible export type"));
+    // Same types in another order, wrong arity and wrong result all fail before any call
+    assert_eq!(inst.get_typed_func::<(f32, i64, f64), f64>("mix").err(), incompatible);
+    assert_eq!(inst.get_typed_func::<i32, i32>("add").err(), incompatible);
+    assert_eq!(inst.get_typed_func::<(i32, i32), i64>("add").err(), incompatible);
+    assert_eq!(inst.get_typed_func::<(i32, i32), ()>("add").err(), incompatible);
+    assert_eq!(inst.get_typed_func::<(), ()>("mem").err(), incompatible);
+    assert_eq!(inst.get_typed_func::<(), ()>("missing").err(), Some(Error::Link("unknown export")));
+}
+
+#[test]
+fn typed_funcs_report_a_missing_host_result() {
+    let none = RuntimeFunction::new_host(vec![], Some(ValType::I32), |_| None::<WasmValue>);
+    let inst = instantiate(
+        r#"(module (import "env" "none" (func $none (result i32))) (export "none" (func $none)))"#,
+        &imports_of("env", vec![("none", ExportValue::Function(none))]),
+    );
+    let typed = inst.get_typed_func::<(), i32>("none").unwrap();
+    assert_eq!(typed.call(()).err(), Some(Error::Trap("host result mismatch")));
+}
+
+#[test]
+fn invoke_rejects_64_bit_values_for_32_bit_signatures() {
+    let inst = instantiate(
+        r#"(module
+            (func (export "add") (param i32 i32) (result i32) (i32.add (local.get 0) (local.get 1)))
+            (func (export "scale") (param f32) (result f32) (f32.mul (local.get 0) (f32.const 2)))
+            (func (export "wide") (param i32 i64) (result i64)
+                (i64.add (i64.extend_i32_u (local.get 0)) (local.get 1)))
+            (func (export "takes_ref") (param funcref) (result i32) (i32.const 1)))"#,
+        &Imports::new(),
+    );
+    let call =
+        |name, args: &[WasmValue]| inst.invoke(&func(&inst, name), args).map(|r| r[0].as_u64());
+    let mismatch = Some(Error::Trap("argument type mismatch"));
+
+    assert_eq!(call("add", &[WasmValue::from_i32(-1), WasmValue::from_i32(2)]).unwrap(), 1);
+    assert_eq!(call("add", &[WasmValue::from_f64(1.0), WasmValue::from_i32(2)]).err(), mismatch);
+    assert_eq!(call("add", &[WasmValue::from_i64(-1), WasmValue::from_i32(2)]).err(), mismatch);
+    assert_eq!(call("scale", &[WasmValue::from_f64(1.5)]).err(), mismatch);
+    assert_eq!(call("scale", &[WasmValue::from_f32(1.5)]).unwrap(), 3.0f32.to_bits() as u64);
+
+    // Checked slot by slot, 64-bit and reference parameters take any value
+    let wide = [WasmValue::from_i32(1), WasmValue::from_i64(1 << 40)];
+    assert_eq!(call("wide", &wide).unwrap(), (1 << 40) + 1);
+    let swapped = [WasmValue::from_i64(1 << 40), WasmValue::from_i32(1)];
+    assert_eq!(call("wide", &swapped).err(), mismatch);
+    assert_eq!(call("takes_ref", &[WasmValue::from_u64(1 << 32 | 1)]).unwrap(), 1);
+}
+
+#[test]
+fn memory_read_and_write_go_through_the_instance() {
+    let inst = instantiate(
+        r#"(module
+            (memory 1)
+            (data (i32.const 8) "wagmi")
+            (func (export "sum") (param i32 i32) (result i32)
+                (i32.add (i32.load8_u (local.get 0)) (i32.load8_u (local.get 1)))))"#,
+        &Imports::new(),
+    );
+    assert_eq!(inst.memory_read(8, 5).unwrap(), b"wagmi");
+    inst.memory_write(100, &[3, 4]).unwrap();
+    let args = [WasmValue::from_i32(100), WasmValue::from_i32(101)];
+    assert_eq!(inst.invoke(&func(&inst, "sum"), &args).unwrap()[0].as_i32(), 7);
+
+    let oob = Some(Error::Trap("out of bounds memory access"));
+    assert_eq!(inst.memory_read(65535, 2).err(), oob);
+    assert_eq!(inst.memory_write(65535, &[1, 2]).err(), oob);
+    // A failed write leaves memory untouched
+    assert_eq!(inst.memory_read(65535, 1).unwrap(), [0]);
+    assert!(inst.memory_read(65536, 0).unwrap().is_empty());
+
+    let no_memory = instantiate("(module)", &Imports::new());
+    assert_eq!(no_memory.memory_read(0, 1).err(), Some(Error::Link("unknown memory")));
+    assert_eq!(no_memory.memory_write(0, &[]).err(), Some(Error::Link("unknown memory")));
+}
+
+#[test]
+fn cancel_flag_stops_a_runaway_invocation() {
+    let inst = instantiate(
+        r#"(module
+            (func (export "spin") (loop $l (br $l)))
+            (func (export "quick") (result i32) (i32.const 7)))"#,
+        &Imports::new(),
+    );
+    let flag = Arc::new(AtomicBool::new(false));
+    inst.set_cancel_flag(flag.clone());
+    assert_eq!(inst.invoke(&func(&inst, "quick"), &[]).unwrap()[0].as_i32(), 7);
+
+    let watchdog = {
+        let flag = flag.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            flag.store(true, Ordering::Relaxed);
+        })
+    };
+    let started = Instant::now();
+    assert_eq!(inst.invoke(&func(&inst, "spin"), &[]).err(), Some(Error::Trap("cancelled")));
+    assert!(started.elapsed() < Duration::from_secs(5));
+    watchdog.join().unwrap();
+
+    // Still set, so the next long enough run is cancelled too, until the flag is cleared
+    assert_eq!(inst.invoke(&func(&inst, "spin"), &[]).err(), Some(Error::Trap("cancelled")));
+    flag.store(false, Ordering::Relaxed);
+    inst.clear_cancel_flag();
+    inst.set_fuel(10_000);
+    assert_eq!(inst.invoke(&func(&inst, "spin"), &[]).err(), Some(Error::Trap("out of fuel")));
+}
+
+#[test]
+fn exported_globals_can_be_read_and_set_by_name() {
+    let inst = instantiate(
+        r#"(module
+            (global (export "limit") (mut i32) (i32.const 10))
+            (global (export "version") i64 (i64.const 3))
+            (memory (export "mem") 1)
+            (func (export "get_limit") (result i32) (global.get 0)))"#,
+        &Imports::new(),
+    );
+    assert_eq!(inst.get_global("limit").unwrap().as_i32(), 10);
+    assert_eq!(inst.get_global("version").unwrap().as_i64(), 3);
+    assert!(inst.get_global("mem").is_none());
+    assert!(inst.get_global("missing").is_none());
+
+    inst.set_global("limit", WasmValue::from_i32(25)).unwrap();
+    assert_eq!(inst.invoke(&func(&inst, "get_limit"), &[]).unwrap()[0].as_i32(), 25);
+    inst.set_global("limit", WasmValue::from_i32(-1)).unwrap();
+
+    // A 64-bit value doesn't fit the i32 global and leaves it unchanged
+    let wide = inst.set_global("limit", WasmValue::from_u64(1 << 32 | 7)).err();
+    assert_eq!(wide, Some(Error::Trap("argument type mismatch")));
+    assert_eq!(inst.get_global("limit").unwrap().as_i32(), -1);
+
+    let set = |name| inst.set_global(name, WasmValue::from_i64(4)).err();
+    assert_eq!(set("version"), Some(Error::Link("global is immutable")));
+    assert_eq!(inst.get_global("version").unwrap().as_i64(), 3);
+    assert_eq!(set("mem"), Some(Error::Link("incompatible export type")));
+    assert_eq!(set("missing"), Some(Error::Link("unknown export")));
+}
+
+#[test]
+fn function_handles_report_their_declared_signature() {
+    let exporter = Rc::new(instantiate(
+        r#"(module
+            (func (export "mix") (param i32 f64 i64) (result f32) (f32.const 0))
+            (func (export "none")))"#,
+        &Imports::new(),
+    ));
+    let mix = func(&exporter, "mix");
+    assert_eq!(mix.param_types(), [ValType::I32, ValType::F64, ValType::I64]);
+    assert_eq!(mix.result_type(), Some(ValType::F32));
+    assert!(func(&exporter, "none").param_types().is_empty());
+    assert_eq!(func(&exporter, "none").result_type(), None);
+
+    let host = RuntimeFunction::new_host(vec![ValType::F32, ValType::I32], None, |_| ());
+    assert_eq!(host.param_types(), [ValType::F32, ValType::I32]);
+    assert_eq!(host.result_type(), None);
+
+    // Imports are checked against the declared order, not just the types that occur
+    let import = |params: &str| {
+        let src = format!(r#"(module (import "env" "f" (func (param {params}))))"#);
+        let imports = imports_of("env", vec![("f", ExportValue::Function(host.clone()))]);
+        Store::new().instantiate(Rc::new(compile(&src)), &imports).err()
+    };
+    assert_eq!(import("f32 i32"), None);
+    assert_eq!(import("i32 f32"), Some(Error::Link("incompatible import type")));
+}
@@ -0,0 +1,38 @@
+// The limit is process-wide, so it lives in its own test binary to keep other
+// tests from allocating against it concurrently.
+mod common;
+
+use std::rc::Rc;
+
+use common::{compile, func, instantiate};
+use wagmi::{set_global_memory_limit, Error, Imports, Store, WasmMemory, WasmValue};
+
+#[test]
+fn global_memory_limit_caps_all_instances() {
+    let page = WasmMemory::PAGE_SIZE as u64;
+    set_global_memory_limit(3 * page);
+
+    let module = Rc::new(compile(
+        r#"(module (memory 1 4)
+            (func (export "grow") (param i32) (result i32) (memory.grow (local.get 0))))"#,
+    ));
+    let first = Store::new().instantiate(module.clone(), &Imports::new()).unwrap();
+    let second = Store::new().instantiate(module.clone(), &Imports::new()).unwrap();
+
+    // One page left: growing by two fails, by one succeeds and exhausts the budget
+    let grow = func(&first, "grow");
+    assert_eq!(first.invoke(&grow, &[WasmValue::from_i32(2)]).unwrap()[0].as_i32(), -1);
+    assert_eq!(first.invoke(&grow, &[WasmValue::from_i32(1)]).unwrap()[0].as_i32(), 1);
+
+    match Store::new().instantiate(module.clone(), &Imports::new()) {
+        Err(e) => assert_eq!(e, Error::Link("global memory limit exceeded")),
+        Ok(_) => panic!("instantiation past the global memory limit should fail"),
+    }
+
+    // Dropping an instance returns its pages to the budget
+    drop(second);
+    assert!(Store::new().instantiate(module, &Imports::new()).is_ok());
+
+    set_global_memory_limit(u64::MAX);
+    let _ = instantiate(r#"(module (memory 8))"#, &Imports::new());
+}
@@ -0,0 +1,122 @@
+use wagmi::WasmMemory;
+
+const OOB: &str = "out of bounds memory access";
+
+#[test]
+fn addressing_near_u32_max_never_wraps() {
+    let mut mem = WasmMemory::new(1, 1);
+    let page = WasmMemory::PAGE_SIZE;
+
+    // Last in-bounds word and the first one straddling the end
+    assert!(mem.store_u32(page - 4, 0, 0xdead_beef).is_ok());
+    assert_eq!(mem.load_u32(page - 8, 4), Ok(0xdead_beef));
+    assert_eq!(mem.load_u32(page - 3, 0), Err(OOB));
+    assert_eq!(mem.load_u64(0, page - 7), Err(OOB));
+
+    // ptr + offset would wrap a 32-bit usize if computed naively
+    for (ptr, offset) in [(u32::MAX, u32::MAX), (u32::MAX, 1), (1, u32::MAX), (u32::MAX - 3, 4)] {
+        assert_eq!(mem.load_u8(ptr, offset), Err(OOB));
+        assert_eq!(mem.load_u64(ptr, offset), Err(OOB));
+        assert_eq!(mem.store_u32(ptr, offset, 1), Err(OOB));
+        assert_eq!(mem.store_u64(ptr, offset, 1), Err(OOB));
+    }
+    assert_eq!(mem.write_bytes(u32::MAX, &[1, 2]), Err(OOB));
+    assert_eq!(mem.write_bytes(page - 1, &[1, 2]), Err(OOB));
+    assert!(mem.write_bytes(page - 2, &[1, 2]).is_ok());
+}
+
+#[test]
+fn zero_sized_memory_rejects_every_access() {
+    let mut mem = WasmMemory::new(0, 0);
+    assert_eq!(mem.load_u8(0, 0), Err(OOB));
+    assert_eq!(mem.store_u8(0, 0, 1), Err(OOB));
+    assert!(mem.write_bytes(0, &[]).is_ok());
+}
+
+#[test]
+fn init_checks_both_ranges_before_writing() {
+    let mut mem = WasmMemory::new(1, 1);
+    let page = WasmMemory::PAGE_SIZE;
+    let segment = [1, 2, 3, 4];
+    let bytes_at = |mem: &WasmMemory, at: u32| {
+        (0..4).map(|i| mem.load_u8(at + i, 0).unwrap()).collect::<Vec<_>>()
+    };
+
+    assert!(mem.init(8, &segment, 1, 3).is_ok());
+    assert_eq!(bytes_at(&mem, 8), vec![2, 3, 4, 0]);
+
+    // Destination runs past memory: nothing is written, not even the bytes that fit
+    assert_eq!(mem.init(page - 2, &segment, 0, 4), Err(OOB));
+    assert_eq!(bytes_at(&mem, page - 4), vec![0; 4]);
+    // Source runs past the segment
+    assert_eq!(mem.init(0, &segment, 2, 3), Err(OOB));
+    assert_eq!(mem.load_u8(0, 0), Ok(0));
+    assert_eq!(mem.init(u32::MAX, &segment, 0, 1), Err(OOB));
+
+    // Empty copies at either end are fine, one past the end is not
+    assert!(mem.init(page, &segment, 4, 0).is_ok());
+    assert_eq!(mem.init(page + 1, &segment, 0, 0), Err(OOB));
+    assert_eq!(mem.init(0, &segment, 5, 0), Err(OOB));
+}
+
+#[test]
+fn copy_handles_overlap_and_fill_checks_bounds() {
+    let mut mem = WasmMemory::new(1, 1);
+    let page = WasmMemory::PAGE_SIZE;
+    let bytes_at = |mem: &WasmMemory, at: u32, n: u32| mem.read_bytes(at, n).unwrap().to_vec();
+    mem.write_bytes(0, &[1, 2, 3, 4, 5]).unwrap();
+
+    // Forward and backward overlapping copies behave like memmove
+    assert!(mem.copy(2, 0, 5).is_ok());
+    assert_eq!(bytes_at(&mem, 0, 7), vec![1, 2, 1, 2, 3, 4, 5]);
+    assert!(mem.copy(0, 2, 5).is_ok());
+    assert_eq!(bytes_at(&mem, 0, 7), vec![1, 2, 3, 4, 5, 4, 5]);
+
+    // Either range running past memory fails without writing
+    assert_eq!(mem.copy(page - 2, 0, 4), Err(OOB));
+    assert_eq!(bytes_at(&mem, page - 2, 2), vec![0, 0]);
+    assert_eq!(mem.copy(0, page - 2, 4), Err(OOB));
+    assert_eq!(mem.load_u8(0, 0), Ok(1));
+    assert!(mem.copy(page, 0, 0).is_ok());
+    assert_eq!(mem.copy(page + 1, 0, 0), Err(OOB));
+
+    assert!(mem.fill(page - 3, 0xab, 3).is_ok());
+    assert_eq!(bytes_at(&mem, page - 4, 4), vec![0, 0xab, 0xab, 0xab]);
+    assert_eq!(mem.fill(page - 3, 0xcd, 4), Err(OOB));
+    assert_eq!(mem.load_u8(page - 3, 0), Ok(0xab));
+    assert_eq!(mem.fill(u32::MAX, 0, 2), Err(OOB));
+
+    mem.set_readonly(true);
+    assert!(mem.fill(0, 0, 1).is_err());
+    assert!(mem.copy(0, 1, 1).is_err());
+}
+
+#[test]
+fn from_bytes_adopts_whole_pages() {
+    let page = WasmMemory::PAGE_SIZE as usize;
+    let mut data = vec![0u8; 2 * page];
+    data[0] = 7;
+    data[2 * page - 1] = 9;
+
+    let mut mem = WasmMemory::from_bytes(data, 3).unwrap();
+    assert_eq!((mem.size(), mem.max()), (2, 3));
+    assert_eq!(mem.load_u8(0, 0), Ok(7));
+    assert_eq!(mem.load_u8(2 * page as u32 - 1, 0), Ok(9));
+    assert_eq!(mem.load_u8(2 * page as u32, 0), Err(OOB));
+    assert_eq!(mem.grow(1), 2);
+    assert_eq!(mem.grow(1), u32::MAX);
+
+    assert!(WasmMemory::from_bytes(Vec::new(), 0).is_ok_and(|m| m.size() == 0));
+    assert_eq!(
+        WasmMemory::from_bytes(vec![0; page + 1], 2).err(),
+        Some("memory size is not a multiple of the page size")
+    );
+    assert_eq!(
+        WasmMemory::from_bytes(vec![0; 2 * page], 1).err(),
+        Some("size minimum must not be greater than maximum")
+    );
+    assert_eq!(
+        WasmMemory::from_bytes(Vec::new(), WasmMemory::MAX_PAGES + 1).err(),
+        Some("memory size must be at most 65536 pages (4GiB)")
+    );
+}
@@ -0,0 +1,641 @@
+mod common;
+
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use common::{compile, module_with_sections, wat};
+use wagmi::{
+    BlockType, CompileOptions, ElementMode, ElementSegment, Error, ExternDesc, Features, Immediate,
+    ImportDesc, Imports, Module, ModuleCache, Signature, Store, TrapKind, ValType,
+};
+
+#[test]
+fn estimated_memory_counts_initial_pages() {
+    let module = compile(r#"(module (memory 4) (table 10 funcref) (global i32 (i32.const 0)))"#);
+    let estimate = module.estimated_memory_bytes();
+    assert!(estimate >= 4 * 64 * 1024);
+    assert_eq!(estimate, 4 * 64 * 1024 + 10 * 8 + 8);
+
+    let imported = compile(r#"(module (import "env" "mem" (memory 4)))"#);
+    assert_eq!(imported.estimated_memory_bytes(), 0);
+}
+
+#[test]
+fn function_body_bytes_spans_instruction_stream() {
+    let module = compile(
+        r#"(module
+            (import "env" "f" (func))
+            (func (param i32) (result i32) (local i64) (i32.add (local.get 0) (i32.const 1))))"#,
+    );
+    assert_eq!(module.function_body_bytes(0), None);
+    assert_eq!(module.function_body_bytes(2), None);
+
+    // local.get 0, i32.const 1, i32.add, end
+    let body = module.function_body_bytes(1).unwrap();
+    assert_eq!(body, &[0x20, 0x00, 0x41, 0x01, 0x6a, 0x0b]);
+}
+
+#[test]
+fn instructions_resolve_block_types() {
+    let module = compile(
+        r#"(module
+            (func (result i32)
+                (block (result i32) (i32.const 1))
+                (loop)
+                (block (param i32) (result i32) (i32.add (i32.const 2)))))"#,
+    );
+    let block_types: Vec<BlockType> = module
+        .instructions(0)
+        .unwrap()
+        .filter_map(|instr| match instr.immediate {
+            Immediate::BlockType(bt) => Some(bt),
+            _ => None,
+        })
+        .collect();
+    let bt = |params: &[ValType], results: &[ValType]| BlockType {
+        params: params.to_vec(),
+        results: results.to_vec(),
+    };
+
+    assert_eq!(
+        block_types,
+        [bt(&[], &[ValType::I32]), bt(&[], &[]), bt(&[ValType::I32], &[ValType::I32])]
+    );
+}
+
+#[test]
+fn oversized_local_declaration_fails_fast() {
+    #[rustfmt::skip]
+    let bytes = vec![
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type: [] -> []
+        0x03, 0x02, 0x01, 0x00, // func: type 0
+        // code: one body declaring 0xffff_ffff i32 locals
+        0x0a, 0x0a, 0x01, 0x08, 0x01, 0xff, 0xff, 0xff, 0xff, 0x0f, 0x7f, 0x0b,
+    ];
+    let start = Instant::now();
+    assert_eq!(Module::compile(bytes).err(), Some(Error::Malformed("too many locals")));
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn reachable_functions_follow_direct_calls_from_roots() {
+    let module = compile(
+        r#"(module
+            (import "env" "log" (func $log (param i32)))
+            (table 1 funcref)
+            (elem (i32.const 0) $indirect)
+            (func $main (export "main") (call $helper (i32.const 0x10)))
+            (func $helper (param i32) (call $log (local.get 0)))
+            (func $indirect)
+            (func $init (call $leaf))
+            (func $leaf)
+            (func $dead (call $leaf))
+            (start $init))"#,
+    );
+    let mut reachable: Vec<u32> = module.reachable_functions().into_iter().collect();
+    reachable.sort_unstable();
+    // $dead (6) is the only function nothing reaches
+    assert_eq!(reachable, vec![0, 1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn export_import_dependencies_follow_the_call_graph() {
+    let module = compile(
+        r#"(module
+            (import "env" "log" (func $log (param i32)))
+            (import "env" "now" (func $now (result i32)))
+            (import "env" "abort" (func $abort))
+            (type $v (func))
+            (table 1 funcref)
+            (elem (i32.const 0) $fail)
+            (func $helper (param i32) (call $log (local.get 0)))
+            (func $fail (call $abort))
+            (func (export "report") (call $helper (call $now)))
+            (func (export "pure") (result i32) (i32.const 1))
+            (func (export "dispatch") (call_indirect (type $v) (i32.const 0)))
+            (memory (export "mem") 1))"#,
+    );
+    let import = |field: &str, params: Vec<ValType>, result: Option<ValType>| ImportDesc {
+        module: "env".into(),
+        field: field.into(),
+        desc: ExternDesc::Func(Signature { params, result }),
+    };
+    let report = module.export_import_dependencies("report");
+    assert_eq!(report.len(), 2);
+    assert!(report.contains(&import("log", vec![ValType::I32], None)));
+    assert!(report.contains(&import("now", vec![], Some(ValType::I32))));
+
+    assert!(module.export_import_dependencies("pure").is_empty());
+    let dispatch = module.export_import_dependencies("dispatch");
+    assert_eq!(dispatch.into_iter().collect::<Vec<_>>(), vec![import("abort", vec![], None)]);
+    assert!(module.export_import_dependencies("mem").is_empty());
+    assert!(module.export_import_dependencies("missing").is_empty());
+}
+
+#[test]
+fn potential_traps_list_every_trapping_instruction() {
+    let module = compile(
+        r#"(module
+            (import "env" "f" (func $f))
+            (type $v (func))
+            (table 1 funcref)
+            (memory 1)
+            (func $checked (param i32) (result i32)
+                (if (i32.eqz (local.get 0)) (then unreachable))
+                (i32.div_s (i32.const 10) (local.get 0)))
+            (func $mixed (param f32)
+                (drop (i32.trunc_f32_s (local.get 0)))
+                (drop (i32.trunc_sat_f32_s (local.get 0)))
+                (i32.store (i32.const 0) (i32.load (i32.const 4)))
+                (memory.fill (i32.const 0) (i32.const 0) (i32.const 1))
+                (drop (memory.grow (i32.const 1)))
+                (drop (table.get 0 (i32.const 0)))
+                (call_indirect (type $v) (i32.const 0))
+                (call $f))
+            (func $pure (result i32) (i32.add (i32.const 1) (i32.const 2))))"#,
+    );
+    let traps = |idx| module.potential_traps(idx).into_iter().map(|(_, k)| k).collect::<Vec<_>>();
+    assert_eq!(traps(1), [TrapKind::Unreachable, TrapKind::IntegerDivision]);
+    assert_eq!(
+        traps(2),
+        [
+            TrapKind::FloatTruncation,
+            TrapKind::MemoryAccess,
+            TrapKind::MemoryAccess,
+            TrapKind::MemoryAccess,
+            TrapKind::TableAccess,
+            TrapKind::IndirectCall,
+        ]
+    );
+    assert!(traps(3).is_empty());
+    assert!(traps(0).is_empty());
+    assert!(traps(9).is_empty());
+
+    // Reported pcs point at the trapping opcodes themselves
+    let bytes = &module.bytes;
+    let pcs: Vec<u8> = module.potential_traps(1).into_iter().map(|(pc, _)| bytes[pc]).collect();
+    assert_eq!(pcs, [0x00, 0x6d]);
+}
+
+#[test]
+fn element_segments_report_mode_table_and_offset() {
+    let module = compile(
+        r#"(module
+            (import "env" "base" (global $base i32))
+            (table 8 funcref)
+            (func $a) (func $b) (func $c)
+            (elem (i32.const 2) $a $b)
+            (elem (global.get $base) $c $a))"#,
+    );
+    let segments: Vec<ElementSegment> = module.element_segments_iter().collect();
+    assert_eq!(segments.len(), 2);
+    assert_eq!(segments[0].functions, [Some(0), Some(1)]);
+    assert_eq!(segments[1].functions, [Some(2), Some(0)]);
+
+    let ElementMode::Active { table: 0, offset_expr } = segments[0].mode else {
+        panic!("expected an active segment on table 0");
+    };
+    // i32.const 2
+    assert_eq!(module.bytes[offset_expr..offset_expr + 2], [0x41, 0x02]);
+    let ElementMode::Active { table: 0, offset_expr } = segments[1].mode else {
+        panic!("expected an active segment on table 0");
+    };
+    // global.get 0
+    assert_eq!(module.bytes[offset_expr..offset_expr + 2], [0x23, 0x00]);
+
+    assert_eq!(compile("(module)").element_segments_iter().count(), 0);
+}
+
+#[test]
+fn element_segment_modes_come_from_the_flags() {
+    let module = compile(
+        r#"(module
+            (table 4 funcref)
+            (func $a) (func $b)
+            (elem func $a $b)
+            (elem declare func $b)
+            (elem (table 0) (i32.const 1) func $b)
+            (elem (i32.const 0) funcref (ref.func $b) (ref.func $a)))"#,
+    );
+    let segments: Vec<ElementSegment> = module.element_segments_iter().collect();
+    assert_eq!(segments.len(), 4);
+    assert_eq!(segments[0].mode, ElementMode::Passive);
+    assert_eq!(segments[0].functions, [Some(0), Some(1)]);
+    assert_eq!(segments[1].mode, ElementMode::Declarative);
+    assert_eq!(segments[1].functions, [Some(1)]);
+    assert!(matches!(segments[2].mode, ElementMode::Active { table: 0, .. }));
+    assert_eq!(segments[2].functions, [Some(1)]);
+    assert!(matches!(segments[3].mode, ElementMode::Active { table: 0, .. }));
+    assert_eq!(segments[3].functions, [Some(1), Some(0)]);
+
+    // Only the active segments are written at instantiation
+    let inst = Store::new().instantiate(Rc::new(module), &Imports::new()).unwrap();
+    let table = inst.table.as_ref().unwrap().borrow();
+    let slots: Vec<u64> = (0..4).map(|i| table.get(i).unwrap().as_u64() & 0xFFFF_FFFF).collect();
+    assert_eq!(slots, [2, 1, 0, 0]);
+
+    let bytes = wat("(module (func $a) (elem declare func $a))");
+    assert_eq!(
+        Module::compile_with_features(bytes, Features::MVP).err(),
+        Some(Error::Malformed("invalid value type"))
+    );
+}
+
+#[test]
+fn count_limits_reject_oversized_sections() {
+    // Type section claiming a billion entries with none actually present
+    #[rustfmt::skip]
+    let huge_types = vec![
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x05, 0x80, 0x94, 0xeb, 0xdc, 0x03,
+    ];
+    let limited = CompileOptions { max_types: Some(1000), ..Default::default() };
+    assert_eq!(
+        Module::compile_with_options(huge_types.clone(), limited).err(),
+        Some(Error::Malformed("too many types"))
+    );
+    // Without a limit the count alone must not drive the allocation
+    assert!(Module::compile(huge_types).is_err());
+
+    let src = r#"(module
+        (import "env" "f" (func))
+        (import "env" "g" (global i32))
+        (func) (func)
+        (global i32 (i32.const 0)))"#;
+    let compile = |options| Module::compile_with_options(wat(src), options).err();
+    let limit = |max_functions, max_globals| CompileOptions {
+        max_functions: Some(max_functions),
+        max_globals: Some(max_globals),
+        ..Default::default()
+    };
+    assert_eq!(compile(limit(2, 2)), Some(Error::Malformed("too many functions")));
+    assert_eq!(compile(limit(3, 1)), Some(Error::Malformed("too many globals")));
+    assert_eq!(compile(limit(3, 2)), None);
+}
+
+#[test]
+fn segment_count_limits_reject_before_parsing() {
+    // Memory and table sections followed by data and element sections each claiming
+    // a billion segments with none present
+    let header = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    let table_and_memory = [0x04, 0x04, 0x01, 0x70, 0x00, 0x01, 0x05, 0x03, 0x01, 0x00, 0x01];
+    let huge_count = [0x80, 0x94, 0xeb, 0xdc, 0x03];
+    let with_section = |id: u8| [&header[..], &table_and_memory, &[id, 0x05], &huge_count].concat();
+    let limited = CompileOptions {
+        max_data_segments: Some(100),
+        max_element_segments: Some(100),
+        ..Default::default()
+    };
+
+    assert_eq!(
+        Module::compile_with_options(with_section(11), limited).err(),
+        Some(Error::Malformed("too many data segments"))
+    );
+    assert_eq!(
+        Module::compile_with_options(with_section(9), limited).err(),
+        Some(Error::Malformed("too many element segments"))
+    );
+    // Unbounded, parsing starts and runs out of bytes instead
+    assert_eq!(
+        Module::compile(with_section(11)).err(),
+        Some(Error::Malformed("unexpected end of section or function"))
+    );
+
+    let src = r#"(module (memory 1) (table 1 funcref) (func)
+        (data (i32.const 0) "a") (data (i32.const 1) "b")
+        (elem (i32.const 0) 0))"#;
+    let limit = |data, elem| CompileOptions {
+        max_data_segments: Some(data),
+        max_element_segments: Some(elem),
+        ..Default::default()
+    };
+    assert!(Module::compile_with_options(wat(src), limit(2, 1)).is_ok());
+    assert_eq!(
+        Module::compile_with_options(wat(src), limit(1, 1)).err(),
+        Some(Error::Malformed("too many data segments"))
+    );
+    assert_eq!(
+        Module::compile_with_options(wat(src), limit(2, 0)).err(),
+        Some(Error::Malformed("too many element segments"))
+    );
+}
+
+#[test]
+fn export_names_must_be_valid_and_unique_utf8() {
+    // Memory 0 exported under each given name
+    let module_exporting = |names: &[&[u8]]| {
+        let mut exports = vec![names.len() as u8];
+        for name in names {
+            exports.push(name.len() as u8);
+            exports.extend_from_slice(name);
+            exports.extend_from_slice(&[0x02, 0x00]);
+        }
+        Module::compile(module_with_sections(&[(0x05, &[0x01, 0x00, 0x00]), (0x07, &exports)]))
+            .map(|_| ())
+    };
+
+    let invalid_utf8 = Err(Error::Malformed("invalid UTF-8 encoding"));
+    assert_eq!(module_exporting(&[b"\xff"]), invalid_utf8);
+    assert_eq!(module_exporting(&[b"mem", b"m\xc3"]), invalid_utf8);
+    assert_eq!(
+        module_exporting(&[b"\xc3\xa9", b"\xc3\xa9"]),
+        Err(Error::Validation("duplicate export name"))
+    );
+    assert_eq!(module_exporting(&[b"e", b"\xc3\xa9"]), Ok(()));
+}
+
+#[test]
+fn functions_share_their_type_signature() {
+    let funcs: String = (0..1000).map(|_| "(func (type 0) local.get 0)").collect();
+    let module = compile(&format!(
+        r#"(module (type (func (param i32) (result i32)))
+            (import "env" "f" (func (type 0)))
+            {})"#,
+        funcs
+    ));
+    assert_eq!(module.types.len(), 1);
+    assert_eq!(module.functions.len(), 1001);
+    // One allocation referenced by the type section and every function
+    assert_eq!(Rc::strong_count(&module.types[0]), 1002);
+    assert!(module.functions.iter().all(|f| Rc::ptr_eq(&f.ty, &module.types[0])));
+}
+
+#[test]
+fn lenient_custom_sections_skip_malformed_names() {
+    // The custom section, then a type section with [] -> []
+    let module_with_custom =
+        |custom: &[u8]| module_with_sections(&[(0x00, custom), (0x01, &[0x01, 0x60, 0x00, 0x00])]);
+    let lenient = CompileOptions { lenient_custom_sections: true, ..Default::default() };
+
+    assert_eq!(Module::compile(module_with_custom(b"\x04name")).unwrap().types.len(), 1);
+
+    // A "name" section whose name isn't UTF-8, and one whose name overruns the section
+    let bad_utf8 = module_with_custom(b"\x04n\xffme\x00");
+    let overrun = module_with_custom(b"\x0aname");
+    assert_eq!(
+        Module::compile(bad_utf8.clone()).err(),
+        Some(Error::Malformed("invalid UTF-8 encoding"))
+    );
+    assert_eq!(
+        Module::compile(overrun.clone()).err(),
+        Some(Error::Malformed("unexpected end of section or function"))
+    );
+    for bytes in [bad_utf8, overrun] {
+        let module = Module::compile_with_options(bytes, lenient).unwrap();
+        assert_eq!(module.types.len(), 1);
+    }
+
+    // The declared section length must still fit in the module
+    let mut truncated = module_with_custom(b"\x04name");
+    truncated[9] = 0x7f;
+    assert!(Module::compile_with_options(truncated, lenient).is_err());
+}
+
+#[test]
+fn metadata_releases_the_module_bytes() {
+    let module = compile(
+        r#"(module (import "env" "f" (func (param i32)))
+            (memory 2) (global i32 (i32.const 1))
+            (func (export "run") (result i32) (i32.const 7)))"#,
+    );
+    let bytes = Rc::downgrade(&module.bytes);
+    let metadata = module.into_metadata();
+
+    assert!(bytes.upgrade().is_none());
+    assert_eq!(metadata.functions.len(), 2);
+    assert_eq!(metadata.types.len(), 2);
+    assert_eq!(metadata.imports["env"].len(), 1);
+    assert_eq!(metadata.exports["run"].idx, 1);
+    assert_eq!(metadata.memory.as_ref().map(|m| m.min), Some(2));
+    assert_eq!(metadata.globals.len(), 1);
+}
+
+#[test]
+fn truncated_float_const_offset_is_rejected_at_compile_time() {
+    for (opcode, len) in [(0x43, 4), (0x44, 8)] {
+        #[rustfmt::skip]
+        let mut bytes = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            0x05, 0x03, 0x01, 0x00, 0x01, // memory: 1 page
+        ];
+        // data: one active segment whose offset const stops one byte short
+        let offset_expr = [&[opcode][..], &vec![0; len - 1]].concat();
+        bytes.extend_from_slice(&[0x0b, (offset_expr.len() + 2) as u8, 0x01, 0x00]);
+        bytes.extend(offset_expr);
+        assert_eq!(
+            Module::compile(bytes).err(),
+            Some(Error::Malformed("unexpected end of section or function"))
+        );
+    }
+}
+
+#[test]
+fn validation_records_max_stack_height() {
+    let module = compile(
+        r#"(module
+            (import "env" "f" (func))
+            (func (param i32) (local i64)
+                i32.const 1 i32.const 2 i32.const 3 drop drop drop)
+            (func (result i32)
+                (block (result i32) (i32.add (i32.const 1) (i32.mul (i32.const 2) (i32.const 3))))))"#,
+    );
+    let heights: Vec<usize> = module.functions.iter().map(|f| f.max_stack_height).collect();
+    assert_eq!(heights, vec![0, 2 + 3, 3]);
+}
+
+#[test]
+fn interface_diff_reports_renamed_and_retyped_items() {
+    let old = compile(
+        r#"(module
+            (import "env" "log" (func (param i32)))
+            (import "env" "mem" (memory 1))
+            (func (export "run") (result i32) (i32.const 0))
+            (func (export "size") (param i32) (result i32) (local.get 0)))"#,
+    );
+    let new = compile(
+        r#"(module
+            (import "env" "log" (func (param i64)))
+            (import "env" "clock" (func (result i64)))
+            (func (export "start") (result i32) (i32.const 0))
+            (func (export "size") (param i32) (result i32) (local.get 0)))"#,
+    );
+
+    let diff = old.interface_diff(&new);
+    assert_eq!(diff.added_exports, vec!["start".to_string()]);
+    assert_eq!(diff.removed_exports, vec!["run".to_string()]);
+    assert!(diff.changed_exports.is_empty());
+    let import = |field: &str| ("env".to_string(), field.to_string());
+    assert_eq!(diff.added_imports, vec![import("clock")]);
+    assert_eq!(diff.removed_imports, vec![import("mem")]);
+    assert_eq!(diff.changed_imports, vec![import("log")]);
+
+    assert!(old.interface_diff(&old).is_empty());
+    assert_eq!(new.interface_diff(&old).added_exports, vec!["run".to_string()]);
+}
+
+#[test]
+fn imported_and_defined_tables_or_memories_are_rejected() {
+    let compile_err = |src| Module::compile(wat(src)).err();
+    assert_eq!(
+        compile_err(r#"(module (import "env" "mem" (memory 1)) (memory 1))"#),
+        Some(Error::Validation("multiple memories"))
+    );
+    assert_eq!(
+        compile_err(r#"(module (import "env" "tab" (table 1 funcref)) (table 1 funcref))"#),
+        Some(Error::Validation("multiple tables"))
+    );
+
+    // Defining first means an import section after the table/memory section,
+    // which the fixed section order never reaches
+    let define_then_import = |defined: (u8, &[u8]), import_kind: &[u8]| {
+        let import = [&[0x01, 0x01, b'e', 0x01, b'x'][..], import_kind].concat();
+        Module::compile(module_with_sections(&[defined, (0x02, &import)])).err()
+    };
+    let out_of_order = Some(Error::Malformed("length out of bounds"));
+    let memory = (0x05, &[0x01, 0x00, 0x01][..]);
+    let table = (0x04, &[0x01, 0x70, 0x00, 0x01][..]);
+    assert_eq!(define_then_import(memory, &[0x02, 0x00, 0x01]), out_of_order);
+    assert_eq!(define_then_import(table, &[0x01, 0x70, 0x00, 0x01]), out_of_order);
+}
+
+#[test]
+fn signature_reports_arity_and_result_types() {
+    let module = compile(
+        r#"(module
+            (type (func (param i32 f32) (result i64)))
+            (type (func (param i32 f32) (result i64)))
+            (type (func (param i32 f32))))"#,
+    );
+    let sig = &module.types[0];
+    assert_eq!(sig.arity(), (2, 1));
+    assert_eq!(sig.params, [ValType::I32, ValType::F32]);
+    assert_eq!(sig.result_types(), [ValType::I64]);
+    assert!(sig.matches(&module.types[1]));
+
+    let no_result = &module.types[2];
+    assert_eq!(no_result.arity(), (2, 0));
+    assert!(no_result.result_types().is_empty());
+    assert!(!sig.matches(no_result));
+    assert_eq!(Signature::default().arity(), (0, 0));
+}
+
+#[test]
+fn module_cache_reuses_identical_modules() {
+    let mut cache = ModuleCache::new(2);
+    let a = wat(r#"(module (func (export "a")))"#);
+    let b = wat(r#"(module (func (export "b")))"#);
+    let c = wat(r#"(module (func (export "c")))"#);
+
+    let first = Module::compile_cached(a.clone(), &mut cache).unwrap();
+    let second = Module::compile_cached(a.clone(), &mut cache).unwrap();
+    assert!(Rc::ptr_eq(&first, &second));
+    assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+    let other = Module::compile_cached(b.clone(), &mut cache).unwrap();
+    assert!(!Rc::ptr_eq(&first, &other));
+    assert_eq!((cache.hits(), cache.misses()), (1, 2));
+
+    // Same bytes under different options are compiled separately
+    let options = CompileOptions { max_functions: Some(1), ..Default::default() };
+    let limited = cache.get_or_compile(a.clone(), options).unwrap();
+    assert!(!Rc::ptr_eq(&first, &limited));
+    assert_eq!((cache.hits(), cache.misses()), (1, 3));
+
+    // Failures aren't cached, and the least recently used entry is evicted
+    assert!(Module::compile_cached(vec![0, 1, 2], &mut cache).is_err());
+    assert_eq!(cache.len(), 2);
+    Module::compile_cached(c, &mut cache).unwrap();
+    Module::compile_cached(a, &mut cache).unwrap();
+    assert_eq!((cache.hits(), cache.misses()), (1, 6));
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn data_count_section_must_match_the_data_section() {
+    // Memory section, data count section claiming `count`, data section with one segment
+    let module = |count: u8| {
+        module_with_sections(&[
+            (0x05, &[0x01, 0x00, 0x01]),
+            (0x0c, &[count]),
+            (0x0b, &[0x01, 0x00, 0x41, 0x00, 0x0b, 0x01, 0x61]),
+        ])
+    };
+
+    let compiled = Module::compile(module(1)).unwrap();
+    assert_eq!(compiled.data_count, Some(1));
+    assert_eq!(
+        Module::compile(module(2)).err(),
+        Some(Error::Malformed("data count and data section have inconsistent lengths"))
+    );
+    assert_eq!(
+        Module::compile_with_features(module(1), Features::ALL.without(Features::BULK_MEMORY))
+            .err(),
+        Some(Error::Malformed("invalid section id"))
+    );
+    assert_eq!(Module::compile(wat("(module)")).unwrap().data_count, None);
+}
+
+#[test]
+fn header_only_module_compiles_and_instantiates_empty() {
+    const HEADER: [u8; 8] = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    let module = Rc::new(Module::compile(HEADER.to_vec()).unwrap());
+    assert!(module.functions.is_empty());
+    assert!(module.exports.is_empty());
+    assert_eq!(module.start, None);
+    let instance = Store::new().instantiate(module, &Imports::new()).unwrap();
+    assert!(instance.exports.is_empty());
+
+    // Anything after the header must be a well-formed section
+    let with = |junk: &[u8]| Module::compile([&HEADER[..], junk].concat()).err();
+    assert_eq!(with(&[0x0d]), Some(Error::Malformed("invalid section id")));
+    assert_eq!(with(&[0xff, 0x00]), Some(Error::Malformed("invalid section id")));
+    assert_eq!(with(&[0x01]), Some(Error::Malformed("unexpected end of section or function")));
+    assert_eq!(
+        with(&[0x01, 0x05, 0x00]),
+        Some(Error::Malformed("unexpected end of section or function"))
+    );
+}
+
+#[test]
+fn inspect_reports_no_exports_for_an_empty_module() {
+    let path = std::env::temp_dir().join(format!("wagmi_empty_{}.wasm", std::process::id()));
+    std::fs::write(&path, [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]).unwrap();
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_wagmi-inspect"))
+        .arg(&path)
+        .output()
+        .expect("failed to run wagmi-inspect");
+    let _ = std::fs::remove_file(&path);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Imports: none"));
+    assert!(stdout.contains("Exports: none"));
+}
+
+#[test]
+fn run_prints_integer_results_in_the_requested_radix() {
+    let path = std::env::temp_dir().join(format!("wagmi_radix_{}.wasm", std::process::id()));
+    std::fs::write(
+        &path,
+        wat(r#"(module
+            (func (export "mask") (result i32) (i32.const 0xff))
+            (func (export "neg") (result i64) (i64.const -1))
+            (func (export "half") (result f32) (f32.const 0.5)))"#),
+    )
+    .unwrap();
+    let run = |func: &str, radix: &str| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_wagmi-run"))
+            .args([path.to_str().unwrap(), "--invoke", func, "--radix", radix])
+            .output()
+            .expect("failed to run wagmi-run");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    assert!(run("mask", "hex").contains("[0] 0xff (i32)"));
+    assert!(run("mask", "bin").contains("[0] 0b11111111 (i32)"));
+    assert!(run("mask", "dec").contains("[0] 255 (i32)"));
+    assert!(run("neg", "hex").contains("[0] 0xffffffffffffffff (i64)"));
+    assert!(run("half", "hex").contains("[0] 0.5 (f32)"));
+    let _ = std::fs::remove_file(&path);
+}
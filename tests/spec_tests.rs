@@ -8,8 +8,8 @@ use std::{
     rc::Rc,
 };
 use wagmi::{
-    Error, ExportValue, Imports, Instance, Module, RuntimeFunction, RuntimeSignature, Signature,
-    ValType, WasmGlobal, WasmMemory, WasmTable, WasmValue,
+    Error, ExportValue, Imports, Instance, Module, NanKind, RuntimeFunction, RuntimeSignature,
+    Signature, Store, ValType, WasmGlobal, WasmMemory, WasmTable, WasmValue,
 };
 
 #[derive(Deserialize, Clone)]
@@ -100,28 +100,25 @@ struct WastJSON {
     commands: Vec<TestCmd>,
 }
 
+/// The NaN class an expected float value stands for, `None` for exact bit patterns
+fn nan_kind(v: &ValueJSON) -> Option<NanKind> {
+    match v.value.as_str() {
+        "nan:canonical" => Some(NanKind::Canonical),
+        "nan:arithmetic" => Some(NanKind::Arithmetic),
+        _ => None,
+    }
+}
+
 fn to_wasm_values(values: &[ValueJSON]) -> Vec<WasmValue> {
     values
         .iter()
-        .map(|v| match v.r#type.as_str() {
-            "i32" => WasmValue::from_u32(v.value.parse().unwrap()),
-            "i64" => WasmValue::from_u64(v.value.parse().unwrap()),
-            "f32" => {
-                let bits = if v.value.starts_with("nan:") {
-                    0x7fc0_0000u32
-                } else {
-                    v.value.parse().unwrap()
-                };
-                WasmValue::from_f32_bits(bits)
-            }
-            "f64" => {
-                let bits = if v.value.starts_with("nan:") {
-                    0x7ff8_0000_0000_0000u64
-                } else {
-                    v.value.parse().unwrap()
-                };
-                WasmValue::from_f64_bits(bits)
-            }
+        .map(|v| match (v.r#type.as_str(), nan_kind(v)) {
+            ("i32", _) => WasmValue::from_u32(v.value.parse().unwrap()),
+            ("i64", _) => WasmValue::from_u64(v.value.parse().unwrap()),
+            ("f32", Some(kind)) => WasmValue::nan_f32(kind),
+            ("f32", None) => WasmValue::from_f32_bits(v.value.parse().unwrap()),
+            ("f64", Some(kind)) => WasmValue::nan_f64(kind),
+            ("f64", None) => WasmValue::from_f64_bits(v.value.parse().unwrap()),
             _ => panic!("unknown value type: {}", v.r#type),
         })
         .collect()
@@ -138,14 +135,15 @@ fn externalized_exports_for(inst: &Rc<Instance>) -> HashMap<String, ExportValue>
                 RuntimeFunction::Host { .. } => {
                     out.insert(name.clone(), ExportValue::Function(src));
                 }
-                RuntimeFunction::OwnedWasm { runtime_sig: ty, .. }
-                | RuntimeFunction::ImportedWasm { runtime_sig: ty, .. } => {
+                RuntimeFunction::OwnedWasm { runtime_sig: ty, sig, .. }
+                | RuntimeFunction::ImportedWasm { runtime_sig: ty, sig, .. } => {
                     // For wasm-backed exports, expose an owner handle that
                     // delegates execution into the owning instance
                     out.insert(
                         name.clone(),
                         ExportValue::Function(RuntimeFunction::ImportedWasm {
                             runtime_sig: ty,
+                            sig,
                             owner: weak.clone(),
                             function_index: fi,
                         }),
@@ -157,7 +155,7 @@ fn externalized_exports_for(inst: &Rc<Instance>) -> HashMap<String, ExportValue>
     out
 }
 
-fn spectest_exports() -> HashMap<String, ExportValue> {
+fn spectest_exports(store: &Store) -> HashMap<String, ExportValue> {
     let mut exports = HashMap::new();
 
     exports.insert(
@@ -193,8 +191,10 @@ fn spectest_exports() -> HashMap<String, ExportValue> {
         })),
     );
 
-    exports
-        .insert("table".into(), ExportValue::Table(Rc::new(RefCell::new(WasmTable::new(10, 20)))));
+    exports.insert(
+        "table".into(),
+        ExportValue::Table(Rc::new(RefCell::new(WasmTable::new(store, 10, 20)))),
+    );
     exports
         .insert("memory".into(), ExportValue::Memory(Rc::new(RefCell::new(WasmMemory::new(1, 2)))));
 
@@ -202,8 +202,9 @@ fn spectest_exports() -> HashMap<String, ExportValue> {
     let make_fn = |sig: Signature| {
         let ty = RuntimeSignature::from_signature(&sig);
         ExportValue::Function(RuntimeFunction::Host {
-            callback: Rc::new(|_| None),
+            callback: Rc::new(|_, _| Ok(Vec::new())),
             runtime_sig: ty,
+            sig: Rc::new(sig),
         })
     };
 
@@ -278,27 +279,14 @@ fn check_results(results: &[WasmValue], expected: &[ValueJSON]) -> Result<(), St
     for (i, ((result, exp_val), exp_json)) in
         results.iter().zip(&exp_values).zip(expected).enumerate()
     {
-        // Exact bit match first
-        if result.as_u64() == exp_val.as_u64() {
-            continue;
-        }
-
-        // Check if both are NaN for float types
-        let both_nan = match exp_json.r#type.as_str() {
-            "f32" => {
-                let r = f32::from_bits(result.as_f32_bits());
-                let e = f32::from_bits(exp_val.as_f32_bits());
-                r.is_nan() && e.is_nan()
-            }
-            "f64" => {
-                let r = f64::from_bits(result.as_f64_bits());
-                let e = f64::from_bits(exp_val.as_f64_bits());
-                r.is_nan() && e.is_nan()
-            }
-            _ => false,
+        // A NaN class admits several bit patterns, anything else must match exactly
+        let matches = match (exp_json.r#type.as_str(), nan_kind(exp_json)) {
+            ("f32", Some(kind)) => result.is_nan_f32(kind),
+            ("f64", Some(kind)) => result.is_nan_f64(kind),
+            _ => result.as_u64() == exp_val.as_u64(),
         };
 
-        if !both_nan {
+        if !matches {
             return Err(format!("result[{}] mismatch", i));
         }
     }
@@ -306,7 +294,17 @@ fn check_results(results: &[WasmValue], expected: &[ValueJSON]) -> Result<(), St
     Ok(())
 }
 
-fn run_test_file(json_path: &Path, wast_name: &str) -> Result<(u32, u32, u32), String> {
+enum FileOutcome {
+    Ran {
+        passes: u32,
+        message_mismatches: u32,
+        failures: u32,
+    },
+    /// The first module needs a proposal wagmi doesn't implement
+    Skipped(Error),
+}
+
+fn run_test_file(json_path: &Path, wast_name: &str) -> Result<FileOutcome, String> {
     let json_text =
         fs::read_to_string(json_path).map_err(|e| format!("failed to read json: {}", e))?;
     let wast: WastJSON =
@@ -315,24 +313,31 @@ fn run_test_file(json_path: &Path, wast_name: &str) -> Result<(u32, u32, u32), S
     let mut instances: HashMap<String, Rc<Instance>> = HashMap::new();
     // Keep strong references to registered instances so cross-module imports remain valid
     let mut keepalive: Vec<Rc<Instance>> = Vec::new();
+    // Every module of a script links against the others, so they share one store
+    let store = Store::new();
     let mut imports: Imports = HashMap::new();
-    imports.insert("spectest".to_string(), spectest_exports());
+    imports.insert("spectest".to_string(), spectest_exports(&store));
 
     let base_dir = json_path.parent().unwrap();
     let mut passes = 0u32;
     let mut message_mismatches = 0u32;
     let mut failures = 0u32;
 
-    for cmd in &wast.commands {
+    for (i, cmd) in wast.commands.iter().enumerate() {
         let result = match cmd {
             TestCmd::Module { name, filename, .. } => {
                 let wasm_path = base_dir.join(filename);
                 let bytes =
                     fs::read(&wasm_path).map_err(|e| format!("failed to read wasm: {}", e))?;
-                let module =
-                    Module::compile(bytes).map_err(|e| format!("compile failed: {}", e))?;
+                let module = match Module::compile(bytes) {
+                    Err(e) if i == 0 && e.is_unsupported_feature() => {
+                        return Ok(FileOutcome::Skipped(e));
+                    }
+                    result => result.map_err(|e| format!("compile failed: {}", e))?,
+                };
                 let module_rc = Rc::new(module);
-                let inst = Instance::instantiate(module_rc, &imports)
+                let inst = store
+                    .instantiate(module_rc, &imports)
                     .map_err(|e| format!("instantiate failed: {}", e))?;
 
                 let inst_rc = Rc::new(inst);
@@ -371,11 +376,6 @@ fn run_test_file(json_path: &Path, wast_name: &str) -> Result<(u32, u32, u32), S
                     Err(Error::Trap(msg)) => {
                         if msg == text || msg.starts_with(text) {
                             Ok(()) // Exact match or starts with expected
-                        } else if text.starts_with(&msg)
-                            || (text.starts_with("uninitialized") && msg == "unreachable")
-                            || (text.starts_with("undefined") && msg == "unreachable")
-                        {
-                            Err(format!("message mismatch: expected '{}', got '{}'", text, msg))
                         } else {
                             Err(format!("message mismatch: expected '{}', got '{}'", text, msg))
                         }
@@ -386,7 +386,7 @@ fn run_test_file(json_path: &Path, wast_name: &str) -> Result<(u32, u32, u32), S
             }
 
             TestCmd::AssertExhaustion { action, .. } => match exec_action(&instances, action) {
-                Err(Error::Trap(msg)) if msg == "call stack exhausted" => Ok(()),
+                Err(Error::Trap("call stack exhausted")) => Ok(()),
                 _ => Err("expected exhaustion".into()),
             },
 
@@ -419,7 +419,7 @@ fn run_test_file(json_path: &Path, wast_name: &str) -> Result<(u32, u32, u32), S
                             Err(format!("message mismatch: expected '{}', got '{}'", text, msg))
                         }
                     }
-                    Ok(m) => match Instance::instantiate(Rc::new(m), &imports) {
+                    Ok(m) => match store.instantiate(Rc::new(m), &imports) {
                         Err(Error::Validation(msg)) => {
                             if msg == text {
                                 Ok(()) // Exact match
@@ -436,7 +436,7 @@ fn run_test_file(json_path: &Path, wast_name: &str) -> Result<(u32, u32, u32), S
             TestCmd::AssertUnlinkable { filename, text, .. } => {
                 let wasm_path = base_dir.join(filename);
                 match fs::read(&wasm_path).ok().and_then(|b| Module::compile(b).ok()) {
-                    Some(m) => match Instance::instantiate(Rc::new(m), &imports) {
+                    Some(m) => match store.instantiate(Rc::new(m), &imports) {
                         Err(Error::Link(msg)) => {
                             if msg == text {
                                 Ok(()) // Exact match
@@ -453,7 +453,7 @@ fn run_test_file(json_path: &Path, wast_name: &str) -> Result<(u32, u32, u32), S
             TestCmd::AssertUninstantiable { filename, text, .. } => {
                 let wasm_path = base_dir.join(filename);
                 match fs::read(&wasm_path).ok().and_then(|b| Module::compile(b).ok()) {
-                    Some(m) => match Instance::instantiate(Rc::new(m), &imports) {
+                    Some(m) => match store.instantiate(Rc::new(m), &imports) {
                         Err(Error::Uninstantiable(msg)) => {
                             if msg == text {
                                 Ok(()) // Exact match
@@ -525,7 +525,7 @@ fn run_test_file(json_path: &Path, wast_name: &str) -> Result<(u32, u32, u32), S
         }
     }
 
-    Ok((passes, message_mismatches, failures))
+    Ok(FileOutcome::Ran { passes, message_mismatches, failures })
 }
 
 #[test]
@@ -547,6 +547,7 @@ fn run_spec_tests() {
     let mut total_passes = 0u32;
     let mut total_mismatches = 0u32;
     let mut total_failures = 0u32;
+    let mut skipped = Vec::new();
 
     for entry in fs::read_dir(test_dir).expect("failed to read test directory") {
         let entry = entry.expect("failed to read entry");
@@ -571,7 +572,9 @@ fn run_spec_tests() {
 
         // Convert wast to json
         let json_path = test_out_dir.join(format!("{}.json", stem));
+        // Proposals wagmi lacks are parsed so their files can be reported as skipped
         let output = Command::new(wast2json)
+            .args(["--enable-simd", "--enable-threads"])
             .arg(&path)
             .arg("-o")
             .arg(&json_path)
@@ -586,7 +589,11 @@ fn run_spec_tests() {
         // Run tests
         println!("Running {}", stem);
         match run_test_file(&json_path, stem) {
-            Ok((passes, message_mismatches, failures)) => {
+            Ok(FileOutcome::Skipped(e)) => {
+                println!("  skipped: {}", e);
+                skipped.push(stem.to_string());
+            }
+            Ok(FileOutcome::Ran { passes, message_mismatches, failures }) => {
                 total_passes += passes;
                 total_mismatches += message_mismatches;
                 total_failures += failures;
@@ -603,9 +610,16 @@ fn run_spec_tests() {
     }
 
     println!(
-        "\nTotal: {} passed, {} had error message mismatch, {} failed",
-        total_passes, total_mismatches, total_failures
+        "\nTotal: {} passed, {} had error message mismatch, {} failed, {} files skipped",
+        total_passes,
+        total_mismatches,
+        total_failures,
+        skipped.len()
     );
+    if !skipped.is_empty() {
+        skipped.sort();
+        println!("Skipped: {}", skipped.join(", "));
+    }
 
     if total_failures > 0 || total_mismatches > 0 {
         panic!(
@@ -0,0 +1,474 @@
+mod common;
+
+use common::{code_with_body, func, instantiate, module_with_sections, wat};
+use wagmi::{
+    CompileOptions, Error, Features, Imports, Module, ValidationRule, WasmValue,
+    DEFAULT_MAX_NESTING_DEPTH,
+};
+
+fn validation_error(src: &str) -> &'static str {
+    match Module::compile(wat(src)) {
+        Err(Error::Validation(msg)) => msg,
+        Err(e) => panic!("expected validation error, got {:?}", e),
+        Ok(_) => panic!("expected validation error, module compiled"),
+    }
+}
+
+// wat2wasm 1.0.13 still spells externref as anyref (both encode as 0x6f)
+#[test]
+fn untyped_select_rejects_reference_operands() {
+    let msg = validation_error(
+        r#"(module (func (param anyref anyref i32) (result anyref)
+            local.get 0 local.get 1 local.get 2 select))"#,
+    );
+    assert_eq!(msg, "type mismatch");
+}
+
+#[test]
+fn typed_select_accepts_reference_operands() {
+    let inst = instantiate(
+        r#"(module (func (export "pick") (param anyref anyref i32) (result anyref)
+            local.get 0 local.get 1 local.get 2 select (result anyref)))"#,
+        &Imports::new(),
+    );
+    let pick = func(&inst, "pick");
+    let args = |c| [WasmValue::from_u64(7), WasmValue::from_u64(9), WasmValue::from_i32(c)];
+    assert_eq!(inst.invoke(&pick, &args(1)).unwrap()[0].as_u64(), 7);
+    assert_eq!(inst.invoke(&pick, &args(0)).unwrap()[0].as_u64(), 9);
+}
+
+#[test]
+fn drop_and_select_after_unreachable_stay_polymorphic() {
+    // From the spec's unreached-valid and unreached-invalid corpora
+    for valid in [
+        "(func (result i32) unreachable select)",
+        "(func (result i64) unreachable (i64.const 0) (i32.const 1) select)",
+        "(func unreachable (i32.const 1) select drop)",
+        "(func unreachable drop drop drop)",
+        "(func (result i32) unreachable (i64.const 0) drop)",
+        "(func (param anyref) (result anyref) unreachable (local.get 0) (i32.const 1) select (result anyref))",
+    ] {
+        let src = format!("(module {})", valid);
+        assert!(Module::compile(wat(&src)).is_ok(), "{}", valid);
+    }
+    for invalid in [
+        // A polymorphic result still counts as a value when the block ends
+        "(func unreachable select)",
+        // Known operand types carry through to the result
+        "(func (result i32) unreachable (i64.const 0) (i32.const 1) select)",
+        "(func (result i32) unreachable (f32.const 0) (i32.const 0) (i32.const 1) select)",
+        "(func unreachable (i32.const 0) (i32.const 1) select (i64.const 0) i64.add drop)",
+        // The condition must still be an i32
+        "(func unreachable (i64.const 0) select drop)",
+        // Untyped select can't pick between references
+        "(func (param anyref) unreachable (local.get 0) (i32.const 1) select drop)",
+        "(func (param anyref) (result i32) unreachable (local.get 0) (i32.const 1) select (result i32))",
+        // Reachable code after the block is checked as usual
+        "(func (block unreachable select drop) drop)",
+        "(func (block unreachable drop drop) (f32.const 0) (i32.const 0) (i32.const 1) select drop)",
+    ] {
+        let src = format!("(module {})", invalid);
+        assert_eq!(validation_error(&src), "type mismatch", "{}", invalid);
+    }
+}
+
+#[test]
+fn typed_select_checks_operands_against_annotation() {
+    let msg = validation_error(
+        r#"(module (func (param anyref i32 i32) (result i32)
+            local.get 0 local.get 1 local.get 2 select (result i32)))"#,
+    );
+    assert_eq!(msg, "type mismatch");
+}
+
+#[test]
+fn forbid_float_rejects_float_types_and_opcodes() {
+    let options = CompileOptions { forbid_float: true, ..Default::default() };
+    let compile = |src| Module::compile_with_options(wat(src), options).map(|_| ());
+    let rejected = Err(Error::Validation("floating point is not allowed"));
+
+    assert_eq!(compile(r#"(module (func (param f64)))"#), rejected);
+    assert_eq!(compile(r#"(module (global f32 (f32.const 0)))"#), rejected);
+    assert_eq!(compile(r#"(module (func (local f32)))"#), rejected);
+    assert_eq!(compile(r#"(module (func (drop (f32.const 1))))"#), rejected);
+    assert_eq!(
+        compile(r#"(module (func (param i64) (result i32) (i32.wrap_i64 (local.get 0))))"#),
+        Ok(())
+    );
+    assert!(Module::compile(wat(r#"(module (func (param f64)))"#)).is_ok());
+}
+
+#[test]
+fn br_to_loop_must_carry_loop_params() {
+    let msg = validation_error(
+        r#"(module (func
+            i32.const 0
+            loop $l (param i32)
+                drop
+                i64.const 0
+                br $l
+            end))"#,
+    );
+    assert_eq!(msg, "type mismatch");
+}
+
+#[test]
+fn else_after_unreachable_then_branch_respects_polymorphic_stack() {
+    let inst = instantiate(
+        r#"(module (func (export "pick") (param i32) (result i32)
+            (if (result i32) (local.get 0)
+                (then unreachable)
+                (else (i32.const 7)))))"#,
+        &Imports::new(),
+    );
+    let pick = func(&inst, "pick");
+    assert_eq!(inst.invoke(&pick, &[WasmValue::from_i32(0)]).unwrap()[0].as_i32(), 7);
+    assert_eq!(
+        inst.invoke(&pick, &[WasmValue::from_i32(1)]).err(),
+        Some(Error::Trap("unreachable"))
+    );
+
+    // Unreachable doesn't excuse extra or mistyped values left before the else
+    for then_body in ["unreachable i32.const 1 i32.const 2", "unreachable i64.const 1"] {
+        let src = format!(
+            r#"(module (func (param i32) (result i32)
+                local.get 0
+                if (result i32) {} else i32.const 7 end))"#,
+            then_body
+        );
+        assert_eq!(validation_error(&src), "type mismatch", "{}", then_body);
+    }
+}
+
+#[test]
+fn function_end_requires_exact_result_arity() {
+    let body = |instrs: &str| format!(r#"(module (func (result i32) {}))"#, instrs);
+    let compiles = |instrs: &str| Module::compile(wat(&body(instrs))).is_ok();
+
+    assert!(compiles("i32.const 1"));
+    assert_eq!(validation_error(&body("i32.const 1 i32.const 2")), "type mismatch");
+    assert_eq!(validation_error(&body("")), "type mismatch");
+    assert_eq!(validation_error(&body("i64.const 1")), "type mismatch");
+
+    // unreachable discards whatever was pushed before it, the stack is polymorphic after
+    assert!(compiles("i32.const 1 i32.const 2 unreachable"));
+    assert!(compiles("i64.const 1 f32.const 2 unreachable i32.const 3"));
+    // but values pushed after it still count against the result arity
+    assert_eq!(validation_error(&body("unreachable i32.const 1 i32.const 2")), "type mismatch");
+}
+
+#[test]
+fn validation_errors_carry_a_stable_rule() {
+    let rule = |src| Module::compile(wat(src)).err().and_then(|e| e.validation_rule());
+
+    assert_eq!(
+        rule(r#"(module (func (result i32) i64.const 0))"#),
+        Some(ValidationRule::TypeMismatch)
+    );
+    assert_eq!(rule(r#"(module (func br 1))"#), Some(ValidationRule::UnknownLabel));
+    assert_eq!(
+        rule(r#"(module (memory 1) (func (drop (i32.load align=8 (i32.const 0)))))"#),
+        Some(ValidationRule::AlignmentTooLarge)
+    );
+    assert_eq!(rule(r#"(module (func))"#), None);
+    assert_eq!(Error::Trap("type mismatch").validation_rule(), None);
+}
+
+#[test]
+fn require_canonical_types_rejects_duplicate_signatures() {
+    let src = r#"(module
+        (type (func (param i32) (result i32)))
+        (type (func (param i64) (result i32)))
+        (type (func (param i32) (result i32))))"#;
+    let canonical = CompileOptions { require_canonical_types: true, ..Default::default() };
+
+    assert!(Module::compile(wat(src)).is_ok());
+    let err = Module::compile_with_options(wat(src), canonical).err();
+    assert_eq!(err, Some(Error::Validation("duplicate function type")));
+    assert_eq!(err.and_then(|e| e.validation_rule()), Some(ValidationRule::DupType));
+
+    let distinct = r#"(module (type (func (param i32) (result i32))) (type (func (param i32))))"#;
+    assert!(Module::compile_with_options(wat(distinct), canonical).is_ok());
+}
+
+#[test]
+fn disabled_features_reject_their_instructions() {
+    let compile = |src, features| Module::compile_with_features(wat(src), features).map(|_| ());
+    let unknown = Err(Error::Malformed("unknown instruction"));
+
+    let typed_select = r#"(module (func (param i32 i32 i32) (result i32)
+        local.get 0 local.get 1 local.get 2 select (result i32)))"#;
+    assert_eq!(compile(typed_select, Features::ALL), Ok(()));
+    assert_eq!(compile(typed_select, Features::ALL.without(Features::REFERENCE_TYPES)), unknown);
+    assert_eq!(compile(typed_select, Features::MVP | Features::REFERENCE_TYPES), Ok(()));
+
+    let block_params = r#"(module (func (result i32)
+        i32.const 1 (block (param i32) (result i32) i32.const 2 i32.add)))"#;
+    assert_eq!(compile(block_params, Features::default()), Ok(()));
+    assert_eq!(compile(block_params, Features::MVP), unknown);
+
+    let extend = r#"(module (func (param i64) (result i64) (i64.extend32_s (local.get 0))))"#;
+    assert_eq!(compile(extend, Features::default()), Ok(()));
+    assert_eq!(compile(extend, Features::ALL.without(Features::SIGN_EXTENSION)), unknown);
+
+    let trunc_sat =
+        r#"(module (func (param f64) (result i64) (i64.trunc_sat_f64_u (local.get 0))))"#;
+    assert_eq!(compile(trunc_sat, Features::default()), Ok(()));
+    assert_eq!(compile(trunc_sat, Features::ALL.without(Features::SAT_FLOAT_TO_INT)), unknown);
+
+    let table_size = r#"(module (table 1 funcref) (func (result i32) (table.size 0)))"#;
+    assert_eq!(compile(table_size, Features::default()), Ok(()));
+    assert_eq!(compile(table_size, Features::ALL.without(Features::REFERENCE_TYPES)), unknown);
+
+    let fill =
+        r#"(module (memory 1) (func (memory.fill (i32.const 0) (i32.const 0) (i32.const 0))))"#;
+    assert_eq!(compile(fill, Features::default()), Ok(()));
+    assert_eq!(compile(fill, Features::ALL.without(Features::BULK_MEMORY)), unknown);
+
+    let mvp = r#"(module (func (param i32 i32 i32) (result i32)
+        local.get 0 local.get 1 local.get 2 select (block (result i32) i32.const 1) i32.add))"#;
+    assert_eq!(compile(mvp, Features::MVP), Ok(()));
+}
+
+#[test]
+fn reference_value_types_need_reference_types() {
+    let compile = |src, features| Module::compile_with_features(wat(src), features).map(|_| ());
+    let invalid = Err(Error::Malformed("invalid value type"));
+
+    let param = r#"(module (func (param anyref)))"#;
+    assert_eq!(compile(param, Features::ALL), Ok(()));
+    assert_eq!(compile(param, Features::MVP), invalid);
+    assert_eq!(compile(param, Features::MVP | Features::REFERENCE_TYPES), Ok(()));
+
+    let block = r#"(module (func (param anyref) (block (result anyref) local.get 0) drop))"#;
+    assert_eq!(compile(block, Features::ALL), Ok(()));
+    assert_eq!(compile(block, Features::MVP | Features::MULTI_VALUE), invalid);
+}
+
+#[test]
+fn control_nesting_depth_is_limited() {
+    // One [] -> [] function whose body is `depth` nested empty blocks
+    let nested_blocks = |depth: usize| {
+        let mut body = vec![0x00]; // no locals
+        body.extend((0..depth).flat_map(|_| [0x02, 0x40]));
+        body.extend(std::iter::repeat_n(0x0b, depth + 1));
+        module_with_sections(&[
+            (0x01, &[0x01, 0x60, 0x00, 0x00]),
+            (0x03, &[0x01, 0x00]),
+            (0x0a, &code_with_body(&body)),
+        ])
+    };
+    let too_deep = Some(Error::Malformed("control nesting too deep"));
+    let limited = CompileOptions { max_nesting_depth: 3, ..Default::default() };
+
+    assert!(Module::compile_with_options(nested_blocks(3), limited).is_ok());
+    assert_eq!(Module::compile_with_options(nested_blocks(4), limited).err(), too_deep);
+
+    let default_limit = DEFAULT_MAX_NESTING_DEPTH as usize;
+    assert!(Module::compile(nested_blocks(default_limit)).is_ok());
+    assert_eq!(Module::compile(nested_blocks(100_000)).err(), too_deep);
+}
+
+#[test]
+fn memarg_memory_index_flag_is_malformed() {
+    // One [] -> [] function with the given body over a one-page memory
+    let with_body = |body: &[u8]| {
+        Module::compile(module_with_sections(&[
+            (0x01, &[0x01, 0x60, 0x00, 0x00]),
+            (0x03, &[0x01, 0x00]),
+            (0x05, &[0x01, 0x00, 0x01]),
+            (0x0a, &code_with_body(body)),
+        ]))
+        .map(|_| ())
+    };
+    let load = |align| with_body(&[0x00, 0x41, 0x00, 0x28, align, 0x00, 0x1a, 0x0b]);
+    let store = |align| with_body(&[0x00, 0x41, 0x00, 0x41, 0x00, 0x36, align, 0x00, 0x0b]);
+    let flagged = Err(Error::Malformed("malformed memop flags"));
+
+    assert_eq!(load(0x02), Ok(()));
+    assert_eq!(store(0x02), Ok(()));
+    assert_eq!(load(0x42), flagged);
+    assert_eq!(store(0x42), flagged);
+    assert_eq!(load(0x40), flagged);
+    assert_eq!(store(0x40), flagged);
+}
+
+#[test]
+fn unimplemented_proposals_are_reported_as_unsupported() {
+    let header = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+    let compile = |sections: &[u8]| Module::compile([&header[..], sections].concat()).err();
+    // One [] -> [] function whose body is `body` followed by end
+    let with_body = |body: &[u8]| {
+        let mut sections = vec![0x01, 0x04, 0x01, 0x60, 0x00, 0x00, 0x03, 0x02, 0x01, 0x00];
+        sections.extend_from_slice(&[0x0a, body.len() as u8 + 4, 0x01, body.len() as u8 + 2]);
+        sections.push(0x00);
+        sections.extend_from_slice(body);
+        sections.push(0x0b);
+        compile(&sections)
+    };
+
+    let v128_param = compile(&[0x01, 0x05, 0x01, 0x60, 0x01, 0x7b, 0x00]).unwrap();
+    assert!(v128_param.is_unsupported_feature());
+    let v128_global = compile(&[0x06, 0x06, 0x01, 0x7b, 0x00, 0x41, 0x00, 0x0b]).unwrap();
+    assert!(v128_global.is_unsupported_feature());
+    assert!(with_body(&[0xfd, 0x0c]).unwrap().is_unsupported_feature());
+    assert!(with_body(&[0xfe, 0x03, 0x00]).unwrap().is_unsupported_feature());
+
+    // Bytes no proposal assigns stay malformed
+    let unknown = with_body(&[0xd7]).unwrap();
+    assert_eq!(unknown, Error::Malformed("unknown instruction"));
+    assert!(!unknown.is_unsupported_feature());
+    let bad_type = compile(&[0x01, 0x05, 0x01, 0x60, 0x01, 0x7a, 0x00]).unwrap();
+    assert_eq!(bad_type, Error::Malformed("invalid value type"));
+}
+
+#[test]
+fn explain_appends_a_hint_to_common_errors() {
+    let err = Module::compile(wat(
+        r#"(module (memory 1) (func (drop (i32.load align=8 (i32.const 0)))))"#,
+    ))
+    .err()
+    .unwrap();
+    assert_eq!(err.to_string(), "alignment must not be larger than natural");
+    assert!(err.hint().unwrap().contains("natural alignment"));
+    let explained = err.explain();
+    assert!(explained.starts_with("alignment must not be larger than natural (hint: "));
+    assert!(explained.contains("natural alignment"));
+
+    assert!(Error::Link("unknown import").explain().contains("hint: provide every import"));
+    let no_hint = Error::Malformed("magic header not detected");
+    assert_eq!(no_hint.hint(), None);
+    assert_eq!(no_hint.explain(), "magic header not detected");
+}
+
+#[test]
+fn bulk_memory_needs_a_memory_and_i32_operands() {
+    let no_memory = r#"(module (func (memory.copy (i32.const 0) (i32.const 0) (i32.const 0))))"#;
+    assert_eq!(validation_error(no_memory), "unknown memory");
+    let wrong_type = r#"(module (memory 1)
+        (func (memory.fill (i32.const 0) (i64.const 0) (i32.const 0))))"#;
+    assert_eq!(validation_error(wrong_type), "type mismatch");
+}
+
+#[test]
+fn data_segment_instructions_need_the_data_count_section() {
+    // memory.init 0 and data.drop 0 with a passive segment but no data count section
+    let module = |code: &[u8]| {
+        let body = [&[0x00][..], code, &[0x0b]].concat();
+        module_with_sections(&[
+            (0x01, &[0x01, 0x60, 0x00, 0x00]), // type () -> ()
+            (0x03, &[0x01, 0x00]),             // one function
+            (0x05, &[0x01, 0x00, 0x01]),       // memory 1
+            (0x0a, &code_with_body(&body)),
+            (0x0b, &[0x01, 0x01, 0x01, 0x61]), // passive "a"
+        ])
+    };
+    let init = [0x41, 0x00, 0x41, 0x00, 0x41, 0x00, 0xfc, 0x08, 0x00, 0x00];
+    let drop = [0xfc, 0x09, 0x00];
+    let required = Some(Error::Malformed("data count section required"));
+    assert_eq!(Module::compile(module(&init)).err(), required);
+    assert_eq!(Module::compile(module(&drop)).err(), required);
+
+    let unknown = validation_error(r#"(module (memory 1) (data "a") (func (data.drop 1)))"#);
+    assert_eq!(unknown, "unknown data segment");
+    let src = r#"(module (memory 1) (data "a") (func (memory.init 0 (i32.const 0) (i32.const 0) (i32.const 1))))"#;
+    assert!(Module::compile(wat(src)).is_ok());
+    assert_eq!(
+        Module::compile_with_features(wat(src), Features::ALL.without(Features::BULK_MEMORY)).err(),
+        Some(Error::Malformed("invalid section id"))
+    );
+}
+
+#[test]
+fn table_init_and_elem_drop_check_the_segment_index() {
+    let unknown = validation_error(r#"(module (table 1 funcref) (func (elem.drop 0)))"#);
+    assert_eq!(unknown, "unknown elem segment");
+    let src = r#"(module (table 1 funcref) (elem func 0)
+        (func (table.init 0 (i32.const 0) (i32.const 0) (i32.const 1)) (elem.drop 0)))"#;
+    assert!(Module::compile(wat(src)).is_ok());
+    let unknown =
+        validation_error(r#"(module (table 1 funcref) (elem func 0) (func (elem.drop 1)))"#);
+    assert_eq!(unknown, "unknown elem segment");
+}
+
+#[test]
+fn table_instructions_are_typed_against_funcref() {
+    assert_eq!(validation_error("(module (func (drop (table.size 0))))"), "unknown table");
+    assert_eq!(
+        validation_error("(module (table 1 funcref) (func (result i64) (table.size 0)))"),
+        "type mismatch"
+    );
+    assert_eq!(
+        validation_error(
+            "(module (table 1 funcref) (func (table.set 0 (i32.const 0) (i32.const 1))))"
+        ),
+        "type mismatch"
+    );
+    assert_eq!(
+        validation_error(
+            "(module (table 1 funcref) (func (param anyref) (drop (table.grow 0 (local.get 0) (i32.const 1)))))"
+        ),
+        "type mismatch"
+    );
+    let valid = r#"(module (table 1 funcref) (func (local funcref)
+        (table.fill 0 (i32.const 0) (table.get 0 (i32.const 0)) (i32.const 1))
+        (table.set 0 (i32.const 0) (local.get 0))))"#;
+    assert!(Module::compile(wat(valid)).is_ok());
+}
+
+#[test]
+fn const_expressions_reject_instructions_with_immediates_cleanly() {
+    // An i32 global initialized by `expr`, next to a () -> i32 function
+    let with_init = |expr: &[u8]| {
+        Module::compile(module_with_sections(&[
+            (0x01, &[0x01, 0x60, 0x00, 0x01, 0x7f]),
+            (0x03, &[0x01, 0x00]),
+            (0x06, &[&[0x01, 0x7f, 0x00][..], expr].concat()),
+            (0x0a, &code_with_body(&[0x00, 0x41, 0x00, 0x0b])),
+        ]))
+        .map(|_| ())
+    };
+    let const_required = Err(Error::Validation("constant expression required"));
+
+    assert_eq!(with_init(&[0x41, 0x2a, 0x0b]), Ok(()));
+    assert_eq!(with_init(&[0x10, 0x00, 0x0b]), const_required);
+    // Had `call`'s immediate been read as `end`, this would be an empty initializer
+    assert_eq!(with_init(&[0x10, 0x0b, 0x0b]), const_required);
+    assert_eq!(with_init(&[0x41, 0x01, 0x10, 0x00, 0x0b]), const_required);
+
+    assert_eq!(
+        validation_error(r#"(module (global i32 (call 0)) (func (result i32) i32.const 0))"#),
+        "constant expression required"
+    );
+    assert_eq!(
+        validation_error(r#"(module (global i64 (i64.ctz (i64.const 8))))"#),
+        "constant expression required"
+    );
+    let inst = instantiate(
+        r#"(module (global (export "g") i64 (i64.mul (i64.sub (i64.const 10) (i64.const 3)) (i64.const 6))))"#,
+        &Imports::new(),
+    );
+    assert_eq!(inst.globals[0].value.get().as_i64(), 42);
+}
+
+#[test]
+fn global_initializers_only_see_earlier_imported_globals() {
+    // Itself, a later global and an earlier defined global are all off limits
+    assert_eq!(validation_error(r#"(module (global $g i32 (global.get $g)))"#), "unknown global");
+    assert_eq!(
+        validation_error(
+            r#"(module (global $a i32 (global.get $b)) (global $b i32 (i32.const 1)))"#
+        ),
+        "unknown global"
+    );
+    assert_eq!(
+        validation_error(
+            r#"(module (global $a i32 (i32.const 1)) (global $b i32 (global.get $a)))"#
+        ),
+        "unknown global"
+    );
+    assert!(Module::compile(wat(r#"(module
+            (import "env" "base" (global $base i32))
+            (global $a i32 (global.get $base)))"#))
+    .is_ok());
+}
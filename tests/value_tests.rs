@@ -0,0 +1,35 @@
+use wagmi::{NanKind, WasmValue};
+
+#[test]
+fn results_serialize_to_little_endian_slots() {
+    let results = [WasmValue::from_i32(-2), WasmValue::from_f64(1.5)];
+
+    let mut slot = [0xaau8; 8];
+    results[0].write_le(&mut slot);
+    assert_eq!(slot, [0xfe, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+
+    let buf = WasmValue::to_le_buffer(&results);
+    assert_eq!(buf.len(), 16);
+    assert_eq!(&buf[..8], &slot);
+    assert_eq!(&buf[8..], &1.5f64.to_le_bytes());
+}
+
+#[test]
+fn nan_kinds_have_distinct_payloads() {
+    let canonical = WasmValue::nan_f32(NanKind::Canonical);
+    let arithmetic = WasmValue::nan_f32(NanKind::Arithmetic);
+    assert_eq!(canonical.as_f32_bits(), 0x7fc0_0000);
+    assert_eq!(arithmetic.as_f32_bits(), 0x7fc0_0001);
+    assert_eq!(WasmValue::nan_f64(NanKind::Canonical).as_f64_bits(), 0x7ff8_0000_0000_0000);
+    assert_eq!(WasmValue::nan_f64(NanKind::Arithmetic).as_f64_bits(), 0x7ff8_0000_0000_0001);
+
+    // Canonical NaNs are arithmetic too, either sign, but signaling NaNs are neither
+    assert!(canonical.is_nan_f32(NanKind::Canonical) && canonical.is_nan_f32(NanKind::Arithmetic));
+    assert!(
+        !arithmetic.is_nan_f32(NanKind::Canonical) && arithmetic.is_nan_f32(NanKind::Arithmetic)
+    );
+    assert!(WasmValue::from_f32_bits(0xffc0_0000).is_nan_f32(NanKind::Canonical));
+    let signaling = WasmValue::from_f64_bits(0x7ff4_0000_0000_0000);
+    assert!(!signaling.is_nan_f64(NanKind::Arithmetic));
+    assert!(!WasmValue::from_f64(1.0).is_nan_f64(NanKind::Arithmetic));
+}